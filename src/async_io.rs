@@ -0,0 +1,375 @@
+use crate::lockfile::LockFile;
+use crate::{vectored, CharDeviceOptions};
+use async_io::Async;
+use blocking::{unblock, Task};
+use futures_io::{AsyncRead, AsyncWrite};
+use io_extras::os::rustix::{AsRawFd, AsRawReadWriteFd, AsReadWriteFd, RawFd};
+use io_lifetimes::{AsFd, BorrowedFd, FromFilelike, IntoFilelike};
+use rustix::fs::FileTypeExt;
+use std::future::Future;
+use std::io::{self, IoSlice, IoSliceMut, Read, Write};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+
+/// An unbuffered character device, for use with any executor.
+///
+/// This implements [`futures_io::AsyncRead`] and [`futures_io::AsyncWrite`],
+/// on top of the [`async_io`] reactor, so it works with smol, async-std, and
+/// any other executor.
+///
+/// When the device's driver supports polling, as terminals, ptys, and most
+/// serial ports do, the device is put in non-blocking mode and registered
+/// with the reactor. Non-blocking mode is a property of the open file
+/// description, so it's also seen through any duplicates of the handle.
+///
+/// Otherwise, this falls back to performing I/O on the [`blocking`] thread
+/// pool, and completes writes in the background until they're flushed.
+/// Reads and writes run in separate tasks, so a write isn't held up by a
+/// read which is waiting for input.
+#[derive(Debug)]
+pub struct AsyncCharDevice(
+    Inner,
+    /// The lock file held while the device is open.
+    Option<Arc<LockFile>>,
+);
+
+/// How an [`AsyncCharDevice`] performs I/O.
+#[derive(Debug)]
+enum Inner {
+    /// The device is in non-blocking mode, and registered with the reactor.
+    Ready(Async<std::fs::File>),
+
+    /// The device is in blocking mode, and used from the blocking thread
+    /// pool.
+    Blocking(Blocking),
+}
+
+/// A device used from the blocking thread pool.
+#[derive(Debug)]
+struct Blocking {
+    file: Arc<std::fs::File>,
+    /// The read in progress, if any.
+    read: Option<Task<io::Result<Vec<u8>>>>,
+    /// The write in progress, if any.
+    write: Option<Task<io::Result<()>>>,
+    /// Data which was read, but didn't fit in the caller's buffer.
+    unread: Vec<u8>,
+}
+
+impl AsyncCharDevice {
+    /// Construct a new `AsyncCharDevice`. Fail if the given handle isn't a
+    /// valid handle for a character device, or it can't be determined.
+    #[inline]
+    pub async fn new<Filelike: IntoFilelike + AsyncRead + AsyncWrite>(
+        filelike: Filelike,
+    ) -> io::Result<Self> {
+        Self::_new(std::fs::File::from_into_filelike(filelike)).await
+    }
+
+    async fn _new(file: std::fs::File) -> io::Result<Self> {
+        let (file, file_type) = unblock(move || {
+            let file_type = file.metadata()?.file_type();
+            io::Result::Ok((file, file_type))
+        })
+        .await?;
+        if !file_type.is_char_device() {
+            return Err(io::Error::other("raw fd is not a char device"));
+        }
+
+        Ok(Self(Inner::new(file), None))
+    }
+
+    /// Construct a new `AsyncCharDevice` from the given filename. Fail if
+    /// the given handle isn't a valid handle for a character device, or it
+    /// can't be determined.
+    #[inline]
+    pub async fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::open_with(path, &CharDeviceOptions::new()).await
+    }
+
+    /// Construct a new `AsyncCharDevice` from the given filename, with the
+    /// given options.
+    ///
+    /// Opening happens on the blocking thread pool, since opening some
+    /// devices waits for them to become ready.
+    pub async fn open_with<P: AsRef<Path>>(
+        path: P,
+        options: &CharDeviceOptions,
+    ) -> io::Result<Self> {
+        let path = path.as_ref().to_owned();
+        let owned_options = options.clone();
+        let (file, lock_file) = unblock(move || {
+            let lock_file = owned_options.acquire_lock_file(&path)?;
+            let file = owned_options.std_options().open(&path)?;
+            io::Result::Ok((file, lock_file))
+        })
+        .await?;
+        let mut device = Self::_new(file).await?;
+        options.apply(&device)?;
+        device.1 = lock_file;
+        Ok(device)
+    }
+
+    /// Construct a new `AsyncCharDevice`.
+    ///
+    /// # Safety
+    ///
+    /// Doesn't check that the handle is valid or a character device.
+    #[inline]
+    pub unsafe fn new_unchecked<Filelike: IntoFilelike>(filelike: Filelike) -> Self {
+        Self(
+            Inner::new(std::fs::File::from_into_filelike(filelike)),
+            None,
+        )
+    }
+
+    /// Construct a new `AsyncCharDevice` which discards writes and reads
+    /// nothing.
+    ///
+    /// This is "/dev/null".
+    #[inline]
+    pub async fn null() -> io::Result<Self> {
+        Self::open("/dev/null").await
+    }
+
+    /// Return the number of bytes which are ready to be read immediately.
+    #[inline]
+    pub fn num_ready_bytes(&self) -> io::Result<u64> {
+        Ok(rustix::io::ioctl_fionread(self)?)
+    }
+
+    /// Return whether this device waits for readiness through the reactor,
+    /// rather than performing I/O on the blocking thread pool.
+    #[inline]
+    pub fn is_readiness_based(&self) -> bool {
+        matches!(self.0, Inner::Ready(_))
+    }
+}
+
+impl Inner {
+    /// Use `file` through the reactor if its driver supports polling, and
+    /// through the blocking thread pool otherwise.
+    fn new(file: std::fs::File) -> Self {
+        let nonblocking = rustix::fs::fcntl_getfl(&file)
+            .is_ok_and(|flags| flags.contains(rustix::fs::OFlags::NONBLOCK));
+
+        // Register a duplicate, because `Async` closes the handle if
+        // registration fails, which it does if the driver doesn't support
+        // polling.
+        if let Ok(fd) = file.try_clone().and_then(Async::new) {
+            return Self::Ready(fd);
+        }
+
+        // `Async` leaves the device in non-blocking mode, so put it back in
+        // the mode it was in. It's shared with any duplicates of the handle,
+        // so it isn't ours to change.
+        let _ = rustix::io::ioctl_fionbio(&file, nonblocking);
+
+        Self::Blocking(Blocking {
+            file: Arc::new(file),
+            read: None,
+            write: None,
+            unread: Vec::new(),
+        })
+    }
+}
+
+impl Blocking {
+    fn poll_read(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        loop {
+            if !self.unread.is_empty() {
+                let n = buf.len().min(self.unread.len());
+                buf[..n].copy_from_slice(&self.unread[..n]);
+                self.unread.drain(..n);
+                return Poll::Ready(Ok(n));
+            }
+            match &mut self.read {
+                None => {
+                    if buf.is_empty() {
+                        return Poll::Ready(Ok(0));
+                    }
+                    let file = Arc::clone(&self.file);
+                    let len = buf.len();
+                    self.read = Some(unblock(move || {
+                        let mut data = vec![0; len];
+                        let n = (&*file).read(&mut data)?;
+                        data.truncate(n);
+                        Ok(data)
+                    }));
+                }
+                Some(task) => {
+                    let result = ready!(Pin::new(task).poll(cx));
+                    self.read = None;
+                    self.unread = result?;
+                    if self.unread.is_empty() {
+                        return Poll::Ready(Ok(0));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Start writing `bufs`, concatenated so that they're written to the
+    /// device together.
+    fn poll_write(
+        &mut self,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        ready!(self.poll_flush(cx))?;
+
+        let file = Arc::clone(&self.file);
+        let bufs = &bufs[..bufs.len().min(vectored::IOV_MAX)];
+        let mut data = Vec::with_capacity(bufs.iter().map(|buf| buf.len()).sum());
+        for buf in bufs {
+            data.extend_from_slice(buf);
+        }
+        let len = data.len();
+        self.write = Some(unblock(move || (&*file).write_all(&data)));
+        Poll::Ready(Ok(len))
+    }
+
+    fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if let Some(task) = &mut self.write {
+            let result = ready!(Pin::new(task).poll(cx));
+            self.write = None;
+            result?;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Perform the non-blocking operation `op` on `fd`, using `wait` to wait
+/// for readiness each time it would block.
+fn poll_io<R>(
+    fd: &Async<std::fs::File>,
+    cx: &mut Context<'_>,
+    wait: fn(&Async<std::fs::File>, &mut Context<'_>) -> Poll<io::Result<()>>,
+    mut op: impl FnMut(&std::fs::File) -> io::Result<R>,
+) -> Poll<io::Result<R>> {
+    loop {
+        match op(fd.get_ref()) {
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+            result => return Poll::Ready(result),
+        }
+        ready!(wait(fd, cx))?;
+    }
+}
+
+impl AsyncRead for AsyncCharDevice {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        match &mut self.0 {
+            Inner::Ready(fd) => poll_io(fd, cx, Async::poll_readable, |file| {
+                Ok(rustix::io::read(file, &mut *buf)?)
+            }),
+            Inner::Blocking(blocking) => blocking.poll_read(cx, buf),
+        }
+    }
+
+    fn poll_read_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &mut [IoSliceMut<'_>],
+    ) -> Poll<io::Result<usize>> {
+        match &mut self.0 {
+            Inner::Ready(fd) => poll_io(fd, cx, Async::poll_readable, |file| {
+                vectored::readv(file, bufs)
+            }),
+            Inner::Blocking(blocking) => {
+                let buf = bufs
+                    .iter_mut()
+                    .find(|buf| !buf.is_empty())
+                    .map_or(&mut [][..], |buf| &mut **buf);
+                blocking.poll_read(cx, buf)
+            }
+        }
+    }
+}
+
+impl AsyncWrite for AsyncCharDevice {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match &mut self.0 {
+            Inner::Ready(fd) => poll_io(fd, cx, Async::poll_writable, |file| {
+                Ok(rustix::io::write(file, buf)?)
+            }),
+            Inner::Blocking(blocking) => blocking.poll_write(cx, &[IoSlice::new(buf)]),
+        }
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        match &mut self.0 {
+            Inner::Ready(fd) => poll_io(fd, cx, Async::poll_writable, |file| {
+                vectored::writev(file, bufs)
+            }),
+            Inner::Blocking(blocking) => blocking.poll_write(cx, bufs),
+        }
+    }
+
+    #[inline]
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match &mut self.0 {
+            Inner::Ready(_) => Poll::Ready(Ok(())),
+            Inner::Blocking(blocking) => blocking.poll_flush(cx),
+        }
+    }
+
+    #[inline]
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
+
+impl AsRawFd for AsyncCharDevice {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.as_fd().as_raw_fd()
+    }
+}
+
+impl AsFd for AsyncCharDevice {
+    #[inline]
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        match &self.0 {
+            Inner::Ready(fd) => fd.get_ref().as_fd(),
+            Inner::Blocking(blocking) => blocking.file.as_fd(),
+        }
+    }
+}
+
+impl AsRawReadWriteFd for AsyncCharDevice {
+    #[inline]
+    fn as_raw_read_fd(&self) -> RawFd {
+        self.as_raw_fd()
+    }
+
+    #[inline]
+    fn as_raw_write_fd(&self) -> RawFd {
+        self.as_raw_fd()
+    }
+}
+
+impl AsReadWriteFd for AsyncCharDevice {
+    #[inline]
+    fn as_read_fd(&self) -> BorrowedFd<'_> {
+        self.as_fd()
+    }
+
+    #[inline]
+    fn as_write_fd(&self) -> BorrowedFd<'_> {
+        self.as_fd()
+    }
+}
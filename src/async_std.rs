@@ -1,9 +1,25 @@
-use async_std::fs::{File, OpenOptions};
+use crate::{vectored, CharDeviceOptions, CloseBehavior, ReadEvent};
+use async_std::fs::File;
 use async_std::io::{self, IoSlice, IoSliceMut, Read, Write};
 use async_std::path::Path;
 use io_lifetimes::{FromFilelike, IntoFilelike};
 use std::pin::Pin;
-use std::task::{Context, Poll};
+use std::task::{ready, Context, Poll};
+#[cfg(not(windows))]
+use {
+    crate::close,
+    crate::ioctl::{self, Ioctl, Plain},
+    crate::lockfile::LockFile,
+    crate::queue::{self, Queue},
+    crate::DeviceLock,
+    async_io::Async,
+    io_extras::os::rustix::{AsRawFd, AsRawReadWriteFd, AsReadWriteFd, IntoRawFd, RawFd},
+    io_lifetimes::{AsFd, BorrowedFd, OwnedFd},
+    rustix::fs::FileTypeExt,
+    std::sync::Arc,
+};
+#[cfg(feature = "tracing")]
+use {crate::trace, io_extras::grip::AsRawGrip, std::time::Instant};
 #[cfg(windows)]
 use {
     ::async_std::os::windows::io::{AsRawHandle, IntoRawHandle, RawHandle},
@@ -14,24 +30,45 @@ use {
     },
     io_lifetimes::{AsFilelike, AsHandle, BorrowedHandle, OwnedHandle},
 };
-#[cfg(not(windows))]
-use {
-    io_extras::os::rustix::{AsRawFd, AsRawReadWriteFd, AsReadWriteFd, IntoRawFd, RawFd},
-    io_lifetimes::{AsFd, BorrowedFd, OwnedFd},
-    rustix::fs::FileTypeExt,
-};
 
 /// An unbuffered character device.
 ///
-/// This is a wrapper around [`async_std::fs::File`] which is intended for use
-/// with character device "files" such as "/dev/tty".
+/// This is intended for use with character device "files" such as
+/// "/dev/tty".
+///
+/// On Posix-ish platforms, when the device's driver supports polling, as
+/// terminals, ptys, and most serial ports do, the device is put in
+/// non-blocking mode and registered with the [`async_io`] reactor which
+/// async-std runs on. Reads and writes then happen directly when the device
+/// is ready, and cancelling one doesn't leave a thread blocked on the
+/// device. Non-blocking mode is a property of the open file description, so
+/// it's also seen through any duplicates of the handle.
 ///
-/// TODO: "Unbuffered" here isn't entirely accurate, given how async-std deals
-/// with the underlying OS APIs being effectively synchronous. Figure out what
-/// to say here.
+/// Otherwise, this falls back to an [`async_std::fs::File`], which performs
+/// each operation on async-std's blocking thread pool, and completes writes
+/// in the background until they're flushed.
 #[derive(Debug, Clone)]
-#[repr(transparent)]
-pub struct AsyncStdCharDevice(async_std::fs::File);
+pub struct AsyncStdCharDevice(
+    Inner,
+    /// The lock file held while the device is open, shared with its clones.
+    #[cfg(not(windows))]
+    Option<Arc<LockFile>>,
+    /// When the read and write in progress were first polled.
+    #[cfg(feature = "tracing")]
+    trace::Timing,
+);
+
+/// How an [`AsyncStdCharDevice`] performs I/O.
+#[derive(Debug, Clone)]
+enum Inner {
+    /// The device is in non-blocking mode, and registered with the reactor.
+    #[cfg(not(windows))]
+    Ready(Arc<Async<std::fs::File>>),
+
+    /// The device is in blocking mode, and used from the blocking thread
+    /// pool.
+    File(File),
+}
 
 impl AsyncStdCharDevice {
     /// Construct a new `CharDevice`. Fail if the given handle isn't a valid
@@ -48,10 +85,7 @@ impl AsyncStdCharDevice {
         {
             let file_type = file.metadata().await?.file_type();
             if !file_type.is_char_device() {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    "raw fd is not a char device",
-                ));
+                return Err(io::Error::other("raw fd is not a char device"));
             }
         }
 
@@ -60,14 +94,50 @@ impl AsyncStdCharDevice {
             let file_type =
                 winx::winapi_util::file::typ(&*file.as_filelike_view::<std::fs::File>())?;
             if !file_type.is_char() {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    "raw handle is not a char device",
-                ));
+                return Err(io::Error::other("raw handle is not a char device"));
             }
         }
 
-        Ok(Self(file))
+        Ok(Self::from_inner(Inner::new(
+            std::fs::File::from_into_filelike(file),
+        )))
+    }
+
+    #[cfg(not(windows))]
+    #[inline]
+    fn from_inner(inner: Inner) -> Self {
+        Self(
+            inner,
+            None,
+            #[cfg(feature = "tracing")]
+            trace::Timing::default(),
+        )
+    }
+
+    #[cfg(windows)]
+    #[inline]
+    fn from_inner(inner: Inner) -> Self {
+        Self(
+            inner,
+            #[cfg(feature = "tracing")]
+            trace::Timing::default(),
+        )
+    }
+
+    /// Hold `lock_file` until this device and its clones are closed.
+    #[cfg(not(windows))]
+    #[inline]
+    fn with_lock_file(mut self, lock_file: Option<Arc<LockFile>>) -> Self {
+        self.1 = lock_file;
+        self
+    }
+
+    /// Return when the read and write in progress were first polled.
+    #[cfg(feature = "tracing")]
+    #[inline]
+    fn timing(&mut self) -> &mut trace::Timing {
+        let Self(.., timing) = self;
+        timing
     }
 
     /// Construct a new `CharDevice` from the given filename. Fail if the given
@@ -75,7 +145,41 @@ impl AsyncStdCharDevice {
     /// determined.
     #[inline]
     pub async fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
-        Self::new(OpenOptions::new().read(true).write(true).open(path).await?).await
+        Self::open_with(path, &CharDeviceOptions::new()).await
+    }
+
+    /// Construct a new `CharDevice` from the given filename, with the given
+    /// options.
+    pub async fn open_with<P: AsRef<Path>>(
+        path: P,
+        options: &CharDeviceOptions,
+    ) -> io::Result<Self> {
+        let path: &std::path::Path = path.as_ref().as_ref();
+        let open = async {
+            #[cfg(not(windows))]
+            let lock_file = {
+                let (options, owned) = (options.clone(), path.to_owned());
+                async_std::task::spawn_blocking(move || options.acquire_lock_file(&owned)).await?
+            };
+            let owned = path.to_owned();
+            let std_options = options.std_options();
+            let file = async_std::task::spawn_blocking(move || std_options.open(owned)).await?;
+            let device = Self::_new(File::from(file)).await?;
+            options.apply(&device)?;
+            #[cfg(not(windows))]
+            let device = device.with_lock_file(lock_file);
+            Ok(device)
+        };
+
+        #[cfg(not(feature = "tracing"))]
+        {
+            open.await
+        }
+
+        #[cfg(feature = "tracing")]
+        {
+            crate::trace::open_async(path, options, open).await
+        }
     }
 
     /// Construct a new `CharDevice`.
@@ -85,7 +189,18 @@ impl AsyncStdCharDevice {
     /// Doesn't check that the handle is valid or a character device.
     #[inline]
     pub unsafe fn new_unchecked<Filelike: IntoFilelike>(filelike: Filelike) -> Self {
-        Self(File::from_into_filelike(filelike))
+        Self::from_inner(Inner::new(std::fs::File::from_into_filelike(filelike)))
+    }
+
+    /// Return whether this device waits for readiness through the reactor,
+    /// rather than performing I/O on the blocking thread pool.
+    #[inline]
+    pub fn is_readiness_based(&self) -> bool {
+        match &self.0 {
+            #[cfg(not(windows))]
+            Inner::Ready(_) => true,
+            Inner::File(_) => false,
+        }
     }
 
     /// Construct a new `CharDevice` which discards writes and reads nothing.
@@ -104,6 +219,14 @@ impl AsyncStdCharDevice {
         }
     }
 
+    /// Read into `buf`, reporting end of file, hangups, and disconnections as
+    /// [`ReadEvent`]s rather than as errors.
+    pub async fn read_event(&mut self, buf: &mut [u8]) -> io::Result<ReadEvent> {
+        let len = buf.len();
+        let result = std::future::poll_fn(|cx| Pin::new(&mut *self).poll_read(cx, buf)).await;
+        ReadEvent::from_read(len, result)
+    }
+
     /// Return the number of bytes which are ready to be read immediately.
     #[inline]
     pub fn num_ready_bytes(&self) -> io::Result<u64> {
@@ -118,6 +241,263 @@ impl AsyncStdCharDevice {
             Ok(0)
         }
     }
+
+    /// Return the number of bytes which have been written but not yet
+    /// transmitted by the device (`TIOCOUTQ`).
+    #[inline]
+    pub fn pending_output_bytes(&self) -> io::Result<u64> {
+        #[cfg(not(windows))]
+        {
+            queue::pending_output_bytes(self)
+        }
+
+        #[cfg(windows)]
+        {
+            // Return the conservatively correct result.
+            Ok(0)
+        }
+    }
+
+    /// Discard data in the given queues of a terminal device (`tcflush`).
+    #[cfg(not(windows))]
+    #[inline]
+    pub fn discard(&self, queue: Queue) -> io::Result<()> {
+        queue::discard(self, queue)
+    }
+
+    /// Flush this device's buffered writes, and wait until all data written
+    /// to the terminal device has been transmitted (`tcdrain`).
+    ///
+    /// The wait happens on async-std's blocking thread pool, so it never blocks a
+    /// runtime worker.
+    pub async fn drain(&mut self) -> io::Result<()> {
+        std::future::poll_fn(|cx| Pin::new(&mut *self).poll_flush(cx)).await?;
+
+        #[cfg(not(windows))]
+        {
+            let fd = self.as_fd().try_clone_to_owned()?;
+            async_std::task::spawn_blocking(move || queue::drain(fd)).await
+        }
+
+        #[cfg(windows)]
+        {
+            let Inner::File(file) = &self.0;
+            file.sync_all().await
+        }
+    }
+
+    /// Acquire an exclusive lock on this device, waiting until any other
+    /// holder releases it.
+    ///
+    /// The wait happens on async-std's blocking thread pool. See
+    /// [`DeviceLock`] for details.
+    #[cfg(not(windows))]
+    pub async fn lock_exclusive(&self) -> io::Result<DeviceLock> {
+        let fd = self.as_fd().try_clone_to_owned()?;
+        async_std::task::spawn_blocking(move || DeviceLock::acquire(fd.as_fd(), true)).await
+    }
+
+    /// Acquire an exclusive lock on this device, failing with
+    /// [`io::ErrorKind::WouldBlock`] if another holder has it.
+    ///
+    /// See [`DeviceLock`] for details.
+    #[cfg(not(windows))]
+    #[inline]
+    pub fn try_lock_exclusive(&self) -> io::Result<DeviceLock> {
+        DeviceLock::acquire(self.as_fd(), false)
+    }
+
+    /// Flush this device's buffered writes and close it, reporting any
+    /// errors from `close`.
+    ///
+    /// Dropping a device also closes it, but ignores errors, which can be
+    /// the only sign that buffered output was lost, for example when a USB
+    /// serial adapter is unplugged.
+    ///
+    /// # Panics
+    ///
+    /// Like converting into an `OwnedFd`, this panics if there are other
+    /// clones of this device.
+    #[inline]
+    pub async fn close(self) -> io::Result<()> {
+        self.close_with(CloseBehavior::Immediate).await
+    }
+
+    /// Flush this device's buffered writes and close it, handling output
+    /// which hasn't been transmitted yet according to `behavior`, and
+    /// reporting any errors.
+    ///
+    /// Waiting for output happens on async-std's blocking thread pool.
+    ///
+    /// # Panics
+    ///
+    /// Like converting into an `OwnedFd`, this panics if there are other
+    /// clones of this device.
+    pub async fn close_with(mut self, behavior: CloseBehavior) -> io::Result<()> {
+        std::future::poll_fn(|cx| Pin::new(&mut self).poll_flush(cx)).await?;
+
+        #[cfg(not(windows))]
+        {
+            // Hold the lock file until the device is closed.
+            let lock_file = self.1.take();
+            let fd = OwnedFd::from(self);
+            let result = async_std::task::spawn_blocking(move || close::close(fd, behavior)).await;
+            drop(lock_file);
+            result
+        }
+
+        #[cfg(windows)]
+        {
+            // `CloseHandle` errors aren't reported by `std`.
+            let _ = behavior;
+            drop(self);
+            Ok(())
+        }
+    }
+
+    /// Perform the `ioctl` described by `request`, which takes no input.
+    ///
+    /// See the [`ioctl`] module for how to declare requests.
+    ///
+    /// [`ioctl`]: mod@crate::ioctl
+    #[cfg(not(windows))]
+    #[inline]
+    pub fn ioctl<Out: Plain>(&self, request: &Ioctl<(), Out>) -> io::Result<Out> {
+        ioctl::call(self, request, ())
+    }
+
+    /// Perform the `ioctl` described by `request`, passing it `input`.
+    ///
+    /// See the [`ioctl`] module for how to declare requests.
+    ///
+    /// [`ioctl`]: mod@crate::ioctl
+    #[cfg(not(windows))]
+    #[inline]
+    pub fn ioctl_with<In: Plain, Out: Plain>(
+        &self,
+        request: &Ioctl<In, Out>,
+        input: In,
+    ) -> io::Result<Out> {
+        ioctl::call(self, request, input)
+    }
+}
+
+impl Inner {
+    /// Use `file` through the reactor if its driver supports polling, and
+    /// through the blocking thread pool otherwise.
+    fn new(file: std::fs::File) -> Self {
+        #[cfg(not(windows))]
+        let nonblocking = rustix::fs::fcntl_getfl(&file)
+            .is_ok_and(|flags| flags.contains(rustix::fs::OFlags::NONBLOCK));
+
+        // Register a duplicate, because `Async` closes the handle if
+        // registration fails, which it does if the driver doesn't support
+        // polling.
+        #[cfg(not(windows))]
+        if let Ok(fd) = file.try_clone().and_then(Async::new) {
+            return Self::Ready(Arc::new(fd));
+        }
+
+        // `Async` leaves the device in non-blocking mode, so put it back in
+        // the mode it was in. It's shared with any duplicates of the handle,
+        // so it isn't ours to change.
+        #[cfg(not(windows))]
+        let _ = rustix::io::ioctl_fionbio(&file, nonblocking);
+
+        Self::File(File::from(file))
+    }
+
+    fn poll_read(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        match self {
+            #[cfg(not(windows))]
+            Self::Ready(fd) => poll_io(fd, cx, Async::poll_readable, |file| {
+                Ok(rustix::io::read(file, &mut *buf)?)
+            }),
+            Self::File(file) => Pin::new(file).poll_read(cx, buf),
+        }
+    }
+
+    fn poll_read_vectored(
+        &mut self,
+        cx: &mut Context<'_>,
+        bufs: &mut [IoSliceMut<'_>],
+    ) -> Poll<io::Result<usize>> {
+        match self {
+            #[cfg(not(windows))]
+            Self::Ready(fd) => poll_io(fd, cx, Async::poll_readable, |file| {
+                vectored::readv(file, bufs)
+            }),
+            Self::File(file) => Pin::new(file).poll_read_vectored(cx, bufs),
+        }
+    }
+
+    fn poll_write(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        match self {
+            #[cfg(not(windows))]
+            Self::Ready(fd) => poll_io(fd, cx, Async::poll_writable, |file| {
+                Ok(rustix::io::write(file, buf)?)
+            }),
+            Self::File(file) => Pin::new(file).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_write_vectored(
+        &mut self,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        match self {
+            #[cfg(not(windows))]
+            Self::Ready(fd) => poll_io(fd, cx, Async::poll_writable, |file| {
+                vectored::writev(file, bufs)
+            }),
+            // `async_std::fs::File` writes only the first buffer, so
+            // concatenate the buffers to keep them in a single write to the
+            // device. Wait for any write in progress first, so that they're
+            // only concatenated once, when they can be written.
+            Self::File(file) => {
+                ready!(Pin::new(&mut *file).poll_flush(cx))?;
+                match vectored::coalesce(bufs) {
+                    Some(data) => Pin::new(file).poll_write(cx, &data),
+                    None => Pin::new(file).poll_write_vectored(cx, bufs),
+                }
+            }
+        }
+    }
+
+    fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self {
+            #[cfg(not(windows))]
+            Self::Ready(_) => Poll::Ready(Ok(())),
+            Self::File(file) => Pin::new(file).poll_flush(cx),
+        }
+    }
+
+    fn poll_close(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self {
+            #[cfg(not(windows))]
+            Self::Ready(_) => Poll::Ready(Ok(())),
+            Self::File(file) => Pin::new(file).poll_close(cx),
+        }
+    }
+}
+
+/// Perform the non-blocking operation `op` on `fd`, using `wait` to wait
+/// for readiness each time it would block.
+#[cfg(not(windows))]
+fn poll_io<R>(
+    fd: &Async<std::fs::File>,
+    cx: &mut Context<'_>,
+    wait: fn(&Async<std::fs::File>, &mut Context<'_>) -> Poll<io::Result<()>>,
+    mut op: impl FnMut(&std::fs::File) -> io::Result<R>,
+) -> Poll<io::Result<R>> {
+    loop {
+        match op(fd.get_ref()) {
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+            result => return Poll::Ready(result),
+        }
+        ready!(wait(fd, cx))?;
+    }
 }
 
 impl Read for AsyncStdCharDevice {
@@ -127,16 +507,39 @@ impl Read for AsyncStdCharDevice {
         cx: &mut Context<'_>,
         buf: &mut [u8],
     ) -> Poll<io::Result<usize>> {
-        Pin::new(&mut self.0).poll_read(cx, buf)
+        #[cfg(feature = "tracing")]
+        self.timing().read.get_or_insert_with(Instant::now);
+        let result = ready!(self.0.poll_read(cx, buf));
+        #[cfg(feature = "tracing")]
+        {
+            let duration = self.timing().read.take().map(|start| start.elapsed());
+            trace::io(self.as_raw_grip(), "read", &result, duration, [&*buf]);
+        }
+        Poll::Ready(result)
     }
 
-    #[inline]
     fn poll_read_vectored(
         mut self: Pin<&mut Self>,
         cx: &mut Context<'_>,
         bufs: &mut [IoSliceMut<'_>],
     ) -> Poll<io::Result<usize>> {
-        Pin::new(&mut self.0).poll_read_vectored(cx, bufs)
+        let len = bufs.len().min(vectored::IOV_MAX);
+        let bufs = &mut bufs[..len];
+        #[cfg(feature = "tracing")]
+        self.timing().read.get_or_insert_with(Instant::now);
+        let result = ready!(self.0.poll_read_vectored(cx, bufs));
+        #[cfg(feature = "tracing")]
+        {
+            let duration = self.timing().read.take().map(|start| start.elapsed());
+            trace::io(
+                self.as_raw_grip(),
+                "read",
+                &result,
+                duration,
+                bufs.iter().map(|buf| &**buf),
+            );
+        }
+        Poll::Ready(result)
     }
 }
 
@@ -147,7 +550,15 @@ impl Write for AsyncStdCharDevice {
         cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<io::Result<usize>> {
-        Pin::new(&mut self.0).poll_write(cx, buf)
+        #[cfg(feature = "tracing")]
+        self.timing().write.get_or_insert_with(Instant::now);
+        let result = ready!(self.0.poll_write(cx, buf));
+        #[cfg(feature = "tracing")]
+        {
+            let duration = self.timing().write.take().map(|start| start.elapsed());
+            trace::io(self.as_raw_grip(), "write", &result, duration, [buf]);
+        }
+        Poll::Ready(result)
     }
 
     fn poll_write_vectored(
@@ -155,17 +566,31 @@ impl Write for AsyncStdCharDevice {
         cx: &mut Context<'_>,
         bufs: &[IoSlice<'_>],
     ) -> Poll<io::Result<usize>> {
-        Pin::new(&mut self.0).poll_write_vectored(cx, bufs)
+        #[cfg(feature = "tracing")]
+        self.timing().write.get_or_insert_with(Instant::now);
+        let result = ready!(self.0.poll_write_vectored(cx, bufs));
+        #[cfg(feature = "tracing")]
+        {
+            let duration = self.timing().write.take().map(|start| start.elapsed());
+            trace::io(
+                self.as_raw_grip(),
+                "write",
+                &result,
+                duration,
+                bufs.iter().map(|buf| &**buf),
+            );
+        }
+        Poll::Ready(result)
     }
 
     #[inline]
     fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
-        Pin::new(&mut self.0).poll_flush(cx)
+        self.0.poll_flush(cx)
     }
 
     #[inline]
     fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
-        Pin::new(&mut self.0).poll_close(cx)
+        self.0.poll_close(cx)
     }
 }
 
@@ -173,7 +598,7 @@ impl Write for AsyncStdCharDevice {
 impl AsRawFd for AsyncStdCharDevice {
     #[inline]
     fn as_raw_fd(&self) -> RawFd {
-        self.0.as_raw_fd()
+        self.as_fd().as_raw_fd()
     }
 }
 
@@ -181,7 +606,8 @@ impl AsRawFd for AsyncStdCharDevice {
 impl AsRawHandle for AsyncStdCharDevice {
     #[inline]
     fn as_raw_handle(&self) -> RawHandle {
-        self.0.as_raw_handle()
+        let Inner::File(file) = &self.0;
+        file.as_raw_handle()
     }
 }
 
@@ -189,7 +615,8 @@ impl AsRawHandle for AsyncStdCharDevice {
 impl AsRawHandleOrSocket for AsyncStdCharDevice {
     #[inline]
     fn as_raw_handle_or_socket(&self) -> RawHandleOrSocket {
-        self.0.as_raw_handle_or_socket()
+        let Inner::File(file) = &self.0;
+        file.as_raw_handle_or_socket()
     }
 }
 
@@ -197,7 +624,10 @@ impl AsRawHandleOrSocket for AsyncStdCharDevice {
 impl AsFd for AsyncStdCharDevice {
     #[inline]
     fn as_fd(&self) -> BorrowedFd<'_> {
-        self.0.as_fd()
+        match &self.0 {
+            Inner::Ready(fd) => fd.get_ref().as_fd(),
+            Inner::File(file) => file.as_fd(),
+        }
     }
 }
 
@@ -205,7 +635,8 @@ impl AsFd for AsyncStdCharDevice {
 impl AsHandle for AsyncStdCharDevice {
     #[inline]
     fn as_handle(&self) -> BorrowedHandle<'_> {
-        self.0.as_handle()
+        let Inner::File(file) = &self.0;
+        file.as_handle()
     }
 }
 
@@ -213,7 +644,8 @@ impl AsHandle for AsyncStdCharDevice {
 impl AsHandleOrSocket for AsyncStdCharDevice {
     #[inline]
     fn as_handle_or_socket(&self) -> BorrowedHandleOrSocket<'_> {
-        self.0.as_handle_or_socket()
+        let Inner::File(file) = &self.0;
+        file.as_handle_or_socket()
     }
 }
 
@@ -221,7 +653,7 @@ impl AsHandleOrSocket for AsyncStdCharDevice {
 impl IntoRawFd for AsyncStdCharDevice {
     #[inline]
     fn into_raw_fd(self) -> RawFd {
-        self.0.into_raw_fd()
+        OwnedFd::from(self).into_raw_fd()
     }
 }
 
@@ -229,7 +661,16 @@ impl IntoRawFd for AsyncStdCharDevice {
 impl From<AsyncStdCharDevice> for OwnedFd {
     #[inline]
     fn from(device: AsyncStdCharDevice) -> OwnedFd {
-        device.0.into()
+        match device.0 {
+            Inner::Ready(fd) => {
+                let fd = Arc::into_inner(fd).expect("device has other clones");
+                let file = fd.into_inner().expect("failed to deregister device");
+                // Restore blocking mode for whoever uses the fd next.
+                let _ = rustix::io::ioctl_fionbio(&file, false);
+                file.into()
+            }
+            Inner::File(file) => file.into(),
+        }
     }
 }
 
@@ -237,7 +678,8 @@ impl From<AsyncStdCharDevice> for OwnedFd {
 impl IntoRawHandle for AsyncStdCharDevice {
     #[inline]
     fn into_raw_handle(self) -> RawHandle {
-        self.0.into_raw_handle()
+        let Inner::File(file) = self.0;
+        file.into_raw_handle()
     }
 }
 
@@ -245,7 +687,8 @@ impl IntoRawHandle for AsyncStdCharDevice {
 impl From<AsyncStdCharDevice> for OwnedHandle {
     #[inline]
     fn from(device: AsyncStdCharDevice) -> OwnedHandle {
-        device.0.into()
+        let Inner::File(file) = device.0;
+        file.into()
     }
 }
 
@@ -253,7 +696,8 @@ impl From<AsyncStdCharDevice> for OwnedHandle {
 impl IntoRawHandleOrSocket for AsyncStdCharDevice {
     #[inline]
     fn into_raw_handle_or_socket(self) -> RawHandleOrSocket {
-        self.0.into_raw_handle_or_socket()
+        let Inner::File(file) = self.0;
+        file.into_raw_handle_or_socket()
     }
 }
 
@@ -261,7 +705,8 @@ impl IntoRawHandleOrSocket for AsyncStdCharDevice {
 impl From<AsyncStdCharDevice> for OwnedHandleOrSocket {
     #[inline]
     fn from(char_device: AsyncStdCharDevice) -> Self {
-        char_device.0.into()
+        let Inner::File(file) = char_device.0;
+        file.into()
     }
 }
 
@@ -1,14 +1,24 @@
+use crate::{vectored, CharDeviceOptions, CloseBehavior, ReadEvent};
 use io_lifetimes::{FromFilelike, IntoFilelike};
+#[cfg(not(feature = "tracing"))]
 use std::fmt::Arguments;
-use std::fs::{File, OpenOptions};
+use std::fs::File;
 use std::io::{self, IoSlice, IoSliceMut, Read, Write};
 use std::path::Path;
+use std::process::Stdio;
 #[cfg(not(windows))]
 use {
+    crate::close,
+    crate::ioctl::{self, Ioctl, Plain},
+    crate::lockfile::LockFile,
+    crate::queue::{self, Queue},
     io_extras::os::rustix::{AsRawFd, AsRawReadWriteFd, AsReadWriteFd, IntoRawFd, RawFd},
     io_lifetimes::{AsFd, BorrowedFd, OwnedFd},
     rustix::fs::FileTypeExt,
+    std::sync::Arc,
 };
+#[cfg(feature = "tracing")]
+use {crate::trace, io_extras::grip::AsRawGrip, std::time::Instant};
 #[cfg(windows)]
 use {
     io_extras::os::windows::{
@@ -25,8 +35,12 @@ use {
 /// This is a wrapper around [`std::fs::File`] which is intended for use with
 /// character device "files" such as "/dev/tty".
 #[derive(Debug)]
-#[repr(transparent)]
-pub struct CharDevice(std::fs::File);
+pub struct CharDevice(
+    std::fs::File,
+    /// The lock file held while the device is open, shared with its clones.
+    #[cfg(not(windows))]
+    Option<Arc<LockFile>>,
+);
 
 impl CharDevice {
     /// Construct a new `CharDevice`. Fail if the given handle isn't a valid
@@ -41,10 +55,7 @@ impl CharDevice {
         {
             let file_type = file.metadata()?.file_type();
             if !file_type.is_char_device() {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    "raw fd is not a char device",
-                ));
+                return Err(io::Error::other("raw fd is not a char device"));
             }
         }
 
@@ -52,14 +63,54 @@ impl CharDevice {
         {
             let file_type = winx::winapi_util::file::typ(&file)?;
             if !file_type.is_char() {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    "raw handle is not a char device",
-                ));
+                return Err(io::Error::other("raw handle is not a char device"));
             }
         }
 
-        Ok(Self(file))
+        Ok(Self::from_file(file))
+    }
+
+    #[cfg(not(windows))]
+    #[inline]
+    fn from_file(file: File) -> Self {
+        Self(file, None)
+    }
+
+    #[cfg(windows)]
+    #[inline]
+    fn from_file(file: File) -> Self {
+        Self(file)
+    }
+
+    /// Hold `lock_file` until this device and its clones are closed.
+    #[cfg(not(windows))]
+    #[inline]
+    pub(crate) fn with_lock_file(mut self, lock_file: Option<Arc<LockFile>>) -> Self {
+        self.1 = lock_file;
+        self
+    }
+
+    /// Convert this device into a `Stdio`, for handing it to a child
+    /// process, along with the lock file it holds, if it was opened with
+    /// [`CharDeviceOptions::lock_file`].
+    ///
+    /// Converting with `Stdio::from` releases the lock file as soon as this
+    /// device and its clones are gone, even though the child is still using
+    /// the device. Keep the returned lock file for as long as the child
+    /// runs instead.
+    ///
+    /// [`CharDeviceOptions::lock_file`]: crate::CharDeviceOptions::lock_file
+    #[cfg(not(windows))]
+    #[inline]
+    pub fn into_stdio(self) -> (Stdio, Option<Arc<LockFile>>) {
+        (self.0.into(), self.1)
+    }
+
+    /// Split this device into its file and its lock file.
+    #[cfg(not(windows))]
+    #[inline]
+    pub(crate) fn into_parts(self) -> (File, Option<Arc<LockFile>>) {
+        (self.0, self.1)
     }
 
     /// Construct a new `CharDevice` from the given filename. Fail if the given
@@ -67,7 +118,7 @@ impl CharDevice {
     /// determined.
     #[inline]
     pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
-        Self::new(OpenOptions::new().read(true).write(true).open(path)?)
+        CharDeviceOptions::new().open(path)
     }
 
     /// Construct a new `CharDevice`.
@@ -77,7 +128,7 @@ impl CharDevice {
     /// Doesn't check that the handle is valid or a character device.
     #[inline]
     pub unsafe fn new_unchecked<Filelike: IntoFilelike>(filelike: Filelike) -> Self {
-        Self(File::from_into_filelike(filelike))
+        Self::from_file(File::from_into_filelike(filelike))
     }
 
     /// Construct a new `CharDevice` which discards writes and reads nothing.
@@ -96,10 +147,82 @@ impl CharDevice {
         }
     }
 
+    /// Construct a new `CharDevice` from a duplicate of the process' standard
+    /// input, if it's a character device, such as a terminal.
+    ///
+    /// Returns `Ok(None)` if standard input is something else, such as a pipe
+    /// or a file.
+    #[inline]
+    pub fn stdin() -> io::Result<Option<Self>> {
+        Self::adopt(io::stdin())
+    }
+
+    /// Construct a new `CharDevice` from a duplicate of the process' standard
+    /// output, if it's a character device, such as a terminal.
+    ///
+    /// Returns `Ok(None)` if standard output is something else, such as a
+    /// pipe or a file.
+    #[inline]
+    pub fn stdout() -> io::Result<Option<Self>> {
+        Self::adopt(io::stdout())
+    }
+
+    /// Construct a new `CharDevice` from a duplicate of the process' standard
+    /// error, if it's a character device, such as a terminal.
+    ///
+    /// Returns `Ok(None)` if standard error is something else, such as a pipe
+    /// or a file.
+    #[inline]
+    pub fn stderr() -> io::Result<Option<Self>> {
+        Self::adopt(io::stderr())
+    }
+
+    /// Open the process' controlling terminal, "/dev/tty".
+    ///
+    /// This works even when the standard streams are redirected. Returns
+    /// `Ok(None)` if the process has no controlling terminal, as is common
+    /// for daemons and in headless environments.
+    #[cfg(not(windows))]
+    pub fn controlling_terminal() -> io::Result<Option<Self>> {
+        match Self::open("/dev/tty") {
+            Ok(tty) => Ok(Some(tty)),
+            Err(e) if e.raw_os_error() == Some(rustix::io::Errno::NXIO.raw_os_error()) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    #[cfg(not(windows))]
+    fn adopt<Fd: AsFd>(fd: Fd) -> io::Result<Option<Self>> {
+        let file = File::from(fd.as_fd().try_clone_to_owned()?);
+        if !file.metadata()?.file_type().is_char_device() {
+            return Ok(None);
+        }
+        Ok(Some(Self::from_file(file)))
+    }
+
+    #[cfg(windows)]
+    fn adopt<Handle: AsHandle>(handle: Handle) -> io::Result<Option<Self>> {
+        let file = File::from(handle.as_handle().try_clone_to_owned()?);
+        if !winx::winapi_util::file::typ(&file)?.is_char() {
+            return Ok(None);
+        }
+        Ok(Some(Self::from_file(file)))
+    }
+
     /// Creates a new independently owned handle to the underlying device.
     #[inline]
     pub fn try_clone(&self) -> io::Result<Self> {
-        self.0.try_clone().map(Self)
+        let clone = Self::from_file(self.0.try_clone()?);
+        #[cfg(not(windows))]
+        let clone = clone.with_lock_file(self.1.clone());
+        Ok(clone)
+    }
+
+    /// Read into `buf`, reporting end of file, hangups, and disconnections as
+    /// [`ReadEvent`]s rather than as errors.
+    #[inline]
+    pub fn read_event(&mut self, buf: &mut [u8]) -> io::Result<ReadEvent> {
+        ReadEvent::from_read(buf.len(), self.0.read(buf))
     }
 
     /// Return the number of bytes which are ready to be read immediately.
@@ -116,35 +239,216 @@ impl CharDevice {
             Ok(0)
         }
     }
+
+    /// Return the number of bytes which have been written but not yet
+    /// transmitted by the device (`TIOCOUTQ`).
+    #[inline]
+    pub fn pending_output_bytes(&self) -> io::Result<u64> {
+        #[cfg(not(windows))]
+        {
+            queue::pending_output_bytes(self)
+        }
+
+        #[cfg(windows)]
+        {
+            // Return the conservatively correct result.
+            Ok(0)
+        }
+    }
+
+    /// Discard data in the given queues of a terminal device (`tcflush`).
+    #[cfg(not(windows))]
+    #[inline]
+    pub fn discard(&self, queue: Queue) -> io::Result<()> {
+        queue::discard(self, queue)
+    }
+
+    /// Wait until all data written to a terminal device has been transmitted
+    /// (`tcdrain`).
+    #[inline]
+    pub fn drain(&self) -> io::Result<()> {
+        #[cfg(not(windows))]
+        {
+            queue::drain(self)
+        }
+
+        #[cfg(windows)]
+        {
+            self.0.sync_all()
+        }
+    }
+
+    /// Close this device, reporting any errors from `close`.
+    ///
+    /// Dropping a device also closes it, but ignores errors, which can be
+    /// the only sign that buffered output was lost, for example when a USB
+    /// serial adapter is unplugged.
+    #[inline]
+    pub fn close(self) -> io::Result<()> {
+        self.close_with(CloseBehavior::Immediate)
+    }
+
+    /// Close this device, handling output which hasn't been transmitted yet
+    /// according to `behavior`, and reporting any errors.
+    pub fn close_with(self, behavior: CloseBehavior) -> io::Result<()> {
+        #[cfg(not(windows))]
+        {
+            // Hold the lock file until the device is closed, so that no one
+            // else takes the device while its output is still draining.
+            let (file, lock_file) = self.into_parts();
+            let result = close::close(file.into(), behavior);
+            drop(lock_file);
+            result
+        }
+
+        #[cfg(windows)]
+        {
+            // `CloseHandle` errors aren't reported by `std`.
+            let _ = behavior;
+            drop(self);
+            Ok(())
+        }
+    }
+
+    /// Perform the `ioctl` described by `request`, which takes no input.
+    ///
+    /// See the [`ioctl`] module for how to declare requests.
+    ///
+    /// [`ioctl`]: mod@crate::ioctl
+    #[cfg(not(windows))]
+    #[inline]
+    pub fn ioctl<Out: Plain>(&self, request: &Ioctl<(), Out>) -> io::Result<Out> {
+        ioctl::call(self, request, ())
+    }
+
+    /// Perform the `ioctl` described by `request`, passing it `input`.
+    ///
+    /// See the [`ioctl`] module for how to declare requests.
+    ///
+    /// [`ioctl`]: mod@crate::ioctl
+    #[cfg(not(windows))]
+    #[inline]
+    pub fn ioctl_with<In: Plain, Out: Plain>(
+        &self,
+        request: &Ioctl<In, Out>,
+        input: In,
+    ) -> io::Result<Out> {
+        ioctl::call(self, request, input)
+    }
+
+    /// Return whether `read_vectored` is implemented with a native vectored
+    /// read.
+    ///
+    /// This is available on stable Rust, unlike `Read::is_read_vectored`.
+    #[inline]
+    pub const fn is_read_vectored(&self) -> bool {
+        cfg!(not(windows))
+    }
+
+    /// Return whether `write_vectored` is implemented with a native vectored
+    /// write.
+    ///
+    /// This is available on stable Rust, unlike `Write::is_write_vectored`.
+    #[inline]
+    pub const fn is_write_vectored(&self) -> bool {
+        cfg!(not(windows))
+    }
+
+    /// Write all of the given buffers, retrying partial writes.
+    ///
+    /// This is available on stable Rust, unlike `Write::write_all_vectored`.
+    /// Buffers beyond `IOV_MAX` are written in subsequent calls. As with
+    /// `Write::write_all_vectored`, the contents of `bufs` are unspecified
+    /// after this returns.
+    #[inline]
+    pub fn write_all_vectored(&mut self, bufs: &mut [IoSlice]) -> io::Result<()> {
+        vectored::write_all_vectored(self, bufs)
+    }
+}
+
+impl CharDevice {
+    #[inline]
+    fn _read_vectored(&mut self, bufs: &mut [IoSliceMut]) -> io::Result<usize> {
+        #[cfg(not(windows))]
+        {
+            vectored::readv(&*self, bufs)
+        }
+
+        #[cfg(windows)]
+        {
+            self.0.read_vectored(bufs)
+        }
+    }
+
+    #[inline]
+    fn _write_vectored(&mut self, bufs: &[IoSlice]) -> io::Result<usize> {
+        #[cfg(not(windows))]
+        {
+            vectored::writev(&*self, bufs)
+        }
+
+        #[cfg(windows)]
+        {
+            self.0.write_vectored(bufs)
+        }
+    }
 }
 
 impl Read for CharDevice {
     #[inline]
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.0.read(buf)
+        #[cfg(not(feature = "tracing"))]
+        {
+            self.0.read(buf)
+        }
+
+        #[cfg(feature = "tracing")]
+        {
+            let start = Instant::now();
+            let result = self.0.read(buf);
+            let duration = Some(start.elapsed());
+            trace::io(self.as_raw_grip(), "read", &result, duration, [&*buf]);
+            result
+        }
     }
 
     #[inline]
     fn read_vectored(&mut self, bufs: &mut [IoSliceMut]) -> io::Result<usize> {
-        self.0.read_vectored(bufs)
+        #[cfg(not(feature = "tracing"))]
+        {
+            self._read_vectored(bufs)
+        }
+
+        #[cfg(feature = "tracing")]
+        {
+            let start = Instant::now();
+            let result = self._read_vectored(bufs);
+            let duration = Some(start.elapsed());
+            let payload = bufs.iter().map(|buf| &**buf);
+            trace::io(self.as_raw_grip(), "read", &result, duration, payload);
+            result
+        }
     }
 
     #[cfg(can_vector)]
     #[inline]
     fn is_read_vectored(&self) -> bool {
-        self.0.is_read_vectored()
+        CharDevice::is_read_vectored(self)
     }
 
+    #[cfg(not(feature = "tracing"))]
     #[inline]
     fn read_to_end(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
         self.0.read_to_end(buf)
     }
 
+    #[cfg(not(feature = "tracing"))]
     #[inline]
     fn read_to_string(&mut self, buf: &mut String) -> io::Result<usize> {
         self.0.read_to_string(buf)
     }
 
+    #[cfg(not(feature = "tracing"))]
     #[inline]
     fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
         self.0.read_exact(buf)
@@ -154,7 +458,19 @@ impl Read for CharDevice {
 impl Write for CharDevice {
     #[inline]
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.0.write(buf)
+        #[cfg(not(feature = "tracing"))]
+        {
+            self.0.write(buf)
+        }
+
+        #[cfg(feature = "tracing")]
+        {
+            let start = Instant::now();
+            let result = self.0.write(buf);
+            let duration = Some(start.elapsed());
+            trace::io(self.as_raw_grip(), "write", &result, duration, [buf]);
+            result
+        }
     }
 
     #[inline]
@@ -164,15 +480,29 @@ impl Write for CharDevice {
 
     #[inline]
     fn write_vectored(&mut self, bufs: &[IoSlice]) -> io::Result<usize> {
-        self.0.write_vectored(bufs)
+        #[cfg(not(feature = "tracing"))]
+        {
+            self._write_vectored(bufs)
+        }
+
+        #[cfg(feature = "tracing")]
+        {
+            let start = Instant::now();
+            let result = self._write_vectored(bufs);
+            let duration = Some(start.elapsed());
+            let payload = bufs.iter().map(|buf| &**buf);
+            trace::io(self.as_raw_grip(), "write", &result, duration, payload);
+            result
+        }
     }
 
     #[cfg(can_vector)]
     #[inline]
     fn is_write_vectored(&self) -> bool {
-        self.0.is_write_vectored()
+        CharDevice::is_write_vectored(self)
     }
 
+    #[cfg(not(feature = "tracing"))]
     #[inline]
     fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
         self.0.write_all(buf)
@@ -181,9 +511,10 @@ impl Write for CharDevice {
     #[cfg(write_all_vectored)]
     #[inline]
     fn write_all_vectored(&mut self, bufs: &mut [IoSlice]) -> io::Result<()> {
-        self.0.write_all_vectored(bufs)
+        vectored::write_all_vectored(self, bufs)
     }
 
+    #[cfg(not(feature = "tracing"))]
     #[inline]
     fn write_fmt(&mut self, fmt: Arguments) -> io::Result<()> {
         self.0.write_fmt(fmt)
@@ -238,6 +569,8 @@ impl AsRawHandleOrSocket for CharDevice {
     }
 }
 
+/// This releases the device's lock file, if this is the last device holding
+/// it. See [`CharDevice::into_stdio`].
 #[cfg(not(windows))]
 impl IntoRawFd for CharDevice {
     #[inline]
@@ -246,6 +579,8 @@ impl IntoRawFd for CharDevice {
     }
 }
 
+/// This releases the device's lock file, if this is the last device holding
+/// it. See [`CharDevice::into_stdio`].
 #[cfg(not(windows))]
 impl From<CharDevice> for OwnedFd {
     #[inline]
@@ -254,6 +589,15 @@ impl From<CharDevice> for OwnedFd {
     }
 }
 
+/// This releases the device's lock file, if this is the last device holding
+/// it. See [`CharDevice::into_stdio`].
+impl From<CharDevice> for Stdio {
+    #[inline]
+    fn from(char_device: CharDevice) -> Self {
+        char_device.0.into()
+    }
+}
+
 #[cfg(windows)]
 impl IntoRawHandle for CharDevice {
     #[inline]
@@ -0,0 +1,83 @@
+//! Explicit closing, with control over pending output.
+
+use std::io;
+use std::time::Duration;
+#[cfg(not(windows))]
+use {
+    crate::queue::{self, Queue},
+    io_lifetimes::{AsFd, OwnedFd},
+    rustix::io::Errno,
+    std::os::unix::io::IntoRawFd,
+    std::sync::mpsc,
+};
+
+/// What to do with output which hasn't been transmitted yet when a device
+/// is closed.
+///
+/// On terminals, the kernel may otherwise block in `close` until pending
+/// output drains, or discard it, depending on the driver. On other devices,
+/// all behaviors just close the device.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub enum CloseBehavior {
+    /// Wait up to the given duration for pending output to be transmitted,
+    /// and then discard whatever remains.
+    ///
+    /// If the driver doesn't stop waiting once the output is discarded, such
+    /// as when a USB serial adapter has hung, closing fails with
+    /// [`io::ErrorKind::TimedOut`] after waiting up to the same duration
+    /// again. The device is then closed once the driver stops waiting.
+    Drain(Duration),
+    /// Discard pending output, so that closing doesn't wait for it.
+    DiscardPending,
+    /// Close without waiting for or discarding pending output, leaving it to
+    /// the driver. This is what dropping a device does.
+    #[default]
+    Immediate,
+}
+
+/// Close `fd`, handling pending output according to `behavior`, and report
+/// any errors, including errors from `close` itself.
+#[cfg(not(windows))]
+pub(crate) fn close(fd: OwnedFd, behavior: CloseBehavior) -> io::Result<()> {
+    let result = match behavior {
+        CloseBehavior::Drain(timeout) => drain_with_timeout(&fd, timeout),
+        CloseBehavior::DiscardPending => queue::discard(&fd, Queue::Output),
+        CloseBehavior::Immediate => Ok(()),
+    };
+    // Devices which aren't terminals have no output queue to manage.
+    let result = match result {
+        Err(e) if e.raw_os_error() == Some(Errno::NOTTY.raw_os_error()) => Ok(()),
+        result => result,
+    };
+
+    // SAFETY: We own `fd`, and it's consumed here.
+    let closed = unsafe { rustix::io::try_close(fd.into_raw_fd()) };
+    result.and(closed.map_err(Into::into))
+}
+
+/// Wait up to `timeout` for `fd`'s output to drain, and then discard what
+/// remains, failing if the wait doesn't end within `timeout` after that.
+#[cfg(not(windows))]
+fn drain_with_timeout(fd: &OwnedFd, timeout: Duration) -> io::Result<()> {
+    let dup = fd.try_clone()?;
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || sender.send(queue::drain(dup)).ok());
+
+    match receiver.recv_timeout(timeout) {
+        Ok(result) => result,
+        Err(_) => {
+            // Discarding the output also wakes up the thread in `tcdrain`,
+            // unless the driver is stuck, in which case the thread is left
+            // to finish, and close its duplicate, whenever it can.
+            queue::discard(fd.as_fd(), Queue::Output)?;
+            match receiver.recv_timeout(timeout) {
+                Ok(result) => result,
+                Err(mpsc::RecvTimeoutError::Timeout) => Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "output didn't drain after being discarded",
+                )),
+                Err(mpsc::RecvTimeoutError::Disconnected) => Ok(()),
+            }
+        }
+    }
+}
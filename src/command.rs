@@ -0,0 +1,87 @@
+//! Spawning child processes with a character device as their stdio.
+//!
+//! These functions take a `std::process::Command`. For a
+//! `tokio::process::Command`, pass `command.as_std_mut()`.
+
+use crate::CharDevice;
+use std::io;
+use std::ops::BitOr;
+use std::process::Command;
+
+/// A set of standard streams.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Streams(u8);
+
+impl Streams {
+    /// No streams.
+    pub const NONE: Self = Self(0);
+    /// Standard input.
+    pub const STDIN: Self = Self(1 << 0);
+    /// Standard output.
+    pub const STDOUT: Self = Self(1 << 1);
+    /// Standard error.
+    pub const STDERR: Self = Self(1 << 2);
+    /// Standard input, output, and error.
+    pub const ALL: Self = Self(Self::STDIN.0 | Self::STDOUT.0 | Self::STDERR.0);
+
+    /// Return whether this set contains all of the streams in `other`.
+    #[inline]
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for Streams {
+    type Output = Self;
+
+    #[inline]
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+/// Configure `command` to use a duplicate of `device` for each of the
+/// given standard streams.
+pub fn attach(command: &mut Command, device: &CharDevice, streams: Streams) -> io::Result<()> {
+    if streams.contains(Streams::STDIN) {
+        command.stdin(device.try_clone()?);
+    }
+    if streams.contains(Streams::STDOUT) {
+        command.stdout(device.try_clone()?);
+    }
+    if streams.contains(Streams::STDERR) {
+        command.stderr(device.try_clone()?);
+    }
+    Ok(())
+}
+
+/// Configure `command` to use a duplicate of `device` for each of the
+/// given standard streams, and to start a new session with `device` as its
+/// controlling terminal, as `getty` and terminal emulators do.
+///
+/// In the child, before it executes, this calls `setsid` and then
+/// `TIOCSCTTY` on the device.
+#[cfg(not(windows))]
+pub fn attach_controlling_terminal(
+    command: &mut Command,
+    device: &CharDevice,
+    streams: Streams,
+) -> io::Result<()> {
+    use io_lifetimes::OwnedFd;
+    use std::os::unix::process::CommandExt;
+
+    attach(command, device, streams)?;
+
+    // This duplicate is close-on-exec, so the child only keeps the streams.
+    let tty = OwnedFd::from(device.try_clone()?);
+
+    // SAFETY: The hook only makes system calls, which are async-signal-safe.
+    unsafe {
+        command.pre_exec(move || {
+            rustix::process::setsid()?;
+            rustix::process::ioctl_tiocsctty(&tty)?;
+            Ok(())
+        });
+    }
+    Ok(())
+}
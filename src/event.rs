@@ -0,0 +1,92 @@
+//! Classifying the outcomes of reads.
+
+use std::io;
+
+/// The outcome of a read from a device, distinguishing the ways a device can
+/// stop producing data from real I/O errors.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ReadEvent {
+    /// The given number of bytes were read.
+    Data(usize),
+    /// The device reported end of file. Terminals report this when the
+    /// end-of-file character is typed in canonical mode, and a pty's slave
+    /// side reports it once the master side is closed.
+    Eof,
+    /// The other side of the device went away, such as when the last
+    /// descriptor for a pty's slave side is closed, which makes reads on the
+    /// master side fail with `EIO`.
+    ///
+    /// Every `EIO` is reported this way, but some drivers also use `EIO`
+    /// for transient hardware errors, after which the device still works. To
+    /// tell these apart, check whether the device is still present, for
+    /// example by opening it again.
+    Hangup,
+    /// The device itself went away, such as when a USB serial adapter is
+    /// unplugged, which makes reads fail with `ENODEV` or `ENXIO`.
+    Disconnected,
+}
+
+impl ReadEvent {
+    /// Classify the result of a read, such as one from [`std::io::Read`].
+    ///
+    /// Errors which indicate a hangup or disconnection are converted into
+    /// events, and other errors are returned as is.
+    ///
+    /// A result of 0 is always taken to be end of file, so this shouldn't be
+    /// used for reads into an empty buffer, which return 0 too.
+    pub fn from_result(result: io::Result<usize>) -> io::Result<Self> {
+        match result {
+            Ok(0) => Ok(Self::Eof),
+            Ok(n) => Ok(Self::Data(n)),
+            Err(e) => classify_error(&e).ok_or(e),
+        }
+    }
+
+    /// Classify the result of a read into a buffer of `len` bytes, for which
+    /// a result of 0 is only end of file if `len` isn't 0.
+    pub(crate) fn from_read(len: usize, result: io::Result<usize>) -> io::Result<Self> {
+        match result {
+            Ok(0) if len == 0 => Ok(Self::Data(0)),
+            result => Self::from_result(result),
+        }
+    }
+}
+
+/// Return the event that `error` indicates, if it indicates a hangup or a
+/// disconnection.
+pub(crate) fn classify_error(error: &io::Error) -> Option<ReadEvent> {
+    match error.raw_os_error() {
+        Some(errno) if is_hangup(errno) => Some(ReadEvent::Hangup),
+        Some(errno) if is_disconnected(errno) => Some(ReadEvent::Disconnected),
+        _ => None,
+    }
+}
+
+#[cfg(not(windows))]
+fn is_hangup(errno: i32) -> bool {
+    use rustix::io::Errno;
+
+    errno == Errno::IO.raw_os_error()
+}
+
+#[cfg(not(windows))]
+fn is_disconnected(errno: i32) -> bool {
+    use rustix::io::Errno;
+
+    errno == Errno::NODEV.raw_os_error() || errno == Errno::NXIO.raw_os_error()
+}
+
+#[cfg(windows)]
+fn is_hangup(errno: i32) -> bool {
+    const ERROR_BROKEN_PIPE: i32 = 109;
+
+    errno == ERROR_BROKEN_PIPE
+}
+
+#[cfg(windows)]
+fn is_disconnected(errno: i32) -> bool {
+    const ERROR_DEV_NOT_EXIST: i32 = 55;
+    const ERROR_DEVICE_NOT_CONNECTED: i32 = 1167;
+
+    errno == ERROR_DEV_NOT_EXIST || errno == ERROR_DEVICE_NOT_CONNECTED
+}
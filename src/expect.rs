@@ -0,0 +1,97 @@
+//! Checking writes against the writes a [`Replayer`] or [`MockDevice`]
+//! expects.
+//!
+//! [`Replayer`]: crate::session::Replayer
+//! [`MockDevice`]: crate::testing::MockDevice
+
+use std::io;
+
+pub(crate) fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// The progress through a sequence of expected steps.
+#[derive(Debug, Default)]
+pub(crate) struct Expectations {
+    /// How much of the current step's data has been read or written.
+    offset: usize,
+    /// The first mismatch, after which every operation fails.
+    error: Option<String>,
+}
+
+impl Expectations {
+    /// Fail if there has been a mismatch.
+    pub(crate) fn check(&self) -> io::Result<()> {
+        match &self.error {
+            Some(error) => Err(invalid_data(error)),
+            None => Ok(()),
+        }
+    }
+
+    /// Record a mismatch, which fails this and every later operation.
+    pub(crate) fn mismatch(&mut self, message: String) -> io::Error {
+        let error = invalid_data(&message);
+        self.error = Some(message);
+        error
+    }
+
+    /// Return the part of the current step's `data` which hasn't been read or
+    /// written yet.
+    #[inline]
+    pub(crate) fn remaining<'a>(&self, data: &'a [u8]) -> &'a [u8] {
+        &data[self.offset..]
+    }
+
+    /// Consume `n` more bytes of the current step's `data`, returning whether
+    /// the step is complete.
+    pub(crate) fn advance(&mut self, n: usize, data: &[u8]) -> bool {
+        self.offset += n;
+        let complete = self.offset == data.len();
+        if complete {
+            self.offset = 0;
+        }
+        complete
+    }
+
+    /// Check a write of `buf`, which should be part of `expected`, the data
+    /// of the current step, or if that isn't a write, fail with `instead`
+    /// describing what is expected. Returns the number of bytes written, and
+    /// whether the step is complete.
+    pub(crate) fn write(
+        &mut self,
+        buf: &[u8],
+        expected: Result<&[u8], &str>,
+    ) -> io::Result<(usize, bool)> {
+        let data = match expected {
+            Ok(data) => data,
+            Err(instead) => {
+                let message = format!("wrote \"{}\", but {}", buf.escape_ascii(), instead);
+                return Err(self.mismatch(message));
+            }
+        };
+        let expected = self.remaining(data);
+        let n = buf.len().min(expected.len());
+        if buf[..n] != expected[..n] {
+            let message = format!(
+                "wrote \"{}\", but expected \"{}\"",
+                buf[..n].escape_ascii(),
+                expected[..n].escape_ascii()
+            );
+            return Err(self.mismatch(message));
+        }
+        Ok((n, self.advance(n, data)))
+    }
+
+    /// Check that there wasn't a mismatch, and that no expected writes
+    /// remain, given that `missing` do.
+    pub(crate) fn finish(&self, missing: usize) -> io::Result<()> {
+        self.check()?;
+        if missing != 0 {
+            return Err(invalid_data(&format!(
+                "{} expected writes not made",
+                missing
+            )));
+        }
+        Ok(())
+    }
+}
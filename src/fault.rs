@@ -0,0 +1,422 @@
+//! Injecting faults into device I/O, for testing error handling.
+//!
+//! Real devices interrupt reads with signals, accept only part of a write,
+//! and hang up, but "/dev/null" never does. A [`FaultyDevice`] wraps a
+//! device and makes some of its operations fail or fall short, according to
+//! a [`FaultPolicy`] which either lists faults for particular operations, or
+//! injects them at random from a seed, so that failures are reproducible.
+//!
+//! ```
+//! use char_device::fault::{Fault, FaultPolicy, FaultyDevice, Op};
+//! use char_device::CharDevice;
+//! use std::io::Write;
+//!
+//! # fn main() -> std::io::Result<()> {
+//! let mut policy = FaultPolicy::new();
+//! policy
+//!     .at(Op::Write, 0, Fault::Interrupted)
+//!     .at(Op::Write, 1, Fault::Short(2));
+//!
+//! let mut device = FaultyDevice::new(CharDevice::null()?, policy);
+//! // `write_all` retries after the interruption, and continues after the
+//! // short write.
+//! device.write_all(b"hello")?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::rng::Rng;
+#[cfg(any(feature = "async-std", feature = "tokio"))]
+use crate::timer::Timer;
+use rustix::io::Errno;
+use std::io::{self, Read, Write};
+use std::time::Duration;
+#[cfg(any(feature = "async-std", feature = "tokio"))]
+use std::{
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::Instant,
+};
+
+/// A kind of operation.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Op {
+    /// A read.
+    Read,
+    /// A write.
+    Write,
+}
+
+/// A fault to inject into an operation.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Fault {
+    /// Fail with `EINTR`, as if interrupted by a signal.
+    Interrupted,
+    /// Fail with `EAGAIN`, as a non-blocking device does when it isn't
+    /// ready.
+    WouldBlock,
+    /// Transfer at most the given number of bytes. `Short(0)` transfers
+    /// nothing and succeeds, which callers take as end of file for reads,
+    /// and as a failure to write anything for writes.
+    Short(usize),
+    /// Fail with `EIO`.
+    Io,
+    /// Hang up, so that this and every later operation fail with `EIO`, as
+    /// they do on a terminal whose other side has gone away.
+    Hangup,
+    /// Wait for the given duration, and then perform the operation normally.
+    Delay(Duration),
+}
+
+/// When to inject faults.
+///
+/// Faults are given for operations by their index, counting reads and
+/// writes separately from zero, or chosen at random, or both, in which case
+/// the given faults take precedence.
+#[derive(Debug, Clone, Default)]
+pub struct FaultPolicy {
+    schedule: Vec<(Op, u64, Fault)>,
+    random: Option<Random>,
+}
+
+impl FaultPolicy {
+    /// Create a policy which doesn't inject any faults.
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Inject `fault` into the read or write with the given index.
+    pub fn at(&mut self, op: Op, index: u64, fault: Fault) -> &mut Self {
+        self.schedule.push((op, index, fault));
+        self
+    }
+
+    /// Inject one of `faults`, chosen at random, into each operation with
+    /// probability `rate`, using a generator seeded with `seed`.
+    pub fn random(&mut self, seed: u64, rate: f64, faults: &[Fault]) -> &mut Self {
+        self.random = Some(Random {
+            rng: Rng::new(seed),
+            rate,
+            faults: faults.to_vec(),
+        });
+        self
+    }
+
+    /// Return the fault to inject into the operation with the given index,
+    /// if any.
+    fn next(&mut self, op: Op, index: u64) -> Option<Fault> {
+        let scheduled = self
+            .schedule
+            .iter()
+            .find(|(o, i, _)| *o == op && *i == index)
+            .map(|(_, _, fault)| *fault);
+        // Draw from the generator even when a fault is scheduled, so that
+        // adding scheduled faults doesn't change which random faults occur.
+        let random = self.random.as_mut().and_then(Random::next);
+        scheduled.or(random)
+    }
+}
+
+/// A seeded generator of random faults.
+#[derive(Debug, Clone)]
+struct Random {
+    rng: Rng,
+    rate: f64,
+    faults: Vec<Fault>,
+}
+
+impl Random {
+    fn next(&mut self) -> Option<Fault> {
+        let roll = self.rng.next_f64();
+        let choice = self.rng.next_u64();
+        if roll >= self.rate || self.faults.is_empty() {
+            return None;
+        }
+        Some(self.faults[(choice % self.faults.len() as u64) as usize])
+    }
+}
+
+fn errno(errno: Errno) -> io::Error {
+    io::Error::from_raw_os_error(errno.raw_os_error())
+}
+
+/// The progress of an operation which may take several polls.
+#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[derive(Debug, Default)]
+struct Pending {
+    /// The fault for the operation, once it has been chosen.
+    fault: Option<Option<Fault>>,
+    /// When a delay ends.
+    deadline: Option<Instant>,
+}
+
+/// A wrapper around a device which injects faults into its reads and
+/// writes.
+///
+/// This works with [`CharDevice`], `TokioCharDevice`, `AsyncStdCharDevice`,
+/// and anything else implementing the same I/O traits. For the async
+/// wrappers, delays are timed by a helper thread, shared by all of the
+/// device's operations, so they work with any executor.
+///
+/// [`CharDevice`]: crate::CharDevice
+#[derive(Debug)]
+pub struct FaultyDevice<D> {
+    device: D,
+    policy: FaultPolicy,
+    reads: u64,
+    writes: u64,
+    hung_up: bool,
+    #[cfg(any(feature = "async-std", feature = "tokio"))]
+    pending_read: Pending,
+    #[cfg(any(feature = "async-std", feature = "tokio"))]
+    pending_write: Pending,
+    #[cfg(any(feature = "async-std", feature = "tokio"))]
+    timer: Timer,
+}
+
+impl<D> FaultyDevice<D> {
+    /// Wrap `device`, injecting faults according to `policy`.
+    pub fn new(device: D, policy: FaultPolicy) -> Self {
+        Self {
+            device,
+            policy,
+            reads: 0,
+            writes: 0,
+            hung_up: false,
+            #[cfg(any(feature = "async-std", feature = "tokio"))]
+            pending_read: Pending::default(),
+            #[cfg(any(feature = "async-std", feature = "tokio"))]
+            pending_write: Pending::default(),
+            #[cfg(any(feature = "async-std", feature = "tokio"))]
+            timer: Timer::default(),
+        }
+    }
+
+    /// Return a reference to the underlying device.
+    #[inline]
+    pub fn get_ref(&self) -> &D {
+        &self.device
+    }
+
+    /// Return a mutable reference to the underlying device. Operations
+    /// through this reference don't have faults injected.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut D {
+        &mut self.device
+    }
+
+    /// Consume the wrapper, returning the underlying device.
+    #[inline]
+    pub fn into_inner(self) -> D {
+        self.device
+    }
+
+    /// Choose the fault for the next operation of kind `op`.
+    fn next_fault(&mut self, op: Op) -> Option<Fault> {
+        let counter = match op {
+            Op::Read => &mut self.reads,
+            Op::Write => &mut self.writes,
+        };
+        let index = *counter;
+        *counter += 1;
+        self.policy.next(op, index)
+    }
+
+    /// Apply `fault`, returning the number of bytes of a `len`-byte
+    /// transfer to perform, or the error to fail with. Delays are left to the
+    /// caller.
+    fn apply(&mut self, fault: Option<Fault>, len: usize) -> io::Result<usize> {
+        if self.hung_up {
+            return Err(errno(Errno::IO));
+        }
+        match fault {
+            None | Some(Fault::Delay(_)) => Ok(len),
+            Some(Fault::Short(n)) => Ok(len.min(n)),
+            Some(Fault::Interrupted) => Err(errno(Errno::INTR)),
+            Some(Fault::WouldBlock) => Err(errno(Errno::AGAIN)),
+            Some(Fault::Io) => Err(errno(Errno::IO)),
+            Some(Fault::Hangup) => {
+                self.hung_up = true;
+                Err(errno(Errno::IO))
+            }
+        }
+    }
+
+    /// Choose and apply the fault for a sync operation.
+    fn begin(&mut self, op: Op, len: usize) -> io::Result<usize> {
+        let fault = self.next_fault(op);
+        if let Some(Fault::Delay(delay)) = fault {
+            std::thread::sleep(delay);
+        }
+        self.apply(fault, len)
+    }
+
+    /// Choose and apply the fault for an async operation, waiting out any
+    /// delay.
+    #[cfg(any(feature = "async-std", feature = "tokio"))]
+    fn poll_begin(&mut self, cx: &mut Context<'_>, op: Op, len: usize) -> Poll<io::Result<usize>> {
+        let fault = match op {
+            Op::Read => self.pending_read.fault,
+            Op::Write => self.pending_write.fault,
+        };
+        let fault = match fault {
+            Some(fault) => fault,
+            None => {
+                let fault = self.next_fault(op);
+                let pending = self.pending(op);
+                pending.fault = Some(fault);
+                if let Some(Fault::Delay(delay)) = fault {
+                    pending.deadline = Some(Instant::now() + delay);
+                }
+                fault
+            }
+        };
+
+        let pending = self.pending(op);
+        if let Some(deadline) = pending.deadline {
+            if Instant::now() < deadline {
+                self.timer.wake_at(op as usize, deadline, cx.waker())?;
+                return Poll::Pending;
+            }
+            pending.deadline = None;
+        }
+
+        // Transfers of nothing, as `Short(0)` makes them, are finished here,
+        // because the device may wait for readiness which never comes.
+        let result = self.apply(fault, len);
+        if !matches!(result, Ok(n) if n != 0) {
+            self.finish(op);
+        }
+        Poll::Ready(result)
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio"))]
+    fn pending(&mut self, op: Op) -> &mut Pending {
+        match op {
+            Op::Read => &mut self.pending_read,
+            Op::Write => &mut self.pending_write,
+        }
+    }
+
+    /// Mark the current async operation of kind `op` as complete.
+    #[cfg(any(feature = "async-std", feature = "tokio"))]
+    fn finish(&mut self, op: Op) {
+        *self.pending(op) = Pending::default();
+    }
+}
+
+impl<D: Read> Read for FaultyDevice<D> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.begin(Op::Read, buf.len())?;
+        self.device.read(&mut buf[..len])
+    }
+}
+
+impl<D: Write> Write for FaultyDevice<D> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.begin(Op::Write, buf.len())?;
+        self.device.write(&buf[..len])
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        self.device.flush()
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<D: tokio::io::AsyncRead + Unpin> tokio::io::AsyncRead for FaultyDevice<D> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let len = ready!(this.poll_begin(cx, Op::Read, buf.remaining()))?;
+        if len == 0 {
+            return Poll::Ready(Ok(()));
+        }
+        let mut limited = tokio::io::ReadBuf::new(&mut buf.initialize_unfilled()[..len]);
+        let result = ready!(Pin::new(&mut this.device).poll_read(cx, &mut limited));
+        this.finish(Op::Read);
+        result?;
+        let n = limited.filled().len();
+        buf.advance(n);
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<D: tokio::io::AsyncWrite + Unpin> tokio::io::AsyncWrite for FaultyDevice<D> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let len = ready!(this.poll_begin(cx, Op::Write, buf.len()))?;
+        if len == 0 {
+            return Poll::Ready(Ok(0));
+        }
+        let result = ready!(Pin::new(&mut this.device).poll_write(cx, &buf[..len]));
+        this.finish(Op::Write);
+        Poll::Ready(result)
+    }
+
+    #[inline]
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().device).poll_flush(cx)
+    }
+
+    #[inline]
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().device).poll_shutdown(cx)
+    }
+}
+
+#[cfg(feature = "async-std")]
+impl<D: async_std::io::Read + Unpin> async_std::io::Read for FaultyDevice<D> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let len = ready!(this.poll_begin(cx, Op::Read, buf.len()))?;
+        if len == 0 {
+            return Poll::Ready(Ok(0));
+        }
+        let result = ready!(Pin::new(&mut this.device).poll_read(cx, &mut buf[..len]));
+        this.finish(Op::Read);
+        Poll::Ready(result)
+    }
+}
+
+#[cfg(feature = "async-std")]
+impl<D: async_std::io::Write + Unpin> async_std::io::Write for FaultyDevice<D> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let len = ready!(this.poll_begin(cx, Op::Write, buf.len()))?;
+        if len == 0 {
+            return Poll::Ready(Ok(0));
+        }
+        let result = ready!(Pin::new(&mut this.device).poll_write(cx, &buf[..len]));
+        this.finish(Op::Write);
+        Poll::Ready(result)
+    }
+
+    #[inline]
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().device).poll_flush(cx)
+    }
+
+    #[inline]
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().device).poll_close(cx)
+    }
+}
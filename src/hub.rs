@@ -0,0 +1,589 @@
+//! Broadcasting one device's input to multiple consumers.
+//!
+//! A [`CharDeviceHub`] reads from a device on a thread of its own, and
+//! copies everything it reads to each of its [`Subscriber`]s. Each
+//! subscriber has a buffer of its own, so a slow subscriber doesn't hold up
+//! the others; when a buffer fills up, the [`LagPolicy`] decides what
+//! happens. Subscribers can also write to the device, and each `write_all`
+//! is written without being interleaved with other subscribers' writes.
+//!
+//! ```no_run
+//! use char_device::hub::{CharDeviceHub, LagPolicy};
+//! use char_device::CharDevice;
+//! use std::io::{BufRead, BufReader};
+//!
+//! # fn main() -> std::io::Result<()> {
+//! let gps = CharDevice::open("/dev/ttyACM0")?;
+//! let hub = CharDeviceHub::new(gps, 64 * 1024, LagPolicy::DropOldest)?;
+//!
+//! let logger = BufReader::new(hub.subscribe());
+//! std::thread::spawn(move || {
+//!     for line in logger.lines() {
+//!         eprintln!("{}", line.unwrap());
+//!     }
+//! });
+//!
+//! for line in BufReader::new(hub.subscribe()).lines() {
+//!     println!("{}", line?);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::CharDevice;
+#[cfg(not(windows))]
+use io_lifetimes::OwnedFd;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::task::Waker;
+#[cfg(feature = "tokio")]
+use {
+    crate::TokioCharDevice,
+    io_lifetimes::AsFilelike,
+    std::future::Future,
+    std::pin::Pin,
+    std::task::{Context, Poll},
+    tokio::io::{AsyncRead, AsyncWrite, ReadBuf},
+};
+
+/// The size of the buffer the hub reads into.
+const CHUNK_SIZE: usize = 4096;
+
+/// What to do when a subscriber's buffer is full.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum LagPolicy {
+    /// Discard the oldest buffered bytes to make room for new ones.
+    DropOldest,
+    /// Discard new bytes until the subscriber has read everything buffered,
+    /// and then fail its next read with a [`Lagged`] error, after which it
+    /// receives new bytes again.
+    Error,
+}
+
+/// The error payload reported to subscribers which fell behind with
+/// [`LagPolicy::Error`].
+///
+/// Use [`io::Error::get_ref`] and `downcast_ref` to get it from an error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lagged {
+    missed: u64,
+}
+
+impl Lagged {
+    /// Return the number of bytes the subscriber missed.
+    #[inline]
+    pub fn missed(&self) -> u64 {
+        self.missed
+    }
+}
+
+impl fmt::Display for Lagged {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "subscriber lagged, missing {} bytes", self.missed)
+    }
+}
+
+impl std::error::Error for Lagged {}
+
+/// How the device's input ended.
+#[derive(Clone)]
+enum Closed {
+    Eof,
+    /// The read failed with this error. The OS error code is kept, so that
+    /// subscribers can classify it, with [`ReadEvent`] for example.
+    ///
+    /// [`ReadEvent`]: crate::ReadEvent
+    Error {
+        kind: io::ErrorKind,
+        raw_os_error: Option<i32>,
+        message: String,
+    },
+}
+
+impl Closed {
+    fn error(error: &io::Error) -> Self {
+        Self::Error {
+            kind: error.kind(),
+            raw_os_error: error.raw_os_error(),
+            message: error.to_string(),
+        }
+    }
+}
+
+/// A subscriber's buffer.
+struct Queue {
+    state: Mutex<QueueState>,
+    ready: Condvar,
+}
+
+struct QueueState {
+    buf: VecDeque<u8>,
+    missed: u64,
+    closed: Option<Closed>,
+    waker: Option<Waker>,
+}
+
+impl Queue {
+    fn new(closed: Option<Closed>) -> Self {
+        Self {
+            state: Mutex::new(QueueState {
+                buf: VecDeque::new(),
+                missed: 0,
+                closed,
+                waker: None,
+            }),
+            ready: Condvar::new(),
+        }
+    }
+
+    fn push(&self, chunk: &[u8], capacity: usize, policy: LagPolicy) {
+        let mut state = self.state.lock().unwrap();
+        match policy {
+            LagPolicy::DropOldest => {
+                let chunk = &chunk[chunk.len().saturating_sub(capacity)..];
+                let excess = (state.buf.len() + chunk.len()).saturating_sub(capacity);
+                state.buf.drain(..excess);
+                state.buf.extend(chunk);
+            }
+            LagPolicy::Error => {
+                if state.missed != 0 || state.buf.len() + chunk.len() > capacity {
+                    state.missed += chunk.len() as u64;
+                } else {
+                    state.buf.extend(chunk);
+                }
+            }
+        }
+        self.notify(state);
+    }
+
+    fn close(&self, closed: Closed) {
+        let mut state = self.state.lock().unwrap();
+        state.closed = Some(closed);
+        self.notify(state);
+    }
+
+    fn notify(&self, mut state: std::sync::MutexGuard<'_, QueueState>) {
+        let waker = state.waker.take();
+        drop(state);
+        self.ready.notify_all();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+    /// Read buffered bytes into `out`, or return `None` if there are none
+    /// yet.
+    fn take(state: &mut QueueState, out: &mut [u8]) -> Option<io::Result<usize>> {
+        if out.is_empty() || !state.buf.is_empty() {
+            let n = out.len().min(state.buf.len());
+            for (dst, src) in out.iter_mut().zip(state.buf.drain(..n)) {
+                *dst = src;
+            }
+            return Some(Ok(n));
+        }
+        if state.missed != 0 {
+            let missed = std::mem::take(&mut state.missed);
+            return Some(Err(io::Error::other(Lagged { missed })));
+        }
+        match &state.closed {
+            Some(Closed::Eof) => Some(Ok(0)),
+            Some(Closed::Error {
+                raw_os_error: Some(errno),
+                ..
+            }) => Some(Err(io::Error::from_raw_os_error(*errno))),
+            Some(Closed::Error { kind, message, .. }) => {
+                Some(Err(io::Error::new(*kind, message.clone())))
+            }
+            None => None,
+        }
+    }
+
+    fn read(&self, out: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(result) = Self::take(&mut state, out) {
+                return result;
+            }
+            state = self.ready.wait(state).unwrap();
+        }
+    }
+}
+
+/// The state shared by a hub and its subscribers. The reader only holds a
+/// weak reference, so this is dropped once the hub and all of its
+/// subscribers are gone, which stops the reader.
+struct Shared<W> {
+    writer: W,
+    subscribers: Mutex<Subscribers>,
+    capacity: usize,
+    policy: LagPolicy,
+    _stop: Stop,
+}
+
+/// Stops the hub's reader when dropped.
+enum Stop {
+    /// The write end of a pipe the reader thread polls along with the
+    /// device. Closing it wakes the thread.
+    #[cfg(not(windows))]
+    Pipe { _writer: OwnedFd },
+    /// The reader thread blocks in `read`, so it only stops after its next
+    /// read.
+    #[cfg(windows)]
+    NextRead,
+    /// The reader task polls the receiving end along with the device.
+    /// Dropping this wakes the task.
+    #[cfg(feature = "tokio")]
+    Task {
+        _sender: tokio::sync::oneshot::Sender<()>,
+    },
+}
+
+struct Subscribers {
+    queues: Vec<Arc<Queue>>,
+    closed: Option<Closed>,
+}
+
+impl<W> Shared<W> {
+    fn new(writer: W, capacity: usize, policy: LagPolicy, stop: Stop) -> Arc<Self> {
+        Arc::new(Self {
+            writer,
+            subscribers: Mutex::new(Subscribers {
+                queues: Vec::new(),
+                closed: None,
+            }),
+            capacity,
+            policy,
+            _stop: stop,
+        })
+    }
+
+    fn subscribe(&self) -> Arc<Queue> {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        let queue = Arc::new(Queue::new(subscribers.closed.clone()));
+        subscribers.queues.push(Arc::clone(&queue));
+        queue
+    }
+
+    fn unsubscribe(&self, queue: &Arc<Queue>) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers
+            .queues
+            .retain(|other| !Arc::ptr_eq(other, queue));
+    }
+
+    fn broadcast(&self, chunk: &[u8]) {
+        let subscribers = self.subscribers.lock().unwrap();
+        for queue in &subscribers.queues {
+            queue.push(chunk, self.capacity, self.policy);
+        }
+    }
+
+    fn close(&self, closed: Closed) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        for queue in &subscribers.queues {
+            queue.close(closed.clone());
+        }
+        subscribers.closed = Some(closed);
+    }
+
+    /// Handle the result of a read, returning whether to keep reading.
+    fn handle_read(shared: &Weak<Self>, result: io::Result<usize>, buf: &[u8]) -> bool {
+        // Stop once the hub and all of its subscribers are gone.
+        let Some(shared) = shared.upgrade() else {
+            return false;
+        };
+        match result {
+            Ok(0) => shared.close(Closed::Eof),
+            Ok(n) => {
+                shared.broadcast(&buf[..n]);
+                return true;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => return true,
+            // A non-blocking device reports this when something else read
+            // the input first, so wait for more.
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return true,
+            Err(e) => shared.close(Closed::error(&e)),
+        }
+        false
+    }
+}
+
+/// Create the pipe which stops a hub's reader thread, returning its read
+/// end and the [`Stop`] holding its write end.
+#[cfg(not(windows))]
+fn stop_pipe() -> io::Result<(OwnedFd, Stop)> {
+    use rustix::io::{fcntl_setfd, FdFlags};
+
+    let (reader, writer) = rustix::pipe::pipe()?;
+    fcntl_setfd(&reader, FdFlags::CLOEXEC)?;
+    fcntl_setfd(&writer, FdFlags::CLOEXEC)?;
+    Ok((reader, Stop::Pipe { _writer: writer }))
+}
+
+/// Wait until `device` is readable, returning `false` if the hub was
+/// dropped first.
+#[cfg(not(windows))]
+fn wait_readable(device: &CharDevice, stop: &OwnedFd) -> bool {
+    use rustix::event::{poll, PollFd, PollFlags};
+
+    let mut fds = [
+        PollFd::new(device, PollFlags::IN),
+        PollFd::new(stop, PollFlags::IN),
+    ];
+    // If polling fails, the read reports what's wrong.
+    while let Err(rustix::io::Errno::INTR) = poll(&mut fds, None) {}
+    fds[1].revents().is_empty()
+}
+
+/// A hub which broadcasts a [`CharDevice`]'s input to [`Subscriber`]s.
+///
+/// The hub reads on a thread of its own, which runs until the device reports
+/// end of file or an error, or until the hub and all of its subscribers have
+/// been dropped. On Windows, the thread only notices that they've been
+/// dropped after its next read. Subscribers only receive input read after
+/// they subscribe.
+pub struct CharDeviceHub {
+    shared: Arc<Shared<Mutex<CharDevice>>>,
+}
+
+impl CharDeviceHub {
+    /// Start broadcasting `device`'s input, buffering up to `capacity` bytes
+    /// for each subscriber and handling full buffers according to `policy`.
+    pub fn new(device: CharDevice, capacity: usize, policy: LagPolicy) -> io::Result<Self> {
+        let mut reader = device.try_clone()?;
+        #[cfg(not(windows))]
+        let (stop_reader, stop) = stop_pipe()?;
+        #[cfg(windows)]
+        let stop = Stop::NextRead;
+        let shared = Shared::new(Mutex::new(device), capacity, policy, stop);
+
+        let thread_shared = Arc::downgrade(&shared);
+        std::thread::Builder::new()
+            .name("char-device hub".to_owned())
+            .spawn(move || {
+                let mut buf = vec![0; CHUNK_SIZE];
+                loop {
+                    #[cfg(not(windows))]
+                    if !wait_readable(&reader, &stop_reader) {
+                        break;
+                    }
+                    if !Shared::handle_read(&thread_shared, reader.read(&mut buf), &buf) {
+                        break;
+                    }
+                }
+            })?;
+
+        Ok(Self { shared })
+    }
+
+    /// Return a new subscriber.
+    pub fn subscribe(&self) -> Subscriber {
+        Subscriber {
+            queue: self.shared.subscribe(),
+            shared: Arc::clone(&self.shared),
+        }
+    }
+
+    /// Write all of `buf` to the device, without interleaving it with other
+    /// writes through the hub.
+    pub fn write_all(&self, buf: &[u8]) -> io::Result<()> {
+        self.shared.writer.lock().unwrap().write_all(buf)
+    }
+}
+
+impl fmt::Debug for CharDeviceHub {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CharDeviceHub")
+            .field("capacity", &self.shared.capacity)
+            .field("policy", &self.shared.policy)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A handle for reading a [`CharDeviceHub`]'s input and writing to its
+/// device.
+pub struct Subscriber {
+    queue: Arc<Queue>,
+    shared: Arc<Shared<Mutex<CharDevice>>>,
+}
+
+impl Read for Subscriber {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.queue.read(buf)
+    }
+}
+
+impl Write for Subscriber {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.shared.writer.lock().unwrap().write(buf)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        self.shared.writer.lock().unwrap().flush()
+    }
+
+    #[inline]
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.shared.writer.lock().unwrap().write_all(buf)
+    }
+}
+
+impl Drop for Subscriber {
+    fn drop(&mut self) {
+        self.shared.unsubscribe(&self.queue);
+    }
+}
+
+impl fmt::Debug for Subscriber {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Subscriber").finish_non_exhaustive()
+    }
+}
+
+/// A hub which broadcasts a [`TokioCharDevice`]'s input to
+/// [`TokioSubscriber`]s.
+///
+/// This behaves like [`CharDeviceHub`], except that it reads in a task
+/// spawned on the current tokio runtime, which stops as soon as the hub and
+/// all of its subscribers have been dropped.
+#[cfg(feature = "tokio")]
+pub struct TokioCharDeviceHub {
+    shared: Arc<Shared<tokio::sync::Mutex<TokioCharDevice>>>,
+}
+
+#[cfg(feature = "tokio")]
+impl TokioCharDeviceHub {
+    /// Start broadcasting `device`'s input, buffering up to `capacity` bytes
+    /// for each subscriber and handling full buffers according to `policy`.
+    ///
+    /// This must be called from within a tokio runtime.
+    pub fn new(device: TokioCharDevice, capacity: usize, policy: LagPolicy) -> io::Result<Self> {
+        // Tokio files only perform one operation at a time, so read through
+        // a separate handle.
+        let reader = device.as_filelike_view::<std::fs::File>().try_clone()?;
+        // SAFETY: `reader` is a duplicate of a character device handle.
+        let mut reader = unsafe { TokioCharDevice::new_unchecked(reader) };
+        let (sender, mut stopped) = tokio::sync::oneshot::channel();
+        let stop = Stop::Task { _sender: sender };
+        let shared = Shared::new(tokio::sync::Mutex::new(device), capacity, policy, stop);
+
+        let task_shared = Arc::downgrade(&shared);
+        tokio::spawn(async move {
+            let mut buf = vec![0; CHUNK_SIZE];
+            loop {
+                let result = std::future::poll_fn(|cx| {
+                    if Pin::new(&mut stopped).poll(cx).is_ready() {
+                        return Poll::Ready(None);
+                    }
+                    let mut buf = ReadBuf::new(&mut buf);
+                    Pin::new(&mut reader)
+                        .poll_read(cx, &mut buf)
+                        .map(|result| Some(result.map(|()| buf.filled().len())))
+                })
+                .await;
+                let Some(result) = result else {
+                    break;
+                };
+                if !Shared::handle_read(&task_shared, result, &buf) {
+                    break;
+                }
+            }
+        });
+
+        Ok(Self { shared })
+    }
+
+    /// Return a new subscriber.
+    pub fn subscribe(&self) -> TokioSubscriber {
+        TokioSubscriber {
+            queue: self.shared.subscribe(),
+            shared: Arc::clone(&self.shared),
+        }
+    }
+
+    /// Write all of `buf` to the device, without interleaving it with other
+    /// writes through the hub.
+    pub async fn write_all(&self, buf: &[u8]) -> io::Result<()> {
+        write_all(&self.shared, buf).await
+    }
+}
+
+#[cfg(feature = "tokio")]
+async fn write_all(
+    shared: &Shared<tokio::sync::Mutex<TokioCharDevice>>,
+    mut buf: &[u8],
+) -> io::Result<()> {
+    let mut device = shared.writer.lock().await;
+    while !buf.is_empty() {
+        let n = std::future::poll_fn(|cx| Pin::new(&mut *device).poll_write(cx, buf)).await?;
+        if n == 0 {
+            return Err(io::ErrorKind::WriteZero.into());
+        }
+        buf = &buf[n..];
+    }
+    std::future::poll_fn(|cx| Pin::new(&mut *device).poll_flush(cx)).await
+}
+
+#[cfg(feature = "tokio")]
+impl fmt::Debug for TokioCharDeviceHub {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokioCharDeviceHub")
+            .field("capacity", &self.shared.capacity)
+            .field("policy", &self.shared.policy)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A handle for reading a [`TokioCharDeviceHub`]'s input and writing to its
+/// device.
+#[cfg(feature = "tokio")]
+pub struct TokioSubscriber {
+    queue: Arc<Queue>,
+    shared: Arc<Shared<tokio::sync::Mutex<TokioCharDevice>>>,
+}
+
+#[cfg(feature = "tokio")]
+impl TokioSubscriber {
+    /// Write all of `buf` to the device, without interleaving it with other
+    /// writes through the hub.
+    pub async fn write_all(&self, buf: &[u8]) -> io::Result<()> {
+        write_all(&self.shared, buf).await
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncRead for TokioSubscriber {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let mut state = self.queue.state.lock().unwrap();
+        match Queue::take(&mut state, buf.initialize_unfilled()) {
+            Some(result) => Poll::Ready(result.map(|n| buf.advance(n))),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Drop for TokioSubscriber {
+    fn drop(&mut self) {
+        self.shared.unsubscribe(&self.queue);
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl fmt::Debug for TokioSubscriber {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokioSubscriber").finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,376 @@
+//! Typed `ioctl` requests.
+//!
+//! An [`Ioctl<In, Out>`] describes an `ioctl` request which takes an `In` and
+//! produces an `Out`. Requests are usually declared with the [`ioctl!`] macro,
+//! which computes the request code using the same encoding as the C `_IO`,
+//! `_IOR`, `_IOW`, and `_IOWR` macros, and then called with
+//! [`CharDevice::ioctl`] or [`CharDevice::ioctl_with`], without any `unsafe`
+//! at the call site. Declaring a request is `unsafe`, since nothing checks
+//! that the driver agrees with the declared types.
+//!
+//! ```no_run
+//! use char_device::ioctl::TIOCGWINSZ;
+//! use char_device::CharDevice;
+//!
+//! char_device::ioctl! {
+//!     /// Get the evdev driver version.
+//!     // SAFETY: `EVIOCGVERSION` reads an `int`.
+//!     pub const EVIOCGVERSION: Ioctl<(), i32> = unsafe { read(b'E', 0x01) };
+//! }
+//!
+//! # fn main() -> std::io::Result<()> {
+//! let tty = CharDevice::open("/dev/tty")?;
+//! let winsize = tty.ioctl(&TIOCGWINSZ)?;
+//! println!("{}x{}", winsize.ws_col, winsize.ws_row);
+//!
+//! let input = CharDevice::open("/dev/input/event0")?;
+//! println!("evdev version {:#x}", input.ioctl(&EVIOCGVERSION)?);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`ioctl!`]: crate::ioctl!
+//! [`CharDevice::ioctl`]: crate::CharDevice::ioctl
+//! [`CharDevice::ioctl_with`]: crate::CharDevice::ioctl_with
+
+use std::ffi::c_void;
+use std::fmt;
+use std::io;
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+use {io_lifetimes::AsFd, rustix::ioctl::IoctlOutput};
+
+pub use rustix::ioctl::Opcode;
+pub use rustix::termios::Winsize;
+
+/// Types which can be passed to and from the kernel as raw bytes.
+///
+/// # Safety
+///
+/// Implementors must be `#[repr(C)]` (or primitive) types without padding,
+/// pointers, or references, for which every bit pattern is a valid value.
+pub unsafe trait Plain: Copy {}
+
+unsafe impl Plain for () {}
+unsafe impl Plain for u8 {}
+unsafe impl Plain for i8 {}
+unsafe impl Plain for u16 {}
+unsafe impl Plain for i16 {}
+unsafe impl Plain for u32 {}
+unsafe impl Plain for i32 {}
+unsafe impl Plain for u64 {}
+unsafe impl Plain for i64 {}
+unsafe impl Plain for usize {}
+unsafe impl Plain for isize {}
+unsafe impl<T: Plain, const N: usize> Plain for [T; N] {}
+unsafe impl Plain for Winsize {}
+
+/// How an `ioctl` request's argument is passed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Convention {
+    /// There's no argument (`_IO`).
+    None,
+    /// The argument is a pointer to a buffer holding the input and receiving
+    /// the output (`_IOR`, `_IOW`, and `_IOWR`).
+    Pointer,
+    /// The argument is an `int` passed by value.
+    Value,
+}
+
+/// An `ioctl` request which takes an `In` and produces an `Out`.
+pub struct Ioctl<In, Out> {
+    opcode: Opcode,
+    convention: Convention,
+    _phantom: PhantomData<fn(In) -> Out>,
+}
+
+impl Ioctl<(), ()> {
+    /// A request with no argument, corresponding to the C `_IO` macro.
+    ///
+    /// # Safety
+    ///
+    /// The driver must not use the argument of this request.
+    #[inline]
+    pub const unsafe fn none(group: u8, number: u8) -> Self {
+        Self::with_convention(rustix::ioctl::opcode::none(group, number), Convention::None)
+    }
+}
+
+impl<Out: Plain> Ioctl<(), Out> {
+    /// A request which reads an `Out` from the kernel, corresponding to the C
+    /// `_IOR` macro.
+    ///
+    /// # Safety
+    ///
+    /// The driver must write at most an `Out` through the argument for this
+    /// request, and must not retain it.
+    #[inline]
+    pub const unsafe fn read(group: u8, number: u8) -> Self {
+        Self::with_convention(
+            rustix::ioctl::opcode::read::<Out>(group, number),
+            Convention::Pointer,
+        )
+    }
+}
+
+impl<In: Plain> Ioctl<In, ()> {
+    /// A request which writes an `In` to the kernel, corresponding to the C
+    /// `_IOW` macro.
+    ///
+    /// # Safety
+    ///
+    /// The driver must read at most an `In` through the argument for this
+    /// request, and must not write through it or retain it.
+    #[inline]
+    pub const unsafe fn write(group: u8, number: u8) -> Self {
+        Self::with_convention(
+            rustix::ioctl::opcode::write::<In>(group, number),
+            Convention::Pointer,
+        )
+    }
+}
+
+impl<T: Plain> Ioctl<T, T> {
+    /// A request which writes a `T` to the kernel and reads the updated `T`
+    /// back, corresponding to the C `_IOWR` macro.
+    ///
+    /// # Safety
+    ///
+    /// The driver must read and write at most a `T` through the argument for
+    /// this request, and must not retain it.
+    #[inline]
+    pub const unsafe fn read_write(group: u8, number: u8) -> Self {
+        Self::with_convention(
+            rustix::ioctl::opcode::read_write::<T>(group, number),
+            Convention::Pointer,
+        )
+    }
+}
+
+impl<In, Out> Ioctl<In, Out> {
+    /// A request with a raw opcode, for legacy requests whose opcodes don't
+    /// follow the `_IOC` encoding. The argument is a pointer to a buffer
+    /// holding an `In` and receiving an `Out`, or no argument at all if both
+    /// are `()`.
+    ///
+    /// # Safety
+    ///
+    /// The driver must read at most `size_of::<In>()` bytes and write at most
+    /// `max(size_of::<In>(), size_of::<Out>())` bytes through the pointer, and
+    /// must not retain it.
+    #[inline]
+    pub const unsafe fn from_opcode(opcode: Opcode) -> Self {
+        let convention = if size_of::<In>() == 0 && size_of::<Out>() == 0 {
+            Convention::None
+        } else {
+            Convention::Pointer
+        };
+        Self::with_convention(opcode, convention)
+    }
+
+    /// Return the raw opcode of this request.
+    #[inline]
+    pub const fn opcode(&self) -> Opcode {
+        self.opcode
+    }
+
+    const fn with_convention(opcode: Opcode, convention: Convention) -> Self {
+        Self {
+            opcode,
+            convention,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl Ioctl<i32, ()> {
+    /// A request with a raw opcode which takes an `int` argument by value
+    /// rather than through a pointer.
+    ///
+    /// # Safety
+    ///
+    /// The driver must treat the argument as an integer, not a pointer.
+    #[inline]
+    pub const unsafe fn from_opcode_by_value(opcode: Opcode) -> Self {
+        Self::with_convention(opcode, Convention::Value)
+    }
+}
+
+impl<In, Out> Clone for Ioctl<In, Out> {
+    #[inline]
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<In, Out> Copy for Ioctl<In, Out> {}
+
+impl<In, Out> fmt::Debug for Ioctl<In, Out> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Ioctl")
+            .field("opcode", &format_args!("{:#x}", self.opcode))
+            .field("in", &std::any::type_name::<In>())
+            .field("out", &std::any::type_name::<Out>())
+            .finish()
+    }
+}
+
+/// Declare typed `ioctl` requests.
+///
+/// Each declaration names one of the [`Ioctl`] constructors `none`, `read`,
+/// `write`, or `read_write`, corresponding to the C `_IO`, `_IOR`, `_IOW`, and
+/// `_IOWR` macros, with the argument type taken from the declared type. The
+/// constructor is called in an `unsafe` block, since the declared types must
+/// match what the driver reads and writes; see the constructors' safety
+/// requirements.
+///
+/// ```
+/// #[derive(Copy, Clone)]
+/// #[repr(C)]
+/// pub struct Info {
+///     pub version: u32,
+///     pub flags: u32,
+/// }
+///
+/// // SAFETY: `Info` is `repr(C)` with no padding, and any bits are valid.
+/// unsafe impl char_device::ioctl::Plain for Info {}
+///
+/// // SAFETY: These match the driver's `_IO*` declarations.
+/// char_device::ioctl! {
+///     /// Reset the device.
+///     pub const MYDEV_RESET: Ioctl<(), ()> = unsafe { none(b'M', 0) };
+///     /// Get the device info.
+///     pub const MYDEV_GET_INFO: Ioctl<(), Info> = unsafe { read(b'M', 1) };
+///     /// Set the device flags.
+///     pub const MYDEV_SET_FLAGS: Ioctl<u32, ()> = unsafe { write(b'M', 2) };
+///     /// Exchange the device info.
+///     pub const MYDEV_SWAP_INFO: Ioctl<Info, Info> = unsafe { read_write(b'M', 3) };
+/// }
+/// ```
+#[macro_export]
+macro_rules! ioctl {
+    ($(
+        $(#[$attr:meta])*
+        $vis:vis const $name:ident: Ioctl<$in:ty, $out:ty> = unsafe { $kind:ident($group:expr, $number:expr) };
+    )*) => {$(
+        $(#[$attr])*
+        $vis const $name: $crate::ioctl::Ioctl<$in, $out> =
+            unsafe { $crate::ioctl::Ioctl::<$in, $out>::$kind($group, $number) };
+    )*};
+}
+
+// Requests common to terminals. Linux uses legacy opcodes for these, which
+// vary by architecture, so take them from `linux-raw-sys`. BSD-family
+// platforms use the `_IOC` encoding.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+use linux_raw_sys::ioctl as codes;
+
+/// `TIOCGWINSZ`—Get the terminal window size.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub const TIOCGWINSZ: Ioctl<(), Winsize> = unsafe { Ioctl::from_opcode(codes::TIOCGWINSZ as _) };
+/// `TIOCGWINSZ`—Get the terminal window size.
+#[cfg(not(any(target_os = "android", target_os = "linux")))]
+pub const TIOCGWINSZ: Ioctl<(), Winsize> = unsafe { Ioctl::read(b't', 104) };
+
+/// `TIOCSWINSZ`—Set the terminal window size.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub const TIOCSWINSZ: Ioctl<Winsize, ()> = unsafe { Ioctl::from_opcode(codes::TIOCSWINSZ as _) };
+/// `TIOCSWINSZ`—Set the terminal window size.
+#[cfg(not(any(target_os = "android", target_os = "linux")))]
+pub const TIOCSWINSZ: Ioctl<Winsize, ()> = unsafe { Ioctl::write(b't', 103) };
+
+/// `TIOCEXCL`—Put the terminal into exclusive mode.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub const TIOCEXCL: Ioctl<(), ()> = unsafe { Ioctl::from_opcode(codes::TIOCEXCL as _) };
+/// `TIOCEXCL`—Put the terminal into exclusive mode.
+#[cfg(not(any(target_os = "android", target_os = "linux")))]
+pub const TIOCEXCL: Ioctl<(), ()> = unsafe { Ioctl::none(b't', 13) };
+
+/// `TIOCNXCL`—Take the terminal out of exclusive mode.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub const TIOCNXCL: Ioctl<(), ()> = unsafe { Ioctl::from_opcode(codes::TIOCNXCL as _) };
+/// `TIOCNXCL`—Take the terminal out of exclusive mode.
+#[cfg(not(any(target_os = "android", target_os = "linux")))]
+pub const TIOCNXCL: Ioctl<(), ()> = unsafe { Ioctl::none(b't', 14) };
+
+/// `TIOCOUTQ`—Get the number of bytes in the output queue.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub const TIOCOUTQ: Ioctl<(), i32> = unsafe { Ioctl::from_opcode(codes::TIOCOUTQ as _) };
+/// `TIOCOUTQ`—Get the number of bytes in the output queue.
+#[cfg(not(any(target_os = "android", target_os = "linux")))]
+pub const TIOCOUTQ: Ioctl<(), i32> = unsafe { Ioctl::read(b't', 115) };
+
+/// Perform the `ioctl` described by `request` on `fd`.
+pub(crate) fn call<Fd: AsFd, In: Plain, Out: Plain>(
+    fd: Fd,
+    request: &Ioctl<In, Out>,
+    input: In,
+) -> io::Result<Out> {
+    let call = Call {
+        request: *request,
+        buf: Buf::new(input),
+    };
+    // SAFETY: The `Ioctl` constructors guarantee that the driver reads and
+    // writes within `buf`, and `Plain` guarantees that whatever the driver
+    // writes is a valid `Out`.
+    Ok(unsafe { rustix::ioctl::ioctl(fd, call)? })
+}
+
+/// A buffer which holds an `In` on the way in and an `Out` on the way out.
+#[repr(C)]
+union Buf<In: Copy, Out: Copy> {
+    input: In,
+    output: Out,
+}
+
+impl<In: Plain, Out: Plain> Buf<In, Out> {
+    fn new(input: In) -> MaybeUninit<Self> {
+        // Zero the buffer first, so that every byte is initialized even if
+        // the driver writes less than a whole `Out`.
+        let mut buf = MaybeUninit::<Self>::zeroed();
+        // SAFETY: `buf` is valid for writes of an `In`, since it's `repr(C)`.
+        unsafe { buf.as_mut_ptr().cast::<In>().write(input) };
+        buf
+    }
+}
+
+struct Call<In: Copy, Out: Copy> {
+    request: Ioctl<In, Out>,
+    buf: MaybeUninit<Buf<In, Out>>,
+}
+
+unsafe impl<In: Plain, Out: Plain> rustix::ioctl::Ioctl for Call<In, Out> {
+    type Output = Out;
+
+    // Conservatively assume that any request may mutate the device.
+    const IS_MUTATING: bool = true;
+
+    fn opcode(&self) -> Opcode {
+        self.request.opcode
+    }
+
+    fn as_ptr(&mut self) -> *mut c_void {
+        match self.request.convention {
+            Convention::None => std::ptr::null_mut(),
+            Convention::Pointer => self.buf.as_mut_ptr().cast(),
+            Convention::Value => {
+                // SAFETY: `Value` requests are only constructed with an `In`
+                // of `i32`, which `buf` holds.
+                let value = unsafe { self.buf.as_ptr().cast::<i32>().read() };
+                value as usize as *mut c_void
+            }
+        }
+    }
+
+    unsafe fn output_from_ptr(_out: IoctlOutput, ptr: *mut c_void) -> rustix::io::Result<Out> {
+        // `ptr` is the `buf` pointer we returned from `as_ptr` for `Pointer`
+        // requests. For other requests, `Out` is `()`.
+        if size_of::<Out>() == 0 {
+            // SAFETY: Any zero-sized `Plain` type is valid for any pointer.
+            return Ok(unsafe { MaybeUninit::<Out>::zeroed().assume_init() });
+        }
+        // SAFETY: `buf` was fully initialized by `Buf::new` and the driver,
+        // and `Out` is `Plain`.
+        Ok(unsafe { ptr.cast::<Buf<In, Out>>().read().output })
+    }
+}
@@ -4,14 +4,65 @@
 #![cfg_attr(can_vector, feature(can_vector))]
 #![cfg_attr(write_all_vectored, feature(write_all_vectored))]
 
+#[cfg(all(not(windows), feature = "use_async_io"))]
+mod async_io;
 #[cfg(feature = "async-std")]
 mod async_std;
 mod char_device;
+mod close;
+pub mod command;
+mod event;
+mod expect;
+#[cfg(not(windows))]
+pub mod fault;
+pub mod hub;
+#[cfg(not(windows))]
+pub mod ioctl;
+pub mod link;
+#[cfg(not(windows))]
+mod lock;
+#[cfg(not(windows))]
+pub mod lockfile;
+#[cfg(all(not(windows), feature = "mio"))]
+mod mio;
+#[cfg(all(any(target_os = "android", target_os = "linux"), feature = "libc"))]
+pub mod notify;
+mod options;
+#[cfg(not(windows))]
+mod queue;
+#[cfg(not(windows))]
+pub mod reconnect;
+pub mod record;
+mod rng;
+pub mod session;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub mod splice;
+#[cfg(feature = "metrics")]
+pub mod stats;
+pub mod testing;
+#[cfg(any(feature = "async-std", feature = "tokio"))]
+mod timer;
 #[cfg(feature = "tokio")]
 mod tokio;
+#[cfg(feature = "tracing")]
+pub mod trace;
+#[cfg(all(any(target_os = "android", target_os = "linux"), feature = "io-uring"))]
+pub mod uring;
+mod vectored;
 
+#[cfg(all(not(windows), feature = "use_async_io"))]
+pub use crate::async_io::AsyncCharDevice;
 #[cfg(feature = "async-std")]
 pub use crate::async_std::AsyncStdCharDevice;
 pub use crate::char_device::CharDevice;
+pub use crate::close::CloseBehavior;
+pub use crate::event::ReadEvent;
+#[cfg(not(windows))]
+pub use crate::lock::DeviceLock;
+#[cfg(all(not(windows), feature = "mio"))]
+pub use crate::mio::MioCharDevice;
+pub use crate::options::CharDeviceOptions;
+#[cfg(not(windows))]
+pub use crate::queue::Queue;
 #[cfg(feature = "tokio")]
 pub use crate::tokio::TokioCharDevice;
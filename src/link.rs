@@ -0,0 +1,607 @@
+//! Simulated serial links, for testing timing-sensitive protocols.
+//!
+//! A [`SimulatedLink`] describes a serial line: its baud rate and frame
+//! format, which together limit how many bytes it carries per second, its
+//! latency, and how often it flips bits. It can join two in-process
+//! [`LinkEnd`]s, or, on Unix-family platforms, the slave sides of two ptys,
+//! so that code which opens devices can be pointed at them.
+//!
+//! ```
+//! use char_device::link::{Parity, SimulatedLink};
+//! use std::io::{Read, Write};
+//! use std::time::Duration;
+//!
+//! # fn main() -> std::io::Result<()> {
+//! let (mut host, mut device) = SimulatedLink::new()
+//!     .baud(9600)
+//!     .frame(8, Parity::None, 1)
+//!     .latency(Duration::from_millis(5))
+//!     .pair();
+//!
+//! // At 9600 8N1, these 96 bytes take a tenth of a second to arrive.
+//! host.write_all(&[b'x'; 96])?;
+//! let mut buf = [0; 96];
+//! device.read_exact(&mut buf)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::rng::Rng;
+#[cfg(any(feature = "async-std", feature = "tokio"))]
+use crate::timer::Timer;
+#[cfg(not(windows))]
+use crate::CharDevice;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::task::Waker;
+use std::time::{Duration, Instant};
+#[cfg(any(feature = "async-std", feature = "tokio"))]
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// The parity bit in a serial frame.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Parity {
+    /// No parity bit.
+    None,
+    /// A parity bit making the number of set bits even.
+    Even,
+    /// A parity bit making the number of set bits odd.
+    Odd,
+}
+
+/// The default number of bytes each direction of a link buffers.
+const DEFAULT_BUFFER_SIZE: usize = 4096;
+
+/// A description of a simulated serial line.
+///
+/// By default, the line carries bytes as fast as they're written, with no
+/// latency and no errors, in frames of 8 data bits, no parity bit, and 1
+/// stop bit, and each direction buffers up to 4096 bytes.
+#[derive(Debug, Clone)]
+pub struct SimulatedLink {
+    baud: Option<u32>,
+    data_bits: u8,
+    parity: Parity,
+    stop_bits: u8,
+    latency: Duration,
+    bit_error_rate: f64,
+    seed: u64,
+    buffer_size: usize,
+}
+
+impl SimulatedLink {
+    /// Create a description of an unthrottled, error-free line.
+    pub fn new() -> Self {
+        Self {
+            baud: None,
+            data_bits: 8,
+            parity: Parity::None,
+            stop_bits: 1,
+            latency: Duration::ZERO,
+            bit_error_rate: 0.0,
+            seed: 0,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+        }
+    }
+
+    /// Limit the line to `baud` bits per second, counting the start, parity,
+    /// and stop bits of each frame.
+    pub fn baud(&mut self, baud: u32) -> &mut Self {
+        self.baud = Some(baud);
+        self
+    }
+
+    /// Set the frame format. With fewer than 8 data bits, the high bits of
+    /// each byte are lost.
+    pub fn frame(&mut self, data_bits: u8, parity: Parity, stop_bits: u8) -> &mut Self {
+        assert!(
+            (5..=8).contains(&data_bits),
+            "unsupported number of data bits"
+        );
+        assert!(
+            (1..=2).contains(&stop_bits),
+            "unsupported number of stop bits"
+        );
+        self.data_bits = data_bits;
+        self.parity = parity;
+        self.stop_bits = stop_bits;
+        self
+    }
+
+    /// Delay each byte by `latency`, on top of the time it takes to send.
+    pub fn latency(&mut self, latency: Duration) -> &mut Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Flip each data bit with probability `rate`, using a generator seeded
+    /// with `seed`. Parity isn't checked, so corrupted bytes are delivered
+    /// as they are.
+    pub fn bit_error_rate(&mut self, rate: f64, seed: u64) -> &mut Self {
+        self.bit_error_rate = rate;
+        self.seed = seed;
+        self
+    }
+
+    /// Limit each direction of the line to `size` bytes which have been
+    /// written but not yet read. Once that many are, writes wait for the
+    /// other end to read, as they do when a serial port's transmit buffer is
+    /// full.
+    pub fn buffer_size(&mut self, size: usize) -> &mut Self {
+        assert!(size != 0, "buffer size must not be zero");
+        self.buffer_size = size;
+        self
+    }
+
+    /// Return the time it takes to send one frame, or zero if the line isn't
+    /// throttled.
+    pub fn byte_time(&self) -> Duration {
+        match self.baud {
+            Some(baud) => {
+                let parity_bits = u64::from(self.parity != Parity::None);
+                let bits = 1 + u64::from(self.data_bits) + parity_bits + u64::from(self.stop_bits);
+                Duration::from_nanos(bits * 1_000_000_000 / u64::from(baud))
+            }
+            None => Duration::ZERO,
+        }
+    }
+
+    /// Create the two ends of a link.
+    pub fn pair(&self) -> (LinkEnd, LinkEnd) {
+        #[cfg(any(feature = "async-std", feature = "tokio"))]
+        let timer = Arc::new(Timer::default());
+        let a_to_b = Arc::new(Channel::new(
+            self,
+            self.seed,
+            #[cfg(any(feature = "async-std", feature = "tokio"))]
+            Arc::clone(&timer),
+        ));
+        let b_to_a = Arc::new(Channel::new(
+            self,
+            !self.seed,
+            #[cfg(any(feature = "async-std", feature = "tokio"))]
+            timer,
+        ));
+        (
+            LinkEnd {
+                rx: Arc::clone(&b_to_a),
+                tx: Arc::clone(&a_to_b),
+            },
+            LinkEnd {
+                rx: a_to_b,
+                tx: b_to_a,
+            },
+        )
+    }
+
+    /// Create two ptys joined by a link, returning their slave sides, which
+    /// are put in raw mode so that bytes pass through unchanged.
+    ///
+    /// Threads copy between the pty master sides and the link, and exit once
+    /// both slave sides are closed.
+    #[cfg(not(windows))]
+    pub fn pty(&self) -> io::Result<(CharDevice, CharDevice)> {
+        let (a_master, a) = open_pty()?;
+        let (b_master, b) = open_pty()?;
+        let (a_end, b_end) = self.pair();
+        let (a_end, b_end) = (Arc::new(a_end), Arc::new(b_end));
+
+        pump_in(a_master.try_clone()?, Arc::clone(&a_end));
+        pump_out(Arc::clone(&b_end), b_master.try_clone()?);
+        pump_in(b_master, b_end);
+        pump_out(a_end, a_master);
+        Ok((a, b))
+    }
+}
+
+impl Default for SimulatedLink {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Open a pty, returning its master side and its slave side in raw mode.
+#[cfg(not(windows))]
+fn open_pty() -> io::Result<(std::fs::File, CharDevice)> {
+    use rustix::pty::{grantpt, openpt, ptsname, unlockpt, OpenptFlags};
+    use rustix::termios::{tcgetattr, tcsetattr, OptionalActions};
+
+    let master = openpt(OpenptFlags::RDWR | OpenptFlags::NOCTTY)?;
+    grantpt(&master)?;
+    unlockpt(&master)?;
+    let name = ptsname(&master, Vec::new())?;
+    let slave = CharDevice::open(name.to_str().map_err(io::Error::other)?)?;
+
+    let mut termios = tcgetattr(&slave)?;
+    termios.make_raw();
+    tcsetattr(&slave, OptionalActions::Now, &termios)?;
+    Ok((master.into(), slave))
+}
+
+/// Copy what's written to a pty slave into the link, on a thread of its own,
+/// and close the link for writing when the slave is closed.
+#[cfg(not(windows))]
+fn pump_in(mut master: std::fs::File, end: Arc<LinkEnd>) {
+    std::thread::spawn(move || {
+        let _ = io::copy(&mut master, &mut &*end);
+        end.tx.close();
+    });
+}
+
+/// Copy what arrives over the link to a pty slave, on a thread of its own.
+#[cfg(not(windows))]
+fn pump_out(end: Arc<LinkEnd>, mut master: std::fs::File) {
+    std::thread::spawn(move || {
+        let _ = io::copy(&mut &*end, &mut master);
+    });
+}
+
+/// One direction of a link.
+#[derive(Debug)]
+struct Channel {
+    state: Mutex<State>,
+    arrived: Condvar,
+    /// Notified when bytes are read, making room for more.
+    space: Condvar,
+    byte_time: Duration,
+    latency: Duration,
+    bit_error_rate: f64,
+    data_mask: u8,
+    buffer_size: usize,
+    #[cfg(any(feature = "async-std", feature = "tokio"))]
+    timer: Arc<Timer>,
+}
+
+#[derive(Debug)]
+struct State {
+    /// Bytes in flight, with the times they arrive.
+    queue: VecDeque<(Instant, u8)>,
+    /// When the line is free to start sending another byte.
+    line_free: Instant,
+    rng: Rng,
+    /// Whether the sending end is closed.
+    closed: bool,
+    /// Whether the receiving end is gone.
+    receiver_gone: bool,
+    /// The task waiting to receive, if any.
+    waker: Option<Waker>,
+    /// The task waiting for room to send, if any.
+    #[cfg(any(feature = "async-std", feature = "tokio"))]
+    send_waker: Option<Waker>,
+}
+
+impl Channel {
+    fn new(
+        link: &SimulatedLink,
+        seed: u64,
+        #[cfg(any(feature = "async-std", feature = "tokio"))] timer: Arc<Timer>,
+    ) -> Self {
+        Self {
+            state: Mutex::new(State {
+                queue: VecDeque::new(),
+                line_free: Instant::now(),
+                rng: Rng::new(seed),
+                closed: false,
+                receiver_gone: false,
+                waker: None,
+                #[cfg(any(feature = "async-std", feature = "tokio"))]
+                send_waker: None,
+            }),
+            arrived: Condvar::new(),
+            space: Condvar::new(),
+            byte_time: link.byte_time(),
+            latency: link.latency,
+            bit_error_rate: link.bit_error_rate,
+            data_mask: (0xff_u16 >> (8 - link.data_bits)) as u8,
+            buffer_size: link.buffer_size,
+            #[cfg(any(feature = "async-std", feature = "tokio"))]
+            timer,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    /// Queue as much of `buf` as there's room for, or return `None` if
+    /// there's no room.
+    fn try_send(&self, state: &mut State, buf: &[u8]) -> Option<io::Result<usize>> {
+        if state.receiver_gone {
+            return Some(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        if buf.is_empty() {
+            return Some(Ok(0));
+        }
+        let room = self.buffer_size.saturating_sub(state.queue.len());
+        if room == 0 {
+            return None;
+        }
+        let buf = &buf[..buf.len().min(room)];
+        let now = Instant::now();
+        for &byte in buf {
+            let mut byte = byte & self.data_mask;
+            if self.bit_error_rate > 0.0 {
+                for bit in 0..8 {
+                    if self.data_mask & (1 << bit) != 0
+                        && state.rng.next_f64() < self.bit_error_rate
+                    {
+                        byte ^= 1 << bit;
+                    }
+                }
+            }
+            let start = state.line_free.max(now);
+            state.line_free = start + self.byte_time;
+            let arrival = state.line_free + self.latency;
+            state.queue.push_back((arrival, byte));
+        }
+        self.notify(state);
+        Some(Ok(buf.len()))
+    }
+
+    fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.lock();
+        loop {
+            if let Some(result) = self.try_send(&mut state, buf) {
+                return result;
+            }
+            state = self.space.wait(state).unwrap();
+        }
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio"))]
+    fn poll_send(&self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let mut state = self.lock();
+        match self.try_send(&mut state, buf) {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.send_waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+
+    fn close(&self) {
+        let mut state = self.lock();
+        state.closed = true;
+        self.notify(&mut state);
+    }
+
+    /// Note that the receiving end is gone, failing any waiting sends.
+    fn close_receiver(&self) {
+        let mut state = self.lock();
+        state.receiver_gone = true;
+        self.notify_space(&mut state);
+    }
+
+    fn notify(&self, state: &mut State) {
+        self.arrived.notify_all();
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn notify_space(&self, state: &mut State) {
+        self.space.notify_all();
+        #[cfg(any(feature = "async-std", feature = "tokio"))]
+        if let Some(waker) = state.send_waker.take() {
+            waker.wake();
+        }
+        #[cfg(not(any(feature = "async-std", feature = "tokio")))]
+        let _ = state;
+    }
+
+    /// Return the number of bytes which have arrived.
+    fn arrived(&self) -> usize {
+        let now = Instant::now();
+        let state = self.lock();
+        state
+            .queue
+            .iter()
+            .take_while(|(arrival, _)| *arrival <= now)
+            .count()
+    }
+
+    /// Receive the bytes which have arrived, if any. Otherwise, return the
+    /// time the next byte arrives, if one is in flight.
+    fn try_recv(&self, state: &mut State, buf: &mut [u8]) -> Result<usize, Option<Instant>> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let now = Instant::now();
+        let mut n = 0;
+        while n < buf.len() {
+            match state.queue.front() {
+                Some(&(arrival, byte)) if arrival <= now => {
+                    buf[n] = byte;
+                    n += 1;
+                    state.queue.pop_front();
+                }
+                _ => break,
+            }
+        }
+        if n > 0 {
+            self.notify_space(state);
+        }
+        match state.queue.front() {
+            _ if n > 0 => Ok(n),
+            Some(&(arrival, _)) => Err(Some(arrival)),
+            None if state.closed => Ok(0),
+            None => Err(None),
+        }
+    }
+
+    fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.lock();
+        loop {
+            state = match self.try_recv(&mut state, buf) {
+                Ok(n) => return Ok(n),
+                Err(Some(arrival)) => {
+                    let timeout = arrival.saturating_duration_since(Instant::now());
+                    self.arrived.wait_timeout(state, timeout).unwrap().0
+                }
+                Err(None) => self.arrived.wait(state).unwrap(),
+            };
+        }
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio"))]
+    fn poll_recv(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let mut state = self.lock();
+        match self.try_recv(&mut state, buf) {
+            Ok(n) => Poll::Ready(Ok(n)),
+            Err(arrival) => {
+                state.waker = Some(cx.waker().clone());
+                if let Some(arrival) = arrival {
+                    // Both directions share the timer, so each uses its own
+                    // address as its key. This is called on every poll, so
+                    // that the timer wakes whichever task polled last.
+                    self.timer
+                        .wake_at(self as *const Self as usize, arrival, cx.waker())?;
+                }
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// One end of a [`SimulatedLink`].
+///
+/// Written bytes queue up and arrive at the other end at the rate the line
+/// allows. Writes only wait when the link's buffer is full, until the other
+/// end reads. Reads wait until at least one byte has arrived, and return 0
+/// once the other end is dropped and everything it sent has arrived.
+#[derive(Debug)]
+pub struct LinkEnd {
+    rx: Arc<Channel>,
+    tx: Arc<Channel>,
+}
+
+impl LinkEnd {
+    /// Return the number of bytes which have arrived, and are ready to be
+    /// read immediately.
+    #[inline]
+    pub fn num_ready_bytes(&self) -> io::Result<u64> {
+        Ok(self.rx.arrived() as u64)
+    }
+}
+
+impl Drop for LinkEnd {
+    fn drop(&mut self) {
+        self.tx.close();
+        self.rx.close_receiver();
+    }
+}
+
+impl Read for LinkEnd {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.rx.recv(buf)
+    }
+}
+
+impl Read for &LinkEnd {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.rx.recv(buf)
+    }
+}
+
+impl Write for LinkEnd {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.tx.send(buf)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Write for &LinkEnd {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.tx.send(buf)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl tokio::io::AsyncRead for LinkEnd {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let n = std::task::ready!(self.rx.poll_recv(cx, buf.initialize_unfilled()))?;
+        buf.advance(n);
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl tokio::io::AsyncWrite for LinkEnd {
+    #[inline]
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.tx.poll_send(cx, buf)
+    }
+
+    #[inline]
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    #[inline]
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.tx.close();
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "async-std")]
+impl async_std::io::Read for LinkEnd {
+    #[inline]
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        self.rx.poll_recv(cx, buf)
+    }
+}
+
+#[cfg(feature = "async-std")]
+impl async_std::io::Write for LinkEnd {
+    #[inline]
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.tx.poll_send(cx, buf)
+    }
+
+    #[inline]
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    #[inline]
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.tx.close();
+        Poll::Ready(Ok(()))
+    }
+}
@@ -0,0 +1,83 @@
+//! Exclusive device locking.
+
+use crate::CharDevice;
+use io_lifetimes::{AsFd, BorrowedFd, OwnedFd};
+use rustix::fs::{flock, FlockOperation};
+use std::io;
+
+/// An exclusive lock on a character device, released when dropped.
+///
+/// This holds an advisory `flock` lock, which other processes honor when
+/// they lock the device too. For terminals, it also sets `TIOCEXCL`, which
+/// makes subsequent `open`s of the device fail with `EBUSY` for everyone
+/// except root.
+///
+/// The lock belongs to the device's open file description, so it also ends
+/// when every handle to the device is closed.
+#[derive(Debug)]
+pub struct DeviceLock {
+    /// A duplicate of the device's file descriptor, used to unlock it.
+    fd: Option<OwnedFd>,
+    tty: bool,
+}
+
+impl DeviceLock {
+    /// Lock `fd`, waiting for another holder to release it if `blocking` is
+    /// true, or failing with `WouldBlock` otherwise.
+    pub(crate) fn acquire(fd: BorrowedFd<'_>, blocking: bool) -> io::Result<Self> {
+        let fd = rustix::io::fcntl_dupfd_cloexec(fd, 0)?;
+        let operation = if blocking {
+            FlockOperation::LockExclusive
+        } else {
+            FlockOperation::NonBlockingLockExclusive
+        };
+        flock(&fd, operation)?;
+
+        let tty = rustix::termios::isatty(&fd);
+        if tty {
+            if let Err(e) = crate::ioctl::call(&fd, &crate::ioctl::TIOCEXCL, ()) {
+                flock(&fd, FlockOperation::Unlock).ok();
+                return Err(e);
+            }
+        }
+
+        Ok(Self { fd: Some(fd), tty })
+    }
+
+    /// Keep the lock for as long as the device stays open, rather than
+    /// releasing it when this guard is dropped.
+    pub(crate) fn persist(mut self) {
+        self.fd = None;
+    }
+}
+
+impl Drop for DeviceLock {
+    fn drop(&mut self) {
+        if let Some(fd) = self.fd.take() {
+            if self.tty {
+                crate::ioctl::call(&fd, &crate::ioctl::TIOCNXCL, ()).ok();
+            }
+            flock(&fd, FlockOperation::Unlock).ok();
+        }
+    }
+}
+
+impl CharDevice {
+    /// Acquire an exclusive lock on this device, waiting until any other
+    /// holder releases it.
+    ///
+    /// See [`DeviceLock`] for details.
+    #[inline]
+    pub fn lock_exclusive(&self) -> io::Result<DeviceLock> {
+        DeviceLock::acquire(self.as_fd(), true)
+    }
+
+    /// Acquire an exclusive lock on this device, failing with
+    /// [`io::ErrorKind::WouldBlock`] if another holder has it.
+    ///
+    /// See [`DeviceLock`] for details.
+    #[inline]
+    pub fn try_lock_exclusive(&self) -> io::Result<DeviceLock> {
+        DeviceLock::acquire(self.as_fd(), false)
+    }
+}
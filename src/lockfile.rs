@@ -0,0 +1,226 @@
+//! UUCP-style lock files for serial ports.
+//!
+//! Traditional Unix tools such as `cu`, `minicom`, and ModemManager
+//! coordinate access to serial ports with lock files named like
+//! "/var/lock/LCK..ttyS0", containing the owner's PID as ten ASCII digits
+//! followed by a newline. This module creates, checks, and removes such
+//! files, so that this crate's users can interoperate with those tools.
+//!
+//! To hold a lock file for as long as a device is open, set
+//! [`CharDeviceOptions::lock_file`]. The lock file is released once the
+//! device and its clones are closed.
+//!
+//! [`CharDeviceOptions::lock_file`]: crate::CharDeviceOptions::lock_file
+
+use rustix::fs::{flock, FlockOperation};
+use rustix::io::Errno;
+use rustix::process::{getpid, test_kill_process, Pid};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The conventional directory for lock files.
+pub const DEFAULT_LOCK_DIR: &str = "/var/lock";
+
+/// A directory of UUCP-style lock files.
+#[derive(Debug, Clone)]
+pub struct LockDir {
+    dir: PathBuf,
+}
+
+impl LockDir {
+    /// Use the conventional lock directory, [`DEFAULT_LOCK_DIR`].
+    #[inline]
+    pub fn new() -> Self {
+        Self::with_dir(DEFAULT_LOCK_DIR)
+    }
+
+    /// Use the given directory for lock files.
+    #[inline]
+    pub fn with_dir<P: AsRef<Path>>(dir: P) -> Self {
+        Self {
+            dir: dir.as_ref().to_owned(),
+        }
+    }
+
+    /// Return the path of the lock file for the device at `device`.
+    ///
+    /// Symlinks such as "/dev/serial/by-id/..." are resolved first, so that
+    /// every path to the same device uses the same lock file.
+    pub fn lock_path<P: AsRef<Path>>(&self, device: P) -> io::Result<PathBuf> {
+        let device = fs::canonicalize(device)?;
+        let name = device
+            .file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "device has no name"))?;
+        let mut file_name = std::ffi::OsString::from("LCK..");
+        file_name.push(name);
+        Ok(self.dir.join(file_name))
+    }
+
+    /// Return the PID of the live process holding the lock for `device`, if
+    /// any.
+    ///
+    /// Lock files left behind by processes which no longer exist are
+    /// reported as `None`.
+    pub fn owner<P: AsRef<Path>>(&self, device: P) -> io::Result<Option<u32>> {
+        let path = self.lock_path(device)?;
+        Ok(read_pid(&path)?.filter(|pid| is_alive(*pid)))
+    }
+
+    /// Create the lock file for `device`, failing with
+    /// [`io::ErrorKind::WouldBlock`] if a live process holds it.
+    ///
+    /// Stale lock files, left behind by processes which no longer exist, are
+    /// removed. The lock file is created atomically, so that other processes
+    /// never see a partially written file.
+    pub fn acquire<P: AsRef<Path>>(&self, device: P) -> io::Result<LockFile> {
+        let path = self.lock_path(device)?;
+        let pid = getpid().as_raw_nonzero().get() as u32;
+
+        // Write our PID to a temporary file, and then hard-link it into place,
+        // which fails if the lock file already exists.
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let temp = self.dir.join(format!(
+            "LTMP.{}.{}",
+            pid,
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let result = write_pid(&temp, pid).and_then(|()| self.link(&temp, &path, pid));
+        fs::remove_file(&temp).ok();
+        result?;
+
+        Ok(LockFile { path: Some(path) })
+    }
+
+    fn link(&self, temp: &Path, path: &Path, pid: u32) -> io::Result<()> {
+        // Retry once after removing a stale lock file.
+        for _ in 0..2 {
+            match fs::hard_link(temp, path) {
+                Ok(()) => return Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e),
+            }
+            remove_stale(path, pid)?;
+        }
+        Err(io::Error::new(
+            io::ErrorKind::WouldBlock,
+            "device lock file is contended",
+        ))
+    }
+}
+
+/// Remove the lock file at `path` if it's stale, or fail with
+/// [`io::ErrorKind::WouldBlock`] if it's held.
+///
+/// The file is `flock`ed while it's checked and removed. Without that, two
+/// processes could both read the same stale PID, and after one removed the
+/// file and linked its own lock file in its place, the other would remove
+/// that one.
+fn remove_stale(path: &Path, pid: u32) -> io::Result<()> {
+    let mut file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    flock(&file, FlockOperation::LockExclusive)?;
+
+    // If another process removed the file while we waited for the lock, the
+    // path now refers to something else, or nothing, so leave it alone.
+    let opened = file.metadata()?;
+    match fs::metadata(path) {
+        Ok(current) if current.dev() == opened.dev() && current.ino() == opened.ino() => {}
+        Ok(_) => return Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    }
+
+    let mut contents = Vec::new();
+    file.read_to_end(&mut contents)?;
+    match parse_pid(&contents) {
+        Some(owner) if owner == pid || is_alive(owner) => Err(io::Error::new(
+            io::ErrorKind::WouldBlock,
+            format!("device is locked by process {}", owner),
+        )),
+        _ => match fs::remove_file(path) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e),
+        },
+    }
+}
+
+impl Default for LockDir {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A held UUCP-style lock file, removed when dropped.
+#[derive(Debug)]
+pub struct LockFile {
+    path: Option<PathBuf>,
+}
+
+impl LockFile {
+    /// Return the path of the lock file.
+    #[inline]
+    pub fn path(&self) -> &Path {
+        self.path.as_deref().unwrap()
+    }
+
+    /// Remove the lock file, reporting any errors.
+    #[inline]
+    pub fn release(mut self) -> io::Result<()> {
+        fs::remove_file(self.path.take().unwrap())
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        if let Some(path) = self.path.take() {
+            fs::remove_file(path).ok();
+        }
+    }
+}
+
+fn write_pid(path: &Path, pid: u32) -> io::Result<()> {
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)?;
+    writeln!(file, "{:10}", pid)?;
+    Ok(())
+}
+
+/// Read the PID from a lock file, accepting both the ASCII format and the
+/// older binary format. Unreadable contents are reported as `None`, so that
+/// they're treated as stale.
+fn read_pid(path: &Path) -> io::Result<Option<u32>> {
+    let contents = match fs::read(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    Ok(parse_pid(&contents))
+}
+
+fn parse_pid(contents: &[u8]) -> Option<u32> {
+    if let Some(pid) = std::str::from_utf8(contents)
+        .ok()
+        .and_then(|s| s.trim().parse().ok())
+    {
+        return Some(pid);
+    }
+    <[u8; 4]>::try_from(contents).ok().map(u32::from_ne_bytes)
+}
+
+fn is_alive(pid: u32) -> bool {
+    match i32::try_from(pid).ok().and_then(Pid::from_raw) {
+        Some(pid) => !matches!(test_kill_process(pid), Err(Errno::SRCH)),
+        None => false,
+    }
+}
@@ -0,0 +1,179 @@
+use crate::{CharDevice, CharDeviceOptions};
+use io_extras::os::rustix::{AsRawFd, AsRawReadWriteFd, AsReadWriteFd, RawFd};
+use io_lifetimes::{AsFd, BorrowedFd, IntoFilelike};
+use mio::event::Source;
+use mio::unix::SourceFd;
+use mio::{Interest, Registry, Token};
+use std::io::{self, IoSlice, IoSliceMut, Read, Write};
+use std::path::Path;
+
+/// A character device in non-blocking mode, for use with [`mio::Poll`].
+///
+/// This implements [`Source`], so it can be registered with a [`Registry`]
+/// directly. Reads and writes fail with [`io::ErrorKind::WouldBlock`] when
+/// the device isn't ready.
+///
+/// Registering fails if the device's driver doesn't support polling, which
+/// is the case for "/dev/null" and similar devices on Linux. Terminals,
+/// ptys, and most serial ports support it.
+///
+/// Non-blocking mode is a property of the open file description, so it's
+/// also seen through any duplicates of the handle.
+#[derive(Debug)]
+pub struct MioCharDevice(CharDevice);
+
+impl MioCharDevice {
+    /// Construct a new `MioCharDevice`, and put it in non-blocking mode.
+    /// Fail if the given handle isn't a valid handle for a character device,
+    /// or it can't be determined.
+    #[inline]
+    pub fn new<Filelike: IntoFilelike + Read + Write>(filelike: Filelike) -> io::Result<Self> {
+        Self::from_char_device(CharDevice::new(filelike)?)
+    }
+
+    fn from_char_device(device: CharDevice) -> io::Result<Self> {
+        rustix::io::ioctl_fionbio(&device, true)?;
+        Ok(Self(device))
+    }
+
+    /// Construct a new `MioCharDevice` from the given filename, and put it
+    /// in non-blocking mode. Fail if the given handle isn't a valid handle
+    /// for a character device, or it can't be determined.
+    #[inline]
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::from_char_device(CharDevice::open(path)?)
+    }
+
+    /// Construct a new `MioCharDevice` from the given filename, with the
+    /// given options, and put it in non-blocking mode.
+    #[inline]
+    pub fn open_with<P: AsRef<Path>>(path: P, options: &CharDeviceOptions) -> io::Result<Self> {
+        Self::from_char_device(options.open(path)?)
+    }
+
+    /// Construct a new `MioCharDevice`.
+    ///
+    /// # Safety
+    ///
+    /// Doesn't check that the handle is valid or a character device, or put
+    /// it in non-blocking mode.
+    #[inline]
+    pub unsafe fn new_unchecked<Filelike: IntoFilelike>(filelike: Filelike) -> Self {
+        Self(CharDevice::new_unchecked(filelike))
+    }
+
+    /// Return the number of bytes which are ready to be read immediately.
+    #[inline]
+    pub fn num_ready_bytes(&self) -> io::Result<u64> {
+        self.0.num_ready_bytes()
+    }
+
+    /// Return a reference to the underlying device.
+    #[inline]
+    pub fn get_ref(&self) -> &CharDevice {
+        &self.0
+    }
+
+    /// Put the device back in blocking mode, and return it.
+    ///
+    /// The device should be deregistered first.
+    #[inline]
+    pub fn into_inner(self) -> io::Result<CharDevice> {
+        rustix::io::ioctl_fionbio(&self.0, false)?;
+        Ok(self.0)
+    }
+}
+
+impl Source for MioCharDevice {
+    #[inline]
+    fn register(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        SourceFd(&self.as_raw_fd()).register(registry, token, interests)
+    }
+
+    #[inline]
+    fn reregister(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        SourceFd(&self.as_raw_fd()).reregister(registry, token, interests)
+    }
+
+    #[inline]
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        SourceFd(&self.as_raw_fd()).deregister(registry)
+    }
+}
+
+impl Read for MioCharDevice {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+
+    #[inline]
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
+        self.0.read_vectored(bufs)
+    }
+}
+
+impl Write for MioCharDevice {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    #[inline]
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        self.0.write_vectored(bufs)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl AsRawFd for MioCharDevice {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl AsFd for MioCharDevice {
+    #[inline]
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.0.as_fd()
+    }
+}
+
+impl AsRawReadWriteFd for MioCharDevice {
+    #[inline]
+    fn as_raw_read_fd(&self) -> RawFd {
+        self.as_raw_fd()
+    }
+
+    #[inline]
+    fn as_raw_write_fd(&self) -> RawFd {
+        self.as_raw_fd()
+    }
+}
+
+impl AsReadWriteFd for MioCharDevice {
+    #[inline]
+    fn as_read_fd(&self) -> BorrowedFd<'_> {
+        self.as_fd()
+    }
+
+    #[inline]
+    fn as_write_fd(&self) -> BorrowedFd<'_> {
+        self.as_fd()
+    }
+}
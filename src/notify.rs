@@ -0,0 +1,238 @@
+//! Asynchronous I/O notification with `O_ASYNC`.
+//!
+//! [`CharDevice::enable_async_notify`] asks the kernel to send the process a
+//! signal whenever the device becomes readable or writable, and
+//! [`notifications`] turns those signals into a channel of file descriptors,
+//! so that programs using this model don't need to write their own signal
+//! handlers.
+//!
+//! ```no_run
+//! use char_device::notify::notifications;
+//! use char_device::CharDevice;
+//!
+//! # fn main() -> std::io::Result<()> {
+//! let signal = libc::SIGRTMIN();
+//! let ready = notifications(signal)?;
+//!
+//! let tty = CharDevice::open("/dev/ttyS0")?;
+//! tty.enable_async_notify(signal)?;
+//!
+//! for fd in ready {
+//!     println!("fd {} is ready", fd);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::CharDevice;
+use io_lifetimes::AsFd;
+use std::collections::HashMap;
+use std::ffi::{c_int, c_long, c_void};
+use std::io::{self, Read};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Mutex, OnceLock};
+
+/// One more than the highest signal number on this target.
+const NSIG: usize = linux_raw_sys::general::_NSIG as usize + 1;
+
+/// The write end of each signal's notification socket, or -1.
+static SOCKETS: [AtomicI32; NSIG] = [const { AtomicI32::new(-1) }; NSIG];
+
+/// The subscribers for each signal.
+static SUBSCRIBERS: OnceLock<Mutex<HashMap<c_int, Vec<Sender<RawFd>>>>> = OnceLock::new();
+
+impl CharDevice {
+    /// Ask the kernel to send this process `signal` whenever the device
+    /// becomes readable or writable.
+    ///
+    /// This sets `O_ASYNC`, directs the signal at this process with
+    /// `F_SETOWN_EX`, and selects the signal with `F_SETSIG`, so that its
+    /// `siginfo_t` identifies the device. Using a real-time signal, such as
+    /// `SIGRTMIN`, lets the kernel queue one signal per event. Use
+    /// [`notifications`] to receive them.
+    ///
+    /// Call [`notifications`] for `signal` first, or install a handler of
+    /// your own. The default action of real-time signals is to terminate the
+    /// process, so an event before then kills it.
+    pub fn enable_async_notify(&self, signal: c_int) -> io::Result<()> {
+        if signal <= 0 || signal as usize >= NSIG {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid signal number",
+            ));
+        }
+
+        let owner = linux_raw_sys::general::f_owner_ex {
+            type_: linux_raw_sys::general::F_OWNER_PID as _,
+            pid: rustix::process::getpid().as_raw_nonzero().get(),
+        };
+        fcntl(
+            self,
+            linux_raw_sys::general::F_SETOWN_EX,
+            &owner as *const _ as usize,
+        )?;
+        fcntl(self, linux_raw_sys::general::F_SETSIG, signal as usize)?;
+
+        let flags = rustix::fs::fcntl_getfl(self)?;
+        rustix::fs::fcntl_setfl(self, flags | rustix::fs::OFlags::ASYNC)?;
+        Ok(())
+    }
+
+    /// Stop sending signals for this device.
+    pub fn disable_async_notify(&self) -> io::Result<()> {
+        let flags = rustix::fs::fcntl_getfl(self)?;
+        rustix::fs::fcntl_setfl(self, flags - rustix::fs::OFlags::ASYNC)?;
+        Ok(())
+    }
+}
+
+/// Return a channel which receives the file descriptor of a device each
+/// time `signal` reports it ready.
+///
+/// The first call for a given signal installs a handler for it, which stays
+/// installed for the life of the process. If the signal already has a handler
+/// which wasn't installed by this function, this fails with
+/// [`io::ErrorKind::AlreadyExists`] rather than replace it. Each call returns
+/// an independent receiver. If the kernel's queue of real-time signals overflows, it sends a
+/// plain `SIGIO` instead, so programs using real-time signals may want to
+/// also watch `SIGIO` and poll all of their devices when it arrives.
+pub fn notifications(signal: c_int) -> io::Result<Receiver<RawFd>> {
+    if signal <= 0 || signal as usize >= NSIG {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "invalid signal number",
+        ));
+    }
+
+    let (sender, receiver) = mpsc::channel();
+    let mut subscribers = SUBSCRIBERS.get_or_init(Default::default).lock().unwrap();
+    let installed = subscribers.contains_key(&signal);
+    subscribers.entry(signal).or_default().push(sender);
+    if !installed {
+        if let Err(e) = install(signal) {
+            subscribers.remove(&signal);
+            return Err(e);
+        }
+    }
+    Ok(receiver)
+}
+
+/// Install the handler for `signal`, and a thread to forward its
+/// notifications to subscribers.
+fn install(signal: c_int) -> io::Result<()> {
+    let (mut reader, writer) = UnixStream::pair()?;
+    writer.set_nonblocking(true)?;
+
+    // SAFETY: Passing a null action only queries the current one.
+    let old = unsafe {
+        let mut old: libc::sigaction = std::mem::zeroed();
+        if libc::sigaction(signal, std::ptr::null(), &mut old) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        old
+    };
+    if old.sa_sigaction != libc::SIG_DFL && old.sa_sigaction != libc::SIG_IGN {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "signal already has a handler",
+        ));
+    }
+
+    SOCKETS[signal as usize].store(writer.as_raw_fd(), Ordering::Release);
+
+    // SAFETY: `handler` only performs async-signal-safe operations.
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = handler as *const () as usize;
+        action.sa_flags = libc::SA_SIGINFO | libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        if libc::sigaction(signal, &action, std::ptr::null_mut()) != 0 {
+            let e = io::Error::last_os_error();
+            SOCKETS[signal as usize].store(-1, Ordering::Release);
+            return Err(e);
+        }
+    }
+
+    let spawned = std::thread::Builder::new()
+        .name(format!("char-device signal {}", signal))
+        .spawn(move || {
+            let mut buf = [0_u8; size_of::<RawFd>()];
+            while reader.read_exact(&mut buf).is_ok() {
+                let fd = RawFd::from_ne_bytes(buf);
+                let mut subscribers = SUBSCRIBERS.get().unwrap().lock().unwrap();
+                if let Some(senders) = subscribers.get_mut(&signal) {
+                    senders.retain(|sender| sender.send(fd).is_ok());
+                }
+            }
+        });
+    if let Err(e) = spawned {
+        // SAFETY: This restores the action the signal had before.
+        unsafe {
+            libc::sigaction(signal, &old, std::ptr::null_mut());
+        }
+        SOCKETS[signal as usize].store(-1, Ordering::Release);
+        return Err(e);
+    }
+
+    // The handler may use the writer at any time from now on, so it's never
+    // closed.
+    std::mem::forget(writer);
+    Ok(())
+}
+
+/// The prefix of `siginfo_t` for `SIGPOLL`-style signals.
+#[repr(C)]
+struct SigPollInfo {
+    signo: c_int,
+    errno: c_int,
+    code: c_int,
+    band: c_long,
+    fd: c_int,
+}
+
+extern "C" fn handler(signal: c_int, info: *mut libc::siginfo_t, _context: *mut c_void) {
+    let socket = SOCKETS[signal as usize].load(Ordering::Acquire);
+    if socket < 0 || info.is_null() {
+        return;
+    }
+
+    // SAFETY: The kernel passes a valid `siginfo_t`, and for signals selected
+    // with `F_SETSIG` it's laid out as a `SigPollInfo`. `send` is
+    // async-signal-safe, and we preserve `errno` around it.
+    unsafe {
+        let fd = (*info.cast::<SigPollInfo>()).fd;
+        let errno = *errno_location();
+        // If the socket is full, drop the notification rather than block.
+        libc::send(
+            socket,
+            fd.to_ne_bytes().as_ptr().cast(),
+            size_of::<RawFd>(),
+            libc::MSG_DONTWAIT | libc::MSG_NOSIGNAL,
+        );
+        *errno_location() = errno;
+    }
+}
+
+#[cfg(target_os = "linux")]
+unsafe fn errno_location() -> *mut c_int {
+    libc::__errno_location()
+}
+
+#[cfg(target_os = "android")]
+unsafe fn errno_location() -> *mut c_int {
+    libc::__errno()
+}
+
+/// Call `fcntl` with a command which isn't available in `rustix`.
+fn fcntl<Fd: AsFd>(fd: Fd, cmd: u32, arg: usize) -> io::Result<()> {
+    // SAFETY: The callers pass commands whose argument is either an integer
+    // or a pointer to a live value of the right type.
+    let ret = unsafe { libc::fcntl(fd.as_fd().as_raw_fd(), cmd as c_int, arg) };
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
@@ -0,0 +1,149 @@
+//! Options for opening character devices.
+
+use crate::CharDevice;
+use std::fs::OpenOptions;
+use std::io;
+use std::path::Path;
+#[cfg(not(windows))]
+use {
+    crate::lockfile::{LockDir, LockFile},
+    std::sync::Arc,
+};
+
+/// Options and flags which can be used to configure how a character device
+/// is opened.
+///
+/// This is similar to [`std::fs::OpenOptions`]. By default, devices are
+/// opened for reading and writing, without exclusive access, and without a
+/// lock file.
+#[derive(Debug, Clone)]
+pub struct CharDeviceOptions {
+    read: bool,
+    write: bool,
+    exclusive: bool,
+    #[cfg(not(windows))]
+    lock_dir: Option<LockDir>,
+}
+
+impl CharDeviceOptions {
+    /// Create a blank new set of options, for reading and writing.
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            read: true,
+            write: true,
+            exclusive: false,
+            #[cfg(not(windows))]
+            lock_dir: None,
+        }
+    }
+
+    /// Set the option for read access.
+    #[inline]
+    pub fn read(&mut self, read: bool) -> &mut Self {
+        self.read = read;
+        self
+    }
+
+    /// Set the option for write access.
+    #[inline]
+    pub fn write(&mut self, write: bool) -> &mut Self {
+        self.write = write;
+        self
+    }
+
+    /// Set the option for exclusive access.
+    ///
+    /// On Posix-ish platforms, this takes a [`DeviceLock`] for as long as the
+    /// device is open, failing with [`io::ErrorKind::WouldBlock`] if another
+    /// holder has it. On Windows, this opens the device without sharing.
+    ///
+    /// [`DeviceLock`]: crate::DeviceLock
+    #[inline]
+    pub fn exclusive(&mut self, exclusive: bool) -> &mut Self {
+        self.exclusive = exclusive;
+        self
+    }
+
+    /// Set the directory of UUCP-style lock files to hold the device's lock
+    /// file in while it's open, or `None` to not use a lock file.
+    ///
+    /// The lock file is taken before the device is opened, failing with
+    /// [`io::ErrorKind::WouldBlock`] if a live process holds it. It's removed
+    /// once the device and any clones of it are closed, or converted into raw
+    /// handles.
+    #[cfg(not(windows))]
+    #[inline]
+    pub fn lock_file(&mut self, dir: Option<LockDir>) -> &mut Self {
+        self.lock_dir = dir;
+        self
+    }
+
+    /// Open the character device at `path` with these options.
+    pub fn open<P: AsRef<Path>>(&self, path: P) -> io::Result<CharDevice> {
+        let path = path.as_ref();
+        let open = || {
+            #[cfg(not(windows))]
+            let lock_file = self.acquire_lock_file(path)?;
+            let device = CharDevice::new(self.std_options().open(path)?)?;
+            self.apply(&device)?;
+            #[cfg(not(windows))]
+            let device = device.with_lock_file(lock_file);
+            Ok(device)
+        };
+
+        #[cfg(not(feature = "tracing"))]
+        {
+            open()
+        }
+
+        #[cfg(feature = "tracing")]
+        {
+            crate::trace::open(path, self, open)
+        }
+    }
+
+    /// Return the `std` options corresponding to these options.
+    pub(crate) fn std_options(&self) -> OpenOptions {
+        let mut options = OpenOptions::new();
+        options.read(self.read).write(self.write);
+        #[cfg(windows)]
+        if self.exclusive {
+            use std::os::windows::fs::OpenOptionsExt;
+            options.share_mode(0);
+        }
+        options
+    }
+
+    /// Take the lock file for the device at `path`, if these options use
+    /// one.
+    #[cfg(not(windows))]
+    pub(crate) fn acquire_lock_file(&self, path: &Path) -> io::Result<Option<Arc<LockFile>>> {
+        self.lock_dir
+            .as_ref()
+            .map(|dir| dir.acquire(path).map(Arc::new))
+            .transpose()
+    }
+
+    /// Apply the options which take effect after the device is opened.
+    #[cfg(not(windows))]
+    pub(crate) fn apply<Fd: io_lifetimes::AsFd>(&self, device: &Fd) -> io::Result<()> {
+        if self.exclusive {
+            crate::DeviceLock::acquire(device.as_fd(), false)?.persist();
+        }
+        Ok(())
+    }
+
+    /// Apply the options which take effect after the device is opened.
+    #[cfg(windows)]
+    pub(crate) fn apply<Handle>(&self, _device: &Handle) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Default for CharDeviceOptions {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,44 @@
+//! Terminal output queue inspection, draining, and discarding.
+
+use io_lifetimes::AsFd;
+use rustix::io::Errno;
+use rustix::termios::{tcdrain, tcflush, QueueSelector};
+use std::io;
+
+/// Which of a terminal's queues to discard.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Queue {
+    /// Data received but not yet read.
+    Input,
+    /// Data written but not yet transmitted.
+    Output,
+    /// Both of the above.
+    Both,
+}
+
+/// Return the number of bytes written to `fd` but not yet transmitted.
+pub(crate) fn pending_output_bytes<Fd: AsFd>(fd: Fd) -> io::Result<u64> {
+    let n = crate::ioctl::call(fd, &crate::ioctl::TIOCOUTQ, ())?;
+    Ok(n as u64)
+}
+
+/// Wait until everything written to `fd` has been transmitted.
+pub(crate) fn drain<Fd: AsFd>(fd: Fd) -> io::Result<()> {
+    loop {
+        match tcdrain(&fd) {
+            Ok(()) => return Ok(()),
+            Err(Errno::INTR) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Discard the contents of the given queues of `fd`.
+pub(crate) fn discard<Fd: AsFd>(fd: Fd, queue: Queue) -> io::Result<()> {
+    let selector = match queue {
+        Queue::Input => QueueSelector::IFlush,
+        Queue::Output => QueueSelector::OFlush,
+        Queue::Both => QueueSelector::IOFlush,
+    };
+    Ok(tcflush(fd, selector)?)
+}
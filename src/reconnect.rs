@@ -0,0 +1,650 @@
+//! Devices which reopen themselves after being disconnected.
+//!
+//! USB serial adapters and similar devices can disappear at any time, and
+//! come back under the same path. [`ReconnectingCharDevice`] remembers the
+//! path a device was opened from, and when a read or write reports that the
+//! device hung up or went away, reopens it with exponential backoff,
+//! restores its configuration, and retries the operation.
+//!
+//! ```no_run
+//! use char_device::reconnect::{ReconnectEvent, ReconnectOptions, ReconnectingCharDevice};
+//! use std::io::Read;
+//!
+//! # fn main() -> std::io::Result<()> {
+//! let mut options = ReconnectOptions::new();
+//! options.wait_for_node(true).on_event(|event| match event {
+//!     ReconnectEvent::Disconnected => eprintln!("disconnected"),
+//!     ReconnectEvent::Reconnected { attempts } => {
+//!         eprintln!("reconnected after {} attempts", attempts)
+//!     }
+//! });
+//!
+//! let mut device = ReconnectingCharDevice::open("/dev/ttyUSB0", &options)?;
+//! let mut buf = [0; 64];
+//! loop {
+//!     let n = device.read(&mut buf)?;
+//!     println!("{:?}", &buf[..n]);
+//! }
+//! # }
+//! ```
+
+use crate::{CharDevice, CharDeviceOptions};
+use io_lifetimes::AsFd;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+use rustix::fd::OwnedFd;
+use rustix::io::Errno;
+use rustix::termios::{tcgetattr, tcsetattr, OptionalActions, Termios};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+#[cfg(all(feature = "tokio", any(target_os = "android", target_os = "linux")))]
+use tokio::io::unix::AsyncFd;
+#[cfg(feature = "tokio")]
+use {
+    crate::TokioCharDevice,
+    std::future::Future,
+    std::pin::Pin,
+    std::task::{ready, Context, Poll},
+    tokio::io::{AsyncRead, AsyncWrite, ReadBuf},
+    tokio::task::JoinHandle,
+};
+
+/// A change in the connection state of a reconnecting device.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ReconnectEvent {
+    /// The device hung up or went away, and reconnecting has started.
+    Disconnected,
+    /// The device was reopened, after the given number of attempts.
+    Reconnected {
+        /// The number of times the device was opened, including the
+        /// successful one.
+        attempts: u32,
+    },
+}
+
+type Configure = Arc<dyn Fn(&CharDevice) -> io::Result<()> + Send + Sync>;
+type OnEvent = Arc<dyn Fn(ReconnectEvent) + Send + Sync>;
+
+/// Options which configure how a device is reconnected.
+///
+/// By default, the first retry happens after 100 milliseconds, the delay
+/// doubles after each failed attempt up to 10 seconds, and attempts continue
+/// indefinitely.
+#[derive(Clone)]
+pub struct ReconnectOptions {
+    options: CharDeviceOptions,
+    initial_delay: Duration,
+    max_delay: Duration,
+    max_attempts: Option<u32>,
+    wait_for_node: bool,
+    configure: Option<Configure>,
+    on_event: Option<OnEvent>,
+}
+
+impl ReconnectOptions {
+    /// Create a new set of options, with the defaults described above.
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            options: CharDeviceOptions::new(),
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(10),
+            max_attempts: None,
+            wait_for_node: false,
+            configure: None,
+            on_event: None,
+        }
+    }
+
+    /// Set the options to open the device with. These are used for every
+    /// open, so an exclusive lock, for example, is taken again after each
+    /// reconnect.
+    #[inline]
+    pub fn open_options(&mut self, options: CharDeviceOptions) -> &mut Self {
+        self.options = options;
+        self
+    }
+
+    /// Set the delay before the first retry.
+    #[inline]
+    pub fn initial_delay(&mut self, delay: Duration) -> &mut Self {
+        self.initial_delay = delay;
+        self
+    }
+
+    /// Set the longest delay between retries.
+    #[inline]
+    pub fn max_delay(&mut self, delay: Duration) -> &mut Self {
+        self.max_delay = delay;
+        self
+    }
+
+    /// Set the number of attempts after which reconnecting fails with the
+    /// last error, or `None` to keep trying.
+    #[inline]
+    pub fn max_attempts(&mut self, attempts: Option<u32>) -> &mut Self {
+        self.max_attempts = attempts;
+        self
+    }
+
+    /// Set whether to use inotify to retry as soon as the device's path
+    /// reappears, rather than waiting out the whole delay.
+    ///
+    /// This only has an effect on Linux and Android.
+    #[inline]
+    pub fn wait_for_node(&mut self, wait: bool) -> &mut Self {
+        self.wait_for_node = wait;
+        self
+    }
+
+    /// Set a function to configure the device each time it's opened,
+    /// including the first time. If it fails, the attempt fails.
+    pub fn configure<F>(&mut self, configure: F) -> &mut Self
+    where
+        F: Fn(&CharDevice) -> io::Result<()> + Send + Sync + 'static,
+    {
+        self.configure = Some(Arc::new(configure));
+        self
+    }
+
+    /// Set a function to be called with each [`ReconnectEvent`].
+    pub fn on_event<F>(&mut self, on_event: F) -> &mut Self
+    where
+        F: Fn(ReconnectEvent) + Send + Sync + 'static,
+    {
+        self.on_event = Some(Arc::new(on_event));
+        self
+    }
+
+    /// Open and configure the device at `path`, once.
+    fn open_once(&self, path: &Path, termios: Option<&Termios>) -> io::Result<CharDevice> {
+        let device = self.options.open(path)?;
+        if let Some(termios) = termios {
+            tcsetattr(&device, OptionalActions::Now, termios)?;
+        }
+        if let Some(configure) = &self.configure {
+            configure(&device)?;
+        }
+        Ok(device)
+    }
+
+    /// Reopen the device at `path`, retrying with backoff.
+    ///
+    /// The old device must already be closed, so that it doesn't hold on to
+    /// an exclusive lock the new one needs.
+    fn reconnect(&self, path: &Path, termios: Option<&Termios>) -> io::Result<CharDevice> {
+        self.emit(ReconnectEvent::Disconnected);
+
+        let mut delay = self.initial_delay;
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            match self.open_once(path, termios) {
+                Ok(device) => {
+                    self.emit(ReconnectEvent::Reconnected { attempts });
+                    return Ok(device);
+                }
+                Err(e) if self.max_attempts.is_some_and(|max| attempts >= max) => return Err(e),
+                Err(_) => {}
+            }
+            self.wait(path, delay);
+            delay = (delay * 2).min(self.max_delay);
+        }
+    }
+
+    /// Wait for up to `delay` before the next attempt to open `path`.
+    fn wait(&self, path: &Path, delay: Duration) {
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        if self.wait_for_node && !path.exists() && wait_for_node(path, delay).is_ok() {
+            return;
+        }
+
+        let _ = path;
+        std::thread::sleep(delay);
+    }
+
+    /// Reopen the device at `path`, retrying with backoff, without blocking
+    /// the runtime. Each attempt to open the device runs on a blocking
+    /// thread, and the waits between them are tokio timers, so dropping the
+    /// future stops the retries.
+    #[cfg(feature = "tokio")]
+    async fn reconnect_async(
+        self,
+        path: PathBuf,
+        termios: Option<Termios>,
+    ) -> io::Result<CharDevice> {
+        self.emit(ReconnectEvent::Disconnected);
+
+        let mut delay = self.initial_delay;
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            match self.open_blocking(&path, termios.as_ref()).await {
+                Ok(device) => {
+                    self.emit(ReconnectEvent::Reconnected { attempts });
+                    return Ok(device);
+                }
+                Err(e) if self.max_attempts.is_some_and(|max| attempts >= max) => return Err(e),
+                Err(_) => {}
+            }
+            self.wait_async(&path, delay).await;
+            delay = (delay * 2).min(self.max_delay);
+        }
+    }
+
+    /// Open and configure the device at `path`, once, on a blocking thread.
+    #[cfg(feature = "tokio")]
+    async fn open_blocking(
+        &self,
+        path: &Path,
+        termios: Option<&Termios>,
+    ) -> io::Result<CharDevice> {
+        let options = self.clone();
+        let path = path.to_owned();
+        let termios = termios.cloned();
+        tokio::task::spawn_blocking(move || options.open_once(&path, termios.as_ref()))
+            .await
+            .map_err(io::Error::other)?
+    }
+
+    /// Wait for up to `delay` before the next attempt to open `path`,
+    /// without blocking the runtime.
+    #[cfg(feature = "tokio")]
+    async fn wait_async(&self, path: &Path, delay: Duration) {
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        if self.wait_for_node && !path.exists() {
+            match watch_node(path).and_then(|watch| watch.map(AsyncFd::new).transpose()) {
+                Ok(None) => return,
+                Ok(Some(watch)) => {
+                    let _ = tokio::time::timeout(delay, watch.readable()).await;
+                    return;
+                }
+                Err(_) => {}
+            }
+        }
+
+        let _ = path;
+        tokio::time::sleep(delay).await;
+    }
+
+    fn emit(&self, event: ReconnectEvent) {
+        if let Some(on_event) = &self.on_event {
+            on_event(event);
+        }
+    }
+}
+
+impl Default for ReconnectOptions {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for ReconnectOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReconnectOptions")
+            .field("options", &self.options)
+            .field("initial_delay", &self.initial_delay)
+            .field("max_delay", &self.max_delay)
+            .field("max_attempts", &self.max_attempts)
+            .field("wait_for_node", &self.wait_for_node)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Wait for up to `timeout` for something to be created at `path`.
+///
+/// This fails if the parent directory can't be watched, which happens when
+/// it's removed along with the device, as "/dev/serial/by-id" is when the
+/// last USB serial adapter is unplugged.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+fn wait_for_node(path: &Path, timeout: Duration) -> io::Result<()> {
+    use rustix::event::{poll, PollFd, PollFlags, Timespec};
+
+    let Some(inotify) = watch_node(path)? else {
+        return Ok(());
+    };
+    let timeout = Timespec {
+        tv_sec: timeout.as_secs() as _,
+        tv_nsec: timeout.subsec_nanos() as _,
+    };
+    match poll(&mut [PollFd::new(&inotify, PollFlags::IN)], Some(&timeout)) {
+        Ok(_) | Err(Errno::INTR) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Watch for something to be created at `path`, returning an inotify
+/// descriptor which becomes readable when it might have been, or `None` if
+/// it already exists.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+fn watch_node(path: &Path) -> io::Result<Option<OwnedFd>> {
+    use rustix::fs::inotify::{self, CreateFlags, WatchFlags};
+
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let inotify = inotify::init(CreateFlags::CLOEXEC)?;
+    inotify::add_watch(
+        &inotify,
+        dir,
+        WatchFlags::CREATE | WatchFlags::MOVED_TO | WatchFlags::ATTRIB,
+    )?;
+
+    // Check again now that the watch is in place, in case the node appeared
+    // in the meantime.
+    if path.exists() {
+        return Ok(None);
+    }
+    Ok(Some(inotify))
+}
+
+/// Return whether `error` means the device hung up or went away.
+fn is_disconnect(error: &io::Error) -> bool {
+    crate::event::classify_error(error).is_some()
+}
+
+/// Return whether the terminal `fd` has been hung up.
+///
+/// After a hangup, reads on a terminal return end of file, which is
+/// indistinguishable from a read timeout in non-canonical mode, but ioctls
+/// fail with `EIO`.
+fn is_hung_up<Fd: AsFd>(fd: Fd) -> bool {
+    matches!(
+        tcgetattr(fd),
+        Err(Errno::IO) | Err(Errno::NODEV) | Err(Errno::NXIO)
+    )
+}
+
+/// A [`CharDevice`] which reopens itself when it's disconnected.
+///
+/// Reads and writes which fail because the device hung up or went away
+/// reconnect, and then are retried on the new device. Data the driver had
+/// buffered when the device went away is lost.
+pub struct ReconnectingCharDevice {
+    /// The current device, or `None` if the last reconnect failed.
+    device: Option<CharDevice>,
+    path: PathBuf,
+    options: ReconnectOptions,
+    termios: Option<Termios>,
+}
+
+impl ReconnectingCharDevice {
+    /// Open the device at `path`, to be reconnected according to `options`.
+    ///
+    /// The first open isn't retried; if it fails, the error is returned.
+    pub fn open<P: AsRef<Path>>(path: P, options: &ReconnectOptions) -> io::Result<Self> {
+        let path = path.as_ref().to_owned();
+        let device = options.open_once(&path, None)?;
+        Ok(Self {
+            device: Some(device),
+            path,
+            options: options.clone(),
+            termios: None,
+        })
+    }
+
+    /// Save the device's current terminal attributes, to be restored each
+    /// time it's reconnected.
+    pub fn save_termios(&mut self) -> io::Result<()> {
+        self.termios = Some(tcgetattr(self.device()?)?);
+        Ok(())
+    }
+
+    /// Close the current device, and reopen it.
+    pub fn reconnect(&mut self) -> io::Result<()> {
+        self.device = None;
+        self.device = Some(self.options.reconnect(&self.path, self.termios.as_ref())?);
+        Ok(())
+    }
+
+    /// Return the current device, reconnecting first if the last reconnect
+    /// failed.
+    fn device(&mut self) -> io::Result<&mut CharDevice> {
+        if self.device.is_none() {
+            self.reconnect()?;
+        }
+        Ok(self.device.as_mut().unwrap())
+    }
+
+    /// Return the path the device is opened from.
+    #[inline]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Return a reference to the current device, or `None` if the last
+    /// reconnect failed.
+    #[inline]
+    pub fn get_ref(&self) -> Option<&CharDevice> {
+        self.device.as_ref()
+    }
+}
+
+impl Read for ReconnectingCharDevice {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let device = self.device()?;
+            match device.read(buf) {
+                Ok(0) if !buf.is_empty() && is_hung_up(&*device) => {}
+                Err(e) if is_disconnect(&e) => {}
+                result => return result,
+            }
+            self.reconnect()?;
+        }
+    }
+}
+
+impl Write for ReconnectingCharDevice {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        loop {
+            match self.device()?.write(buf) {
+                Err(e) if is_disconnect(&e) => {}
+                result => return result,
+            }
+            self.reconnect()?;
+        }
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.device {
+            Some(device) => device.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Debug for ReconnectingCharDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReconnectingCharDevice")
+            .field("device", &self.device)
+            .field("path", &self.path)
+            .field("options", &self.options)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A [`TokioCharDevice`] which reopens itself when it's disconnected.
+///
+/// This behaves like [`ReconnectingCharDevice`]. Reconnecting happens in a
+/// task on the current runtime, which opens the device on a blocking thread
+/// for each attempt and waits between attempts with tokio's timers, so the
+/// runtime needs its time driver enabled. Dropping the device stops the
+/// task. For devices which don't support polling,
+/// tokio buffers writes, so data written shortly before the device went away
+/// may be lost.
+#[cfg(feature = "tokio")]
+pub struct TokioReconnectingCharDevice {
+    /// The current device, or `None` while reconnecting or if the last
+    /// reconnect failed.
+    device: Option<TokioCharDevice>,
+    reconnecting: Option<JoinHandle<io::Result<CharDevice>>>,
+    path: PathBuf,
+    options: ReconnectOptions,
+    termios: Option<Termios>,
+}
+
+#[cfg(feature = "tokio")]
+impl TokioReconnectingCharDevice {
+    /// Open the device at `path`, to be reconnected according to `options`.
+    ///
+    /// The first open isn't retried; if it fails, the error is returned.
+    pub async fn open<P: AsRef<Path>>(path: P, options: &ReconnectOptions) -> io::Result<Self> {
+        let path = path.as_ref().to_owned();
+        let options = options.clone();
+        let device = options.open_blocking(&path, None).await?;
+        Ok(Self {
+            device: Some(tokio_device(device)),
+            reconnecting: None,
+            path,
+            options,
+            termios: None,
+        })
+    }
+
+    /// Save the device's current terminal attributes, to be restored each
+    /// time it's reconnected.
+    ///
+    /// This fails if the device is being reconnected.
+    pub fn save_termios(&mut self) -> io::Result<()> {
+        let device = self.device.as_ref().ok_or(io::ErrorKind::NotConnected)?;
+        self.termios = Some(tcgetattr(device)?);
+        Ok(())
+    }
+
+    /// Close the current device, and reopen it.
+    pub async fn reconnect(&mut self) -> io::Result<()> {
+        self.start_reconnect();
+        std::future::poll_fn(|cx| self.poll_device(cx).map_ok(|_| ())).await
+    }
+
+    /// Return the path the device is opened from.
+    #[inline]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Return a reference to the current device, or `None` while it's being
+    /// reconnected or if the last reconnect failed.
+    #[inline]
+    pub fn get_ref(&self) -> Option<&TokioCharDevice> {
+        self.device.as_ref()
+    }
+
+    fn start_reconnect(&mut self) {
+        if self.reconnecting.is_none() {
+            // Close the old device first, so that it doesn't hold on to an
+            // exclusive lock the new one needs.
+            self.device = None;
+            let path = self.path.clone();
+            let options = self.options.clone();
+            let termios = self.termios.clone();
+            self.reconnecting = Some(tokio::spawn(options.reconnect_async(path, termios)));
+        }
+    }
+
+    /// Wait for a reconnect, if one is in progress, or start one if the
+    /// last one failed, and return the current device.
+    fn poll_device(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<&mut TokioCharDevice>> {
+        if self.device.is_none() {
+            self.start_reconnect();
+        }
+        if let Some(reconnecting) = &mut self.reconnecting {
+            let result = ready!(Pin::new(reconnecting).poll(cx));
+            self.reconnecting = None;
+            self.device = Some(tokio_device(result.map_err(io::Error::other)??));
+        }
+        Poll::Ready(Ok(self.device.as_mut().unwrap()))
+    }
+}
+
+#[cfg(feature = "tokio")]
+fn tokio_device(device: CharDevice) -> TokioCharDevice {
+    TokioCharDevice::from_char_device(device)
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncRead for TokioReconnectingCharDevice {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            let device = ready!(this.poll_device(cx))?;
+            let filled = buf.filled().len();
+            match ready!(Pin::new(&mut *device).poll_read(cx, buf)) {
+                Ok(())
+                    if buf.filled().len() == filled
+                        && buf.remaining() != 0
+                        && is_hung_up(&*device) => {}
+                Err(e) if is_disconnect(&e) => {}
+                result => return Poll::Ready(result),
+            }
+            this.start_reconnect();
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncWrite for TokioReconnectingCharDevice {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        loop {
+            let device = ready!(this.poll_device(cx))?;
+            match ready!(Pin::new(device).poll_write(cx, buf)) {
+                Err(e) if is_disconnect(&e) => {}
+                result => return Poll::Ready(result),
+            }
+            this.start_reconnect();
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            let device = ready!(this.poll_device(cx))?;
+            match ready!(Pin::new(device).poll_flush(cx)) {
+                Err(e) if is_disconnect(&e) => {}
+                result => return Poll::Ready(result),
+            }
+            this.start_reconnect();
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let device = ready!(this.poll_device(cx))?;
+        Pin::new(device).poll_shutdown(cx)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Drop for TokioReconnectingCharDevice {
+    fn drop(&mut self) {
+        if let Some(reconnecting) = &self.reconnecting {
+            reconnecting.abort();
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl fmt::Debug for TokioReconnectingCharDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokioReconnectingCharDevice")
+            .field("device", &self.device)
+            .field("path", &self.path)
+            .field("options", &self.options)
+            .finish_non_exhaustive()
+    }
+}
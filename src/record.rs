@@ -0,0 +1,335 @@
+//! Reading devices which return one record per `read`.
+//!
+//! Many devices, such as "/dev/kmsg", evdev, hidraw, uhid, and tun, return
+//! exactly one record from each `read`, and discard or reject whatever
+//! doesn't fit in the buffer. Reading them as byte streams, as [`Read`]
+//! encourages, can silently corrupt records. [`RecordReader`] reads one whole
+//! record at a time into a reusable buffer, and reports records which didn't
+//! fit as errors, which can be recognized with [`is_truncated`].
+//!
+//! ```no_run
+//! use char_device::record::{Record, RecordReader};
+//! use char_device::CharDevice;
+//! use std::io;
+//!
+//! struct Message(String);
+//!
+//! impl Record for Message {
+//!     const MAX_SIZE: usize = 8192;
+//!
+//!     fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+//!         Ok(Self(String::from_utf8_lossy(bytes).into_owned()))
+//!     }
+//! }
+//!
+//! # fn main() -> io::Result<()> {
+//! let kmsg = RecordReader::<Message>::new(CharDevice::open("/dev/kmsg")?);
+//! for message in kmsg {
+//!     print!("{}", message?.0);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::CharDevice;
+use std::fmt;
+use std::io::{self, Read};
+use std::marker::PhantomData;
+#[cfg(any(feature = "async-std", feature = "tokio"))]
+use std::{
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+/// A record which a device returns from a single `read`.
+pub trait Record: Sized {
+    /// The size of the largest record the device returns, in bytes.
+    const MAX_SIZE: usize;
+
+    /// The size of the smallest complete record, in bytes. Reads which
+    /// return fewer bytes are reported as truncated.
+    const MIN_SIZE: usize = 1;
+
+    /// Decode a record from the bytes returned by one `read`.
+    fn from_bytes(bytes: &[u8]) -> io::Result<Self>;
+}
+
+/// Fixed-size records are returned as arrays of bytes.
+impl<const N: usize> Record for [u8; N] {
+    const MAX_SIZE: usize = N;
+    const MIN_SIZE: usize = N;
+
+    #[inline]
+    fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        bytes.try_into().map_err(|_| truncated())
+    }
+}
+
+/// Return whether `error` reports a record which didn't fit in the buffer,
+/// or which was shorter than [`Record::MIN_SIZE`].
+pub fn is_truncated(error: &io::Error) -> bool {
+    error.get_ref().is_some_and(|inner| inner.is::<Truncated>())
+}
+
+/// The error payload for truncated records.
+#[derive(Debug)]
+struct Truncated;
+
+impl fmt::Display for Truncated {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("record truncated")
+    }
+}
+
+impl std::error::Error for Truncated {}
+
+fn truncated() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, Truncated)
+}
+
+/// Allocate a buffer for records of type `T`.
+///
+/// The buffer has room for one more byte than the largest record, so that a
+/// device which returns more than that, or fills the whole buffer, can be
+/// detected.
+fn buffer<T: Record>() -> Vec<u8> {
+    vec![0; T::MAX_SIZE + 1]
+}
+
+/// Check the result of reading one record of type `T`. Returns the length of
+/// the record, with `0` meaning end of file.
+fn check<T: Record>(result: io::Result<usize>) -> io::Result<usize> {
+    match result {
+        Ok(0) => Ok(0),
+        Ok(n) if n < T::MIN_SIZE || n > T::MAX_SIZE => Err(truncated()),
+        Ok(n) => Ok(n),
+        // Devices such as "/dev/kmsg" fail with `EINVAL` when a record
+        // doesn't fit.
+        Err(e) if e.kind() == io::ErrorKind::InvalidInput => Err(truncated()),
+        Err(e) => Err(e),
+    }
+}
+
+/// A reader which reads one whole record of type `T` at a time from a
+/// [`CharDevice`].
+pub struct RecordReader<T> {
+    device: CharDevice,
+    buf: Vec<u8>,
+    _record: PhantomData<fn() -> T>,
+}
+
+impl<T: Record> RecordReader<T> {
+    /// Construct a new `RecordReader` reading from `device`.
+    #[inline]
+    pub fn new(device: CharDevice) -> Self {
+        Self {
+            device,
+            buf: buffer::<T>(),
+            _record: PhantomData,
+        }
+    }
+
+    /// Read the bytes of the next record, or `None` at end of file.
+    ///
+    /// The bytes are only valid until the next read.
+    pub fn read_bytes(&mut self) -> io::Result<Option<&[u8]>> {
+        loop {
+            match check::<T>(self.device.read(&mut self.buf)) {
+                Ok(0) => return Ok(None),
+                Ok(n) => return Ok(Some(&self.buf[..n])),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Read the next record, or `None` at end of file.
+    pub fn read_record(&mut self) -> io::Result<Option<T>> {
+        self.read_bytes()?.map(T::from_bytes).transpose()
+    }
+
+    /// Return a reference to the underlying device.
+    #[inline]
+    pub fn get_ref(&self) -> &CharDevice {
+        &self.device
+    }
+
+    /// Consume the reader, returning the underlying device.
+    #[inline]
+    pub fn into_inner(self) -> CharDevice {
+        self.device
+    }
+}
+
+impl<T: Record> Iterator for RecordReader<T> {
+    type Item = io::Result<T>;
+
+    /// Read the next record. Truncated records are reported as errors, and
+    /// reading can continue after them.
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+impl<T> fmt::Debug for RecordReader<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RecordReader")
+            .field("device", &self.device)
+            .finish()
+    }
+}
+
+/// A [`Stream`] of records of type `T` read from a [`TokioCharDevice`].
+///
+/// [`Stream`]: futures_core::Stream
+/// [`TokioCharDevice`]: crate::TokioCharDevice
+#[cfg(feature = "tokio")]
+pub struct TokioRecordStream<T> {
+    device: crate::TokioCharDevice,
+    buf: Vec<u8>,
+    _record: PhantomData<fn() -> T>,
+}
+
+#[cfg(feature = "tokio")]
+impl<T: Record> TokioRecordStream<T> {
+    /// Construct a new `TokioRecordStream` reading from `device`.
+    #[inline]
+    pub fn new(device: crate::TokioCharDevice) -> Self {
+        Self {
+            device,
+            buf: buffer::<T>(),
+            _record: PhantomData,
+        }
+    }
+
+    /// Read the next record, or `None` at end of file.
+    pub async fn read_record(&mut self) -> io::Result<Option<T>> {
+        std::future::poll_fn(|cx| self.poll_record(cx))
+            .await
+            .transpose()
+    }
+
+    /// Return a reference to the underlying device.
+    #[inline]
+    pub fn get_ref(&self) -> &crate::TokioCharDevice {
+        &self.device
+    }
+
+    /// Consume the stream, returning the underlying device.
+    #[inline]
+    pub fn into_inner(self) -> crate::TokioCharDevice {
+        self.device
+    }
+
+    fn poll_record(&mut self, cx: &mut Context<'_>) -> Poll<Option<io::Result<T>>> {
+        use tokio::io::{AsyncRead, ReadBuf};
+
+        loop {
+            let mut buf = ReadBuf::new(&mut self.buf);
+            let result = ready!(Pin::new(&mut self.device).poll_read(cx, &mut buf));
+            let n = buf.filled().len();
+            return Poll::Ready(match check::<T>(result.map(|()| n)) {
+                Ok(0) => None,
+                Ok(n) => Some(T::from_bytes(&self.buf[..n])),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => Some(Err(e)),
+            });
+        }
+    }
+}
+
+#[cfg(all(feature = "tokio", feature = "futures-core"))]
+impl<T: Record> futures_core::Stream for TokioRecordStream<T> {
+    type Item = io::Result<T>;
+
+    #[inline]
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_record(cx)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<T> fmt::Debug for TokioRecordStream<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokioRecordStream")
+            .field("device", &self.device)
+            .finish()
+    }
+}
+
+/// A [`Stream`] of records of type `T` read from an [`AsyncStdCharDevice`].
+///
+/// [`Stream`]: async_std::stream::Stream
+/// [`AsyncStdCharDevice`]: crate::AsyncStdCharDevice
+#[cfg(feature = "async-std")]
+pub struct AsyncStdRecordStream<T> {
+    device: crate::AsyncStdCharDevice,
+    buf: Vec<u8>,
+    _record: PhantomData<fn() -> T>,
+}
+
+#[cfg(feature = "async-std")]
+impl<T: Record> AsyncStdRecordStream<T> {
+    /// Construct a new `AsyncStdRecordStream` reading from `device`.
+    #[inline]
+    pub fn new(device: crate::AsyncStdCharDevice) -> Self {
+        Self {
+            device,
+            buf: buffer::<T>(),
+            _record: PhantomData,
+        }
+    }
+
+    /// Read the next record, or `None` at end of file.
+    pub async fn read_record(&mut self) -> io::Result<Option<T>> {
+        std::future::poll_fn(|cx| self.poll_record(cx))
+            .await
+            .transpose()
+    }
+
+    /// Return a reference to the underlying device.
+    #[inline]
+    pub fn get_ref(&self) -> &crate::AsyncStdCharDevice {
+        &self.device
+    }
+
+    /// Consume the stream, returning the underlying device.
+    #[inline]
+    pub fn into_inner(self) -> crate::AsyncStdCharDevice {
+        self.device
+    }
+
+    fn poll_record(&mut self, cx: &mut Context<'_>) -> Poll<Option<io::Result<T>>> {
+        use async_std::io::Read as _;
+
+        loop {
+            let result = ready!(Pin::new(&mut self.device).poll_read(cx, &mut self.buf));
+            return Poll::Ready(match check::<T>(result) {
+                Ok(0) => None,
+                Ok(n) => Some(T::from_bytes(&self.buf[..n])),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => Some(Err(e)),
+            });
+        }
+    }
+}
+
+#[cfg(feature = "async-std")]
+impl<T: Record> async_std::stream::Stream for AsyncStdRecordStream<T> {
+    type Item = io::Result<T>;
+
+    #[inline]
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_record(cx)
+    }
+}
+
+#[cfg(feature = "async-std")]
+impl<T> fmt::Debug for AsyncStdRecordStream<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AsyncStdRecordStream")
+            .field("device", &self.device)
+            .finish()
+    }
+}
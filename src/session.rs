@@ -0,0 +1,737 @@
+//! Recording and replaying device traffic.
+//!
+//! A [`Recorder`] wraps a device and logs every read and write to a file,
+//! with timestamps. A [`Replayer`] plays such a file back: reads return what
+//! the device returned, and writes are checked against what was written. This
+//! lets protocol tests be recorded once against real hardware, and then run
+//! anywhere.
+//!
+//! ```no_run
+//! use char_device::session::{Recorder, Replayer};
+//! use char_device::CharDevice;
+//! use std::io::{Read, Write};
+//!
+//! fn query<D: Read + Write>(device: &mut D) -> std::io::Result<String> {
+//!     device.write_all(b"*IDN?\n")?;
+//!     let mut buf = [0; 64];
+//!     let n = device.read(&mut buf)?;
+//!     Ok(String::from_utf8_lossy(&buf[..n]).into_owned())
+//! }
+//!
+//! # fn main() -> std::io::Result<()> {
+//! // Once, against the real device:
+//! let device = CharDevice::open("/dev/ttyUSB0")?;
+//! let mut recorder = Recorder::create(device, "idn.session")?;
+//! query(&mut recorder)?;
+//!
+//! // In CI:
+//! let mut replayer = Replayer::open("idn.session")?;
+//! query(&mut replayer)?;
+//! replayer.finish()?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! # File format
+//!
+//! A session file starts with the eight bytes `chardev1`, followed by one
+//! entry for each read and write. Each entry is a direction byte, `R` or `W`,
+//! the time since recording started in microseconds as a little-endian
+//! `u64`, the length of the data as a little-endian `u32`, and then the data.
+//! A read which returned end of file is recorded as a read with no data. A
+//! read or write which failed has the direction byte `r` or `w`, and its
+//! data is the OS error code as a little-endian `i32`, or 0 if the error
+//! didn't come from the OS.
+
+use crate::expect::{invalid_data, Expectations};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+#[cfg(any(feature = "async-std", feature = "tokio"))]
+use std::{
+    pin::Pin,
+    sync::{mpsc, Arc, Condvar, Mutex},
+    task::{ready, Context, Poll, Waker},
+};
+
+/// The first bytes of a session file.
+const MAGIC: &[u8; 8] = b"chardev1";
+
+/// The direction of a recorded transfer.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// Data read from the device.
+    Read,
+    /// Data written to the device.
+    Write,
+}
+
+/// One read or write in a recorded session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// Whether this was a read or a write.
+    pub direction: Direction,
+    /// The time since recording started.
+    pub time: Duration,
+    /// The bytes read or written.
+    pub data: Vec<u8>,
+    /// If the read or write failed, the OS error code it failed with, or 0
+    /// if the error didn't come from the OS. `data` is empty in that case.
+    pub error: Option<i32>,
+}
+
+impl Entry {
+    /// Return the error this entry records, if any.
+    fn to_error(&self) -> Option<io::Error> {
+        match self.error? {
+            0 => Some(io::Error::other("recorded error")),
+            errno => Some(io::Error::from_raw_os_error(errno)),
+        }
+    }
+}
+
+/// Read all of the entries from a session file.
+pub fn read_session<R: Read>(mut log: R) -> io::Result<Vec<Entry>> {
+    let mut magic = [0; 8];
+    log.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid_data("not a session file"));
+    }
+
+    let mut entries = Vec::new();
+    let mut direction = [0; 1];
+    loop {
+        match log.read_exact(&mut direction) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(entries),
+            Err(e) => return Err(e),
+        }
+        let (direction, failed) = match &direction {
+            b"R" => (Direction::Read, false),
+            b"W" => (Direction::Write, false),
+            b"r" => (Direction::Read, true),
+            b"w" => (Direction::Write, true),
+            _ => return Err(invalid_data("invalid session entry")),
+        };
+        let mut time = [0; 8];
+        log.read_exact(&mut time)?;
+        let mut len = [0; 4];
+        log.read_exact(&mut len)?;
+        let mut data = vec![0; u32::from_le_bytes(len) as usize];
+        log.read_exact(&mut data)?;
+        let error = if failed {
+            let errno = <[u8; 4]>::try_from(&data[..])
+                .map_err(|_| invalid_data("invalid session error entry"))?;
+            data.clear();
+            Some(i32::from_le_bytes(errno))
+        } else {
+            None
+        };
+        entries.push(Entry {
+            direction,
+            time: Duration::from_micros(u64::from_le_bytes(time)),
+            data,
+            error,
+        });
+    }
+}
+
+/// A wrapper around a device which logs every read and write to a session
+/// file, for replaying with a [`Replayer`].
+///
+/// This works with [`CharDevice`] and anything else which implements
+/// [`Read`] and [`Write`], and with the async wrappers. So that async reads
+/// and writes don't block on the log, the first one hands the log to a
+/// thread which writes it from then on.
+///
+/// An error writing the log is reported by the next read, write, or flush.
+/// The read or write being logged has already taken effect on the device,
+/// so its result is returned as it is.
+///
+/// [`CharDevice`]: crate::CharDevice
+#[derive(Debug)]
+pub struct Recorder<D, W = File> {
+    device: D,
+    /// The log, until it's handed to `thread`.
+    log: Option<W>,
+    #[cfg(any(feature = "async-std", feature = "tokio"))]
+    thread: Option<LogThread<W>>,
+    start: Instant,
+    /// An error writing the log, which hasn't been reported yet.
+    error: Option<io::Error>,
+}
+
+/// A thread which writes a recorder's log.
+#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[derive(Debug)]
+struct LogThread<W> {
+    sender: mpsc::Sender<LogRequest>,
+    state: Arc<(Mutex<LogState>, Condvar)>,
+    thread: std::thread::JoinHandle<W>,
+    /// The number of flushes requested, and whether the last one is still
+    /// being waited for.
+    flushes: u64,
+    flushing: bool,
+}
+
+#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[derive(Debug)]
+enum LogRequest {
+    Write(Vec<u8>),
+    Flush,
+}
+
+#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[derive(Debug, Default)]
+struct LogState {
+    /// The number of flushes completed.
+    flushed: u64,
+    /// An error writing the log, which hasn't been reported yet.
+    error: Option<io::Error>,
+    /// The task waiting for a flush.
+    waker: Option<Waker>,
+}
+
+#[cfg(any(feature = "async-std", feature = "tokio"))]
+impl<W: Write + Send + 'static> LogThread<W> {
+    fn spawn(mut log: W) -> io::Result<Self> {
+        let (sender, receiver) = mpsc::channel();
+        let state = Arc::new((Mutex::new(LogState::default()), Condvar::new()));
+        let thread_state = Arc::clone(&state);
+        let thread = std::thread::Builder::new()
+            .name("char-device recorder".to_owned())
+            .spawn(move || {
+                // This ends once the recorder is dropped or consumed.
+                for request in receiver {
+                    let (result, flush) = match request {
+                        LogRequest::Write(entry) => (log.write_all(&entry), false),
+                        LogRequest::Flush => (log.flush(), true),
+                    };
+                    let (state, flushed) = &*thread_state;
+                    let mut state = state.lock().unwrap();
+                    if let Err(e) = result {
+                        state.error.get_or_insert(e);
+                    }
+                    if flush {
+                        state.flushed += 1;
+                        flushed.notify_all();
+                        if let Some(waker) = state.waker.take() {
+                            waker.wake();
+                        }
+                    }
+                }
+                log
+            })?;
+        Ok(Self {
+            sender,
+            state,
+            thread,
+            flushes: 0,
+            flushing: false,
+        })
+    }
+}
+
+#[cfg(any(feature = "async-std", feature = "tokio"))]
+impl<W> LogThread<W> {
+    fn write(&mut self, entry: Vec<u8>) -> io::Result<()> {
+        if let Some(error) = self.state.0.lock().unwrap().error.take() {
+            return Err(error);
+        }
+        // The thread only exits once the sender is dropped.
+        self.sender.send(LogRequest::Write(entry)).unwrap();
+        Ok(())
+    }
+
+    fn start_flush(&mut self) {
+        if !self.flushing {
+            self.flushing = true;
+            self.flushes += 1;
+            self.sender.send(LogRequest::Flush).unwrap();
+        }
+    }
+
+    fn finish_flush(&mut self, state: &mut LogState) -> io::Result<()> {
+        self.flushing = false;
+        state.error.take().map_or(Ok(()), Err)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.start_flush();
+        let (state, flushed) = &*Arc::clone(&self.state);
+        let mut state = state.lock().unwrap();
+        while state.flushed < self.flushes {
+            state = flushed.wait(state).unwrap();
+        }
+        self.finish_flush(&mut state)
+    }
+
+    fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.start_flush();
+        let state = Arc::clone(&self.state);
+        let mut state = state.0.lock().unwrap();
+        if state.flushed < self.flushes {
+            state.waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        Poll::Ready(self.finish_flush(&mut state))
+    }
+
+    /// Wait for the thread to write everything, and return the log.
+    fn join(self) -> W {
+        drop(self.sender);
+        self.thread
+            .join()
+            .unwrap_or_else(|e| std::panic::resume_unwind(e))
+    }
+}
+
+impl<D> Recorder<D> {
+    /// Record traffic on `device` into a new file at `path`.
+    pub fn create<P: AsRef<Path>>(device: D, path: P) -> io::Result<Self> {
+        Self::new(device, File::create(path)?)
+    }
+}
+
+impl<D, W: Write> Recorder<D, W> {
+    /// Record traffic on `device` into `log`.
+    pub fn new(device: D, mut log: W) -> io::Result<Self> {
+        log.write_all(MAGIC)?;
+        Ok(Self {
+            device,
+            log: Some(log),
+            #[cfg(any(feature = "async-std", feature = "tokio"))]
+            thread: None,
+            start: Instant::now(),
+            error: None,
+        })
+    }
+
+    /// Return a reference to the underlying device.
+    #[inline]
+    pub fn get_ref(&self) -> &D {
+        &self.device
+    }
+
+    /// Return a mutable reference to the underlying device. Traffic through
+    /// this reference isn't recorded.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut D {
+        &mut self.device
+    }
+
+    /// Consume the recorder, returning the device and the log.
+    ///
+    /// If the log was handed to a thread, this waits for the thread to
+    /// finish writing it.
+    pub fn into_inner(self) -> (D, W) {
+        #[cfg(any(feature = "async-std", feature = "tokio"))]
+        if let Some(thread) = self.thread {
+            return (self.device, thread.join());
+        }
+        (self.device, self.log.unwrap())
+    }
+
+    /// Encode an entry for the outcome of a read or write.
+    fn entry(
+        &self,
+        direction: Direction,
+        result: Result<&[u8], &io::Error>,
+    ) -> io::Result<Vec<u8>> {
+        let errno;
+        let (tag, data) = match (direction, result) {
+            (Direction::Read, Ok(data)) => (b'R', data),
+            (Direction::Write, Ok(data)) => (b'W', data),
+            (direction, Err(error)) => {
+                errno = error.raw_os_error().unwrap_or(0).to_le_bytes();
+                let tag = match direction {
+                    Direction::Read => b'r',
+                    Direction::Write => b'w',
+                };
+                (tag, &errno[..])
+            }
+        };
+        let len = u32::try_from(data.len()).map_err(|_| invalid_data("transfer too large"))?;
+        let time = self.start.elapsed().as_micros() as u64;
+
+        let mut entry = Vec::with_capacity(13 + data.len());
+        entry.push(tag);
+        entry.extend_from_slice(&time.to_le_bytes());
+        entry.extend_from_slice(&len.to_le_bytes());
+        entry.extend_from_slice(data);
+        Ok(entry)
+    }
+
+    /// Return the error writing the log, if there is one which hasn't been
+    /// reported yet.
+    fn take_error(&mut self) -> io::Result<()> {
+        self.error.take().map_or(Ok(()), Err)
+    }
+
+    /// Keep the error from writing the log, if any, to report later.
+    fn defer(&mut self, logged: io::Result<()>) {
+        if let Err(e) = logged {
+            self.error.get_or_insert(e);
+        }
+    }
+
+    /// Log the outcome of a sync read or write.
+    fn log(&mut self, direction: Direction, result: Result<&[u8], &io::Error>) -> io::Result<()> {
+        let entry = self.entry(direction, result)?;
+        #[cfg(any(feature = "async-std", feature = "tokio"))]
+        if let Some(thread) = &mut self.thread {
+            return thread.write(entry);
+        }
+        self.log.as_mut().unwrap().write_all(&entry)
+    }
+
+    /// Log the outcome of an async read or write, handing the log to a thread
+    /// if that hasn't been done yet.
+    #[cfg(any(feature = "async-std", feature = "tokio"))]
+    fn log_async(
+        &mut self,
+        direction: Direction,
+        result: Result<&[u8], &io::Error>,
+    ) -> io::Result<()>
+    where
+        W: Send + 'static,
+    {
+        let entry = self.entry(direction, result)?;
+        if let Some(log) = self.log.take() {
+            self.thread = Some(LogThread::spawn(log)?);
+        }
+        self.thread.as_mut().unwrap().write(entry)
+    }
+
+    /// Flush the log after an async flush of the device.
+    #[cfg(any(feature = "async-std", feature = "tokio"))]
+    fn poll_flush_log(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match &mut self.thread {
+            Some(thread) => thread.poll_flush(cx),
+            None => Poll::Ready(self.log.as_mut().unwrap().flush()),
+        }
+    }
+}
+
+impl<D: Read, W: Write> Read for Recorder<D, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.take_error()?;
+        let result = self.device.read(buf);
+        let logged = self.log(Direction::Read, result.as_ref().map(|n| &buf[..*n]));
+        self.defer(logged);
+        result
+    }
+}
+
+impl<D: Write, W: Write> Write for Recorder<D, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.take_error()?;
+        let result = self.device.write(buf);
+        let logged = self.log(Direction::Write, result.as_ref().map(|n| &buf[..*n]));
+        self.defer(logged);
+        result
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.device.flush()?;
+        self.take_error()?;
+        #[cfg(any(feature = "async-std", feature = "tokio"))]
+        if let Some(thread) = &mut self.thread {
+            return thread.flush();
+        }
+        self.log.as_mut().unwrap().flush()
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<D, W> tokio::io::AsyncRead for Recorder<D, W>
+where
+    D: tokio::io::AsyncRead + Unpin,
+    W: Write + Send + Unpin + 'static,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        this.take_error()?;
+        let filled = buf.filled().len();
+        let result = ready!(Pin::new(&mut this.device).poll_read(cx, buf));
+        let data = result.as_ref().map(|()| &buf.filled()[filled..]);
+        let logged = this.log_async(Direction::Read, data);
+        this.defer(logged);
+        Poll::Ready(result)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<D, W> tokio::io::AsyncWrite for Recorder<D, W>
+where
+    D: tokio::io::AsyncWrite + Unpin,
+    W: Write + Send + Unpin + 'static,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        this.take_error()?;
+        let result = ready!(Pin::new(&mut this.device).poll_write(cx, buf));
+        let logged = this.log_async(Direction::Write, result.as_ref().map(|n| &buf[..*n]));
+        this.defer(logged);
+        Poll::Ready(result)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(Pin::new(&mut this.device).poll_flush(cx))?;
+        this.take_error()?;
+        this.poll_flush_log(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().device).poll_shutdown(cx)
+    }
+}
+
+#[cfg(feature = "async-std")]
+impl<D, W> async_std::io::Read for Recorder<D, W>
+where
+    D: async_std::io::Read + Unpin,
+    W: Write + Send + Unpin + 'static,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        this.take_error()?;
+        let result = ready!(Pin::new(&mut this.device).poll_read(cx, buf));
+        let logged = this.log_async(Direction::Read, result.as_ref().map(|n| &buf[..*n]));
+        this.defer(logged);
+        Poll::Ready(result)
+    }
+}
+
+#[cfg(feature = "async-std")]
+impl<D, W> async_std::io::Write for Recorder<D, W>
+where
+    D: async_std::io::Write + Unpin,
+    W: Write + Send + Unpin + 'static,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        this.take_error()?;
+        let result = ready!(Pin::new(&mut this.device).poll_write(cx, buf));
+        let logged = this.log_async(Direction::Write, result.as_ref().map(|n| &buf[..*n]));
+        this.defer(logged);
+        Poll::Ready(result)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(Pin::new(&mut this.device).poll_flush(cx))?;
+        this.take_error()?;
+        this.poll_flush_log(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().device).poll_close(cx)
+    }
+}
+
+/// A stand-in for a device which plays back a recorded session.
+///
+/// Each read returns the data of the next recorded read, and each write must
+/// match the next recorded write, though writes may be split up
+/// differently than they were when recording. Reads and writes which were
+/// recorded as failing fail with the same error. A write which doesn't match,
+/// or a read when the recording has a write next, fails, as does every
+/// operation after it. At the end of a test, call [`Replayer::finish`] to
+/// check that everything which was recorded as written has been.
+///
+/// Once the recording runs out, reads return end of file.
+#[derive(Debug)]
+pub struct Replayer {
+    entries: VecDeque<Entry>,
+    expectations: Expectations,
+}
+
+impl Replayer {
+    /// Play back the session in the file at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::new(io::BufReader::new(File::open(path)?))
+    }
+
+    /// Play back the session read from `log`.
+    pub fn new<R: Read>(log: R) -> io::Result<Self> {
+        Ok(Self::from_entries(read_session(log)?))
+    }
+
+    /// Play back the given entries.
+    pub fn from_entries(entries: Vec<Entry>) -> Self {
+        Self {
+            entries: entries.into(),
+            expectations: Expectations::default(),
+        }
+    }
+
+    /// Check that the session was followed, and that all of the recorded
+    /// writes have been made.
+    pub fn finish(self) -> io::Result<()> {
+        let missing = self
+            .entries
+            .iter()
+            .filter(|entry| entry.direction == Direction::Write)
+            .count();
+        self.expectations.finish(missing)
+    }
+
+    /// Consume `n` bytes of the current entry.
+    fn advance(&mut self, n: usize) {
+        if self.expectations.advance(n, &self.entries[0].data) {
+            self.entries.pop_front();
+        }
+    }
+}
+
+impl Read for Replayer {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.expectations.check()?;
+        let Some(entry) = self.entries.front() else {
+            return Ok(0);
+        };
+        if entry.direction == Direction::Write {
+            let message = match entry.error {
+                Some(_) => "read, but the session expects a failed write".to_owned(),
+                None => format!(
+                    "read, but the session expects a write of \"{}\"",
+                    self.expectations.remaining(&entry.data).escape_ascii()
+                ),
+            };
+            return Err(self.expectations.mismatch(message));
+        }
+        if let Some(error) = entry.to_error() {
+            self.entries.pop_front();
+            return Err(error);
+        }
+        if entry.data.is_empty() {
+            self.entries.pop_front();
+            return Ok(0);
+        }
+
+        let data = self.expectations.remaining(&entry.data);
+        let n = buf.len().min(data.len());
+        buf[..n].copy_from_slice(&data[..n]);
+        self.advance(n);
+        Ok(n)
+    }
+}
+
+impl Write for Replayer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.expectations.check()?;
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let expected = match self.entries.front() {
+            Some(entry) if entry.direction == Direction::Write => {
+                if let Some(error) = entry.to_error() {
+                    self.entries.pop_front();
+                    return Err(error);
+                }
+                Ok(&entry.data[..])
+            }
+            Some(_) => Err("the session expects a read"),
+            None => Err("the session has ended"),
+        };
+        let (n, complete) = self.expectations.write(buf, expected)?;
+        if complete {
+            self.entries.pop_front();
+        }
+        Ok(n)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl tokio::io::AsyncRead for Replayer {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let n = self.get_mut().read(buf.initialize_unfilled())?;
+        buf.advance(n);
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl tokio::io::AsyncWrite for Replayer {
+    #[inline]
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(self.get_mut().write(buf))
+    }
+
+    #[inline]
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    #[inline]
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "async-std")]
+impl async_std::io::Read for Replayer {
+    #[inline]
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(self.get_mut().read(buf))
+    }
+}
+
+#[cfg(feature = "async-std")]
+impl async_std::io::Write for Replayer {
+    #[inline]
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(self.get_mut().write(buf))
+    }
+
+    #[inline]
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    #[inline]
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
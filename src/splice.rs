@@ -0,0 +1,277 @@
+//! Zero-copy transfers between character devices and pipes or sockets.
+//!
+//! These use Linux's `splice`, going through a [`SplicePipe`], and fall back
+//! to plain `read` and `write` when a driver doesn't support `splice`.
+
+use crate::CharDevice;
+use io_extras::os::rustix::{AsRawFd, RawFd};
+use io_lifetimes::{AsFd, BorrowedFd, OwnedFd};
+use rustix::event::{poll, PollFd, PollFlags};
+use rustix::io::Errno;
+use rustix::pipe::{pipe_with, splice, PipeFlags, SpliceFlags};
+use std::io;
+
+/// The size of the buffer used when falling back to `read` and `write`.
+const FALLBACK_BUF_SIZE: usize = 64 * 1024;
+
+impl CharDevice {
+    /// Move up to `len` bytes from this device to `out` through `pipe`,
+    /// without copying them through userspace, returning the number of bytes
+    /// written to `out`.
+    ///
+    /// See [`SplicePipe::transfer`] for details.
+    #[inline]
+    pub fn splice_to<Fd: AsFd>(
+        &self,
+        out: &Fd,
+        len: usize,
+        pipe: &mut SplicePipe,
+    ) -> io::Result<usize> {
+        pipe.transfer(self, out, len)
+    }
+
+    /// Move up to `len` bytes from `input` to this device through `pipe`,
+    /// without copying them through userspace, returning the number of bytes
+    /// written to this device.
+    ///
+    /// See [`SplicePipe::transfer`] for details.
+    #[inline]
+    pub fn splice_from<Fd: AsFd>(
+        &self,
+        input: &Fd,
+        len: usize,
+        pipe: &mut SplicePipe,
+    ) -> io::Result<usize> {
+        pipe.transfer(input, self, len)
+    }
+}
+
+/// Copy data in both directions between `device` and `other` until either
+/// direction reaches end-of-file or fails, returning the number of bytes
+/// copied from `device` to `other` and from `other` to `device`,
+/// respectively.
+///
+/// Each direction runs on its own thread and uses `splice` where possible.
+/// When one direction finishes, the other is stopped, rather than being left
+/// blocked on a device which may never produce more data. If `other` is a
+/// socket, its write side is shut down once the copy from `device` finishes.
+pub fn copy_bidirectional<Fd: AsFd>(device: &CharDevice, other: &Fd) -> io::Result<(u64, u64)> {
+    let other = other.as_fd();
+    // Once either direction finishes, it writes to this pipe, which wakes up
+    // the other.
+    let (stop_reader, stop_writer) = pipe_with(PipeFlags::CLOEXEC)?;
+    let stop = || {
+        let _ = rustix::io::write(&stop_writer, &[0]);
+    };
+    std::thread::scope(|scope| {
+        let from_device = scope.spawn(|| -> io::Result<u64> {
+            let result = copy(device.as_fd(), other, stop_reader.as_fd());
+            stop();
+            let n = result?;
+            match rustix::net::shutdown(other, rustix::net::Shutdown::Write) {
+                Ok(()) | Err(Errno::NOTSOCK) | Err(Errno::NOTCONN) => {}
+                Err(e) => return Err(e.into()),
+            }
+            Ok(n)
+        });
+        let to_device = copy(other, device.as_fd(), stop_reader.as_fd());
+        stop();
+        let from_device = from_device
+            .join()
+            .unwrap_or_else(|e| std::panic::resume_unwind(e));
+        Ok((from_device?, to_device?))
+    })
+}
+
+/// Copy everything from `input` to `out` until end-of-file, or until `stop`
+/// becomes readable.
+fn copy(input: BorrowedFd<'_>, out: BorrowedFd<'_>, stop: BorrowedFd<'_>) -> io::Result<u64> {
+    let mut pipe = SplicePipe::new()?;
+    let mut total = 0;
+    loop {
+        // Wait until there's something to do, so that we don't block in
+        // `splice` or `read` after being asked to stop.
+        let filling = pipe.buffered() == 0;
+        let (fd, flags) = if filling {
+            (input, PollFlags::IN)
+        } else {
+            (out, PollFlags::OUT)
+        };
+        let mut fds = [PollFd::new(&fd, flags), PollFd::new(&stop, PollFlags::IN)];
+        match poll(&mut fds, None) {
+            Ok(_) | Err(Errno::INTR) => {}
+            Err(e) => return Err(e.into()),
+        }
+        if !fds[1].revents().is_empty() {
+            return Ok(total);
+        }
+        if fds[0].revents().is_empty() {
+            continue;
+        }
+
+        let result = if filling {
+            pipe.fill(input, FALLBACK_BUF_SIZE)
+        } else {
+            pipe.drain(out)
+        };
+        match result {
+            Ok(0) if filling => return Ok(total),
+            Ok(n) if !filling => total += n as u64,
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// A pipe used to splice between two file descriptors.
+///
+/// `splice` needs a pipe on one side, so transfers between two things which
+/// aren't pipes go through this one. Creating a pipe takes two file
+/// descriptors, so reuse a `SplicePipe` for many transfers.
+///
+/// Data which was moved out of the input, but couldn't be written to the
+/// output, stays in the `SplicePipe`, and is written first by the next
+/// transfer.
+#[derive(Debug)]
+pub struct SplicePipe {
+    reader: OwnedFd,
+    writer: OwnedFd,
+    /// The number of bytes in the pipe.
+    in_pipe: usize,
+    /// Data read by the fallback, which is written before the data in the
+    /// pipe, in `buf[pos..]`.
+    buf: Vec<u8>,
+    pos: usize,
+    /// The most recent input and output whose drivers don't support
+    /// `splice`, so that we go straight to the fallback for them.
+    unsupported_input: Option<RawFd>,
+    unsupported_output: Option<RawFd>,
+}
+
+impl SplicePipe {
+    /// Create a new `SplicePipe`.
+    pub fn new() -> io::Result<Self> {
+        let (reader, writer) = pipe_with(PipeFlags::CLOEXEC)?;
+        Ok(Self {
+            reader,
+            writer,
+            in_pipe: 0,
+            buf: Vec::new(),
+            pos: 0,
+            unsupported_input: None,
+            unsupported_output: None,
+        })
+    }
+
+    /// Return the number of bytes which were moved out of an input, but not
+    /// yet written to an output.
+    #[inline]
+    pub fn buffered(&self) -> usize {
+        self.buf.len() - self.pos + self.in_pipe
+    }
+
+    /// Move up to `len` bytes from `input` to `out`, returning the number of
+    /// bytes written to `out`.
+    ///
+    /// `input` and `out` may be character devices, pipes, sockets, or
+    /// anything else `splice` can read from or write to. If a driver doesn't
+    /// support `splice`, this falls back to `read` and `write`.
+    ///
+    /// If data is left over from an earlier transfer, this writes it to `out`
+    /// instead of reading from `input`. Otherwise, a return value of 0
+    /// indicates end-of-file. If writing fails after reading, the data is
+    /// kept, and [`buffered`] says how much there is.
+    ///
+    /// [`buffered`]: Self::buffered
+    pub fn transfer<In: AsFd, Out: AsFd>(
+        &mut self,
+        input: &In,
+        out: &Out,
+        len: usize,
+    ) -> io::Result<usize> {
+        if self.buffered() == 0 && self.fill(input.as_fd(), len)? == 0 {
+            return Ok(0);
+        }
+        self.drain(out.as_fd())
+    }
+
+    /// Move up to `len` bytes from `input` into this pipe.
+    fn fill(&mut self, input: BorrowedFd<'_>, len: usize) -> io::Result<usize> {
+        if self.unsupported_input != Some(input.as_raw_fd()) {
+            match splice(input, None, &self.writer, None, len, SpliceFlags::MOVE) {
+                Ok(n) => {
+                    self.in_pipe = n;
+                    return Ok(n);
+                }
+                Err(Errno::INVAL) => self.unsupported_input = Some(input.as_raw_fd()),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        self.pos = 0;
+        read_into(&mut self.buf, input, len)
+    }
+
+    /// Write what this pipe holds to `out`, returning the number of bytes
+    /// written. If writing fails part way, this returns the number of bytes
+    /// written so far, and the rest is kept.
+    fn drain(&mut self, out: BorrowedFd<'_>) -> io::Result<usize> {
+        let mut written = 0;
+        let error = loop {
+            // Data in `buf` was read out of the pipe, or read instead of being
+            // spliced into it, so it comes first.
+            let result = if self.pos < self.buf.len() {
+                rustix::io::write(out, &self.buf[self.pos..]).inspect(|n| self.pos += n)
+            } else if self.in_pipe == 0 {
+                return Ok(written);
+            } else if self.unsupported_output != Some(out.as_raw_fd()) {
+                match splice(
+                    &self.reader,
+                    None,
+                    out,
+                    None,
+                    self.in_pipe,
+                    SpliceFlags::MOVE,
+                ) {
+                    Err(Errno::INVAL) => {
+                        self.unsupported_output = Some(out.as_raw_fd());
+                        continue;
+                    }
+                    result => result.inspect(|n| self.in_pipe -= n),
+                }
+            } else {
+                // Move the data out of the pipe, to be written with `write`.
+                self.pos = 0;
+                self.in_pipe -= read_into(&mut self.buf, self.reader.as_fd(), self.in_pipe)?;
+                continue;
+            };
+            match result {
+                Ok(0) => break io::ErrorKind::WriteZero.into(),
+                Ok(n) => written += n,
+                Err(Errno::INTR) => {}
+                Err(e) => break io::Error::from(e),
+            }
+        };
+        if written != 0 {
+            Ok(written)
+        } else {
+            Err(error)
+        }
+    }
+}
+
+/// Replace the contents of `buf` with up to `len` bytes read from `fd`.
+fn read_into(buf: &mut Vec<u8>, fd: BorrowedFd<'_>, len: usize) -> io::Result<usize> {
+    buf.clear();
+    buf.resize(len.min(FALLBACK_BUF_SIZE), 0);
+    match rustix::io::read(fd, &mut *buf) {
+        Ok(n) => {
+            buf.truncate(n);
+            Ok(n)
+        }
+        Err(e) => {
+            buf.clear();
+            Err(e.into())
+        }
+    }
+}
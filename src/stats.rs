@@ -0,0 +1,469 @@
+//! Per-device I/O statistics.
+//!
+//! A [`MeteredDevice`] wraps a device and counts what passes through it: the
+//! bytes and calls of reads and writes, errors by errno, the time spent
+//! waiting for reads, and the most bytes [`num_ready_bytes`] has reported
+//! ready at once. The counts are available as a [`Stats`] snapshot, and are
+//! also reported through the [`metrics`] facade, labeled with the device's
+//! name:
+//!
+//! | Metric                          | Kind      | Labels              |
+//! | ------------------------------- | --------- | ------------------- |
+//! | `char_device_bytes_read`        | counter   | `device`            |
+//! | `char_device_bytes_written`     | counter   | `device`            |
+//! | `char_device_reads`             | counter   | `device`            |
+//! | `char_device_writes`            | counter   | `device`            |
+//! | `char_device_errors`            | counter   | `device`, `errno`   |
+//! | `char_device_read_seconds`      | histogram | `device`            |
+//! | `char_device_max_ready_bytes`   | gauge     | `device`            |
+//!
+//! ```no_run
+//! use char_device::stats::MeteredDevice;
+//! use char_device::CharDevice;
+//! use std::io::Read;
+//!
+//! # fn main() -> std::io::Result<()> {
+//! let mut gps = MeteredDevice::new(CharDevice::open("/dev/ttyACM0")?, "gps");
+//! let mut buf = [0; 256];
+//! gps.read(&mut buf)?;
+//! println!("{} bytes read", gps.stats().bytes_read);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`num_ready_bytes`]: MeteredDevice::num_ready_bytes
+
+use std::collections::BTreeMap;
+use std::io::{self, IoSlice, IoSliceMut, Read, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+#[cfg(any(feature = "async-std", feature = "tokio"))]
+use {
+    atomic_waker::AtomicWaker,
+    std::{
+        pin::Pin,
+        sync::{atomic::AtomicBool, Arc},
+        task::{ready, Context, Poll, Wake, Waker},
+    },
+};
+
+/// A snapshot of a device's I/O statistics.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// The number of bytes read.
+    pub bytes_read: u64,
+    /// The number of bytes written.
+    pub bytes_written: u64,
+    /// The number of reads, including ones which failed.
+    pub reads: u64,
+    /// The number of writes, including ones which failed.
+    pub writes: u64,
+    /// The number of failed reads and writes, by errno. Errors which don't
+    /// come from the OS are counted under 0.
+    pub errors: BTreeMap<i32, u64>,
+    /// The total time spent waiting for reads to complete.
+    pub read_time: Duration,
+    /// The most bytes [`MeteredDevice::num_ready_bytes`] has reported.
+    pub max_ready_bytes: u64,
+}
+
+/// The metrics handles for a device.
+struct Handles {
+    bytes_read: metrics::Counter,
+    bytes_written: metrics::Counter,
+    reads: metrics::Counter,
+    writes: metrics::Counter,
+    read_seconds: metrics::Histogram,
+    max_ready_bytes: metrics::Gauge,
+}
+
+/// A waker which notes that the device woke the task, so that the next poll
+/// of a read can be told apart from the first poll of a new one.
+#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[derive(Default)]
+struct ReadWaker {
+    woken: AtomicBool,
+    waker: AtomicWaker,
+}
+
+#[cfg(any(feature = "async-std", feature = "tokio"))]
+impl ReadWaker {
+    /// Wake `waker` when the device is ready, and return a waker to pass to
+    /// the device.
+    fn register(self: &Arc<Self>, waker: &Waker) -> Waker {
+        self.waker.register(waker);
+        Waker::from(Arc::clone(self))
+    }
+}
+
+#[cfg(any(feature = "async-std", feature = "tokio"))]
+impl Wake for ReadWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.woken.store(true, Ordering::Relaxed);
+        self.waker.wake();
+    }
+}
+
+/// A wrapper around a device which keeps statistics about its I/O.
+///
+/// This works with [`CharDevice`], `TokioCharDevice`, `AsyncStdCharDevice`,
+/// and anything else implementing the same I/O traits. For the async
+/// wrappers, the time spent waiting for a read is measured from when it's
+/// first polled until it completes. A poll which doesn't follow a wakeup from
+/// the device is taken to start a new read, so that the wait of a read which
+/// was cancelled isn't counted.
+///
+/// The metrics handles are registered with the recorder installed when the
+/// wrapper is created.
+///
+/// [`CharDevice`]: crate::CharDevice
+pub struct MeteredDevice<D> {
+    device: D,
+    name: String,
+    stats: Stats,
+    /// Updated by [`MeteredDevice::num_ready_bytes`], which only needs `&self`.
+    max_ready_bytes: AtomicU64,
+    handles: Handles,
+    #[cfg(any(feature = "async-std", feature = "tokio"))]
+    read_start: Option<Instant>,
+    #[cfg(any(feature = "async-std", feature = "tokio"))]
+    read_waker: Arc<ReadWaker>,
+}
+
+impl<D> MeteredDevice<D> {
+    /// Wrap `device`, labeling its metrics with `name`.
+    pub fn new(device: D, name: impl Into<String>) -> Self {
+        let name = name.into();
+        let label = [("device", name.clone())];
+        let handles = Handles {
+            bytes_read: metrics::counter!("char_device_bytes_read", &label),
+            bytes_written: metrics::counter!("char_device_bytes_written", &label),
+            reads: metrics::counter!("char_device_reads", &label),
+            writes: metrics::counter!("char_device_writes", &label),
+            read_seconds: metrics::histogram!("char_device_read_seconds", &label),
+            max_ready_bytes: metrics::gauge!("char_device_max_ready_bytes", &label),
+        };
+        Self {
+            device,
+            name,
+            stats: Stats::default(),
+            max_ready_bytes: AtomicU64::new(0),
+            handles,
+            #[cfg(any(feature = "async-std", feature = "tokio"))]
+            read_start: None,
+            #[cfg(any(feature = "async-std", feature = "tokio"))]
+            read_waker: Arc::default(),
+        }
+    }
+
+    /// Return the name the device's metrics are labeled with.
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Return a snapshot of the device's statistics.
+    pub fn stats(&self) -> Stats {
+        Stats {
+            max_ready_bytes: self.max_ready_bytes.load(Ordering::Relaxed),
+            ..self.stats.clone()
+        }
+    }
+
+    /// Return a reference to the underlying device.
+    #[inline]
+    pub fn get_ref(&self) -> &D {
+        &self.device
+    }
+
+    /// Return a mutable reference to the underlying device. I/O through
+    /// this reference isn't counted.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut D {
+        &mut self.device
+    }
+
+    /// Consume the wrapper, returning the underlying device.
+    #[inline]
+    pub fn into_inner(self) -> D {
+        self.device
+    }
+
+    fn record_read(&mut self, result: &io::Result<usize>, duration: Duration) {
+        self.stats.reads += 1;
+        self.stats.read_time += duration;
+        self.handles.reads.increment(1);
+        self.handles.read_seconds.record(duration.as_secs_f64());
+        match result {
+            Ok(n) => {
+                self.stats.bytes_read += *n as u64;
+                self.handles.bytes_read.increment(*n as u64);
+            }
+            Err(err) => self.record_error(err),
+        }
+    }
+
+    fn record_write(&mut self, result: &io::Result<usize>) {
+        self.stats.writes += 1;
+        self.handles.writes.increment(1);
+        match result {
+            Ok(n) => {
+                self.stats.bytes_written += *n as u64;
+                self.handles.bytes_written.increment(*n as u64);
+            }
+            Err(err) => self.record_error(err),
+        }
+    }
+
+    fn record_error(&mut self, err: &io::Error) {
+        let errno = err.raw_os_error().unwrap_or(0);
+        *self.stats.errors.entry(errno).or_insert(0) += 1;
+        metrics::counter!(
+            "char_device_errors",
+            "device" => self.name.clone(),
+            "errno" => errno.to_string()
+        )
+        .increment(1);
+    }
+
+    fn record_ready(&self, result: io::Result<u64>) -> io::Result<u64> {
+        if let Ok(n) = result {
+            if n > self.max_ready_bytes.fetch_max(n, Ordering::Relaxed) {
+                self.handles.max_ready_bytes.set(n as f64);
+            }
+        }
+        result
+    }
+
+    /// Poll an async read with `poll`, and record it once it's complete.
+    #[cfg(any(feature = "async-std", feature = "tokio"))]
+    fn poll_read_with(
+        &mut self,
+        cx: &mut Context<'_>,
+        poll: impl FnOnce(Pin<&mut D>, &mut Context<'_>) -> Poll<io::Result<usize>>,
+    ) -> Poll<io::Result<usize>>
+    where
+        D: Unpin,
+    {
+        // If the device hasn't woken the task since the last poll returned
+        // `Pending`, that read was cancelled, and this is a new one.
+        let woken = self.read_waker.woken.swap(false, Ordering::Relaxed);
+        let start = match self.read_start.take() {
+            Some(start) if woken => start,
+            _ => Instant::now(),
+        };
+        let waker = self.read_waker.register(cx.waker());
+        match poll(Pin::new(&mut self.device), &mut Context::from_waker(&waker)) {
+            Poll::Ready(result) => {
+                self.record_read(&result, start.elapsed());
+                Poll::Ready(result)
+            }
+            Poll::Pending => {
+                self.read_start = Some(start);
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl MeteredDevice<crate::CharDevice> {
+    /// Return the number of bytes which are ready to be read immediately,
+    /// and record it.
+    #[inline]
+    pub fn num_ready_bytes(&self) -> io::Result<u64> {
+        self.record_ready(self.device.num_ready_bytes())
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl MeteredDevice<crate::TokioCharDevice> {
+    /// Return the number of bytes which are ready to be read immediately,
+    /// and record it.
+    #[inline]
+    pub fn num_ready_bytes(&self) -> io::Result<u64> {
+        self.record_ready(self.device.num_ready_bytes())
+    }
+}
+
+#[cfg(feature = "async-std")]
+impl MeteredDevice<crate::AsyncStdCharDevice> {
+    /// Return the number of bytes which are ready to be read immediately,
+    /// and record it.
+    #[inline]
+    pub fn num_ready_bytes(&self) -> io::Result<u64> {
+        self.record_ready(self.device.num_ready_bytes())
+    }
+}
+
+impl<D: std::fmt::Debug> std::fmt::Debug for MeteredDevice<D> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MeteredDevice")
+            .field("device", &self.device)
+            .field("name", &self.name)
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+impl<D: Read> Read for MeteredDevice<D> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let start = Instant::now();
+        let result = self.device.read(buf);
+        self.record_read(&result, start.elapsed());
+        result
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
+        let start = Instant::now();
+        let result = self.device.read_vectored(bufs);
+        self.record_read(&result, start.elapsed());
+        result
+    }
+
+    #[cfg(can_vector)]
+    #[inline]
+    fn is_read_vectored(&self) -> bool {
+        self.device.is_read_vectored()
+    }
+}
+
+impl<D: Write> Write for MeteredDevice<D> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let result = self.device.write(buf);
+        self.record_write(&result);
+        result
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        let result = self.device.write_vectored(bufs);
+        self.record_write(&result);
+        result
+    }
+
+    #[cfg(can_vector)]
+    #[inline]
+    fn is_write_vectored(&self) -> bool {
+        self.device.is_write_vectored()
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        self.device.flush()
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<D: tokio::io::AsyncRead + Unpin> tokio::io::AsyncRead for MeteredDevice<D> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let result = ready!(self.get_mut().poll_read_with(cx, |device, cx| {
+            let result = ready!(device.poll_read(cx, buf));
+            Poll::Ready(result.map(|()| buf.filled().len() - filled))
+        }));
+        Poll::Ready(result.map(drop))
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<D: tokio::io::AsyncWrite + Unpin> tokio::io::AsyncWrite for MeteredDevice<D> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let result = ready!(Pin::new(&mut this.device).poll_write(cx, buf));
+        this.record_write(&result);
+        Poll::Ready(result)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let result = ready!(Pin::new(&mut this.device).poll_write_vectored(cx, bufs));
+        this.record_write(&result);
+        Poll::Ready(result)
+    }
+
+    #[inline]
+    fn is_write_vectored(&self) -> bool {
+        self.device.is_write_vectored()
+    }
+
+    #[inline]
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().device).poll_flush(cx)
+    }
+
+    #[inline]
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().device).poll_shutdown(cx)
+    }
+}
+
+#[cfg(feature = "async-std")]
+impl<D: async_std::io::Read + Unpin> async_std::io::Read for MeteredDevice<D> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut()
+            .poll_read_with(cx, |device, cx| device.poll_read(cx, buf))
+    }
+
+    fn poll_read_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &mut [IoSliceMut<'_>],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut()
+            .poll_read_with(cx, |device, cx| device.poll_read_vectored(cx, bufs))
+    }
+}
+
+#[cfg(feature = "async-std")]
+impl<D: async_std::io::Write + Unpin> async_std::io::Write for MeteredDevice<D> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let result = ready!(Pin::new(&mut this.device).poll_write(cx, buf));
+        this.record_write(&result);
+        Poll::Ready(result)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let result = ready!(Pin::new(&mut this.device).poll_write_vectored(cx, bufs));
+        this.record_write(&result);
+        Poll::Ready(result)
+    }
+
+    #[inline]
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().device).poll_flush(cx)
+    }
+
+    #[inline]
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().device).poll_close(cx)
+    }
+}
@@ -0,0 +1,346 @@
+//! In-memory stand-ins for devices, for unit tests.
+//!
+//! A [`MockDevice`] implements the same I/O traits as [`CharDevice`] and the
+//! async wrappers, so code which is generic over those traits can be tested
+//! without a device. It either follows a script of expected writes and the
+//! responses to them, or is one of a pair of linked devices, each reading
+//! what the other writes.
+//!
+//! ```
+//! use char_device::testing::MockDevice;
+//! use std::io::{Read, Write};
+//!
+//! # fn main() -> std::io::Result<()> {
+//! let mut device = MockDevice::new();
+//! device.respond(b"READY\n").expect(b"AT\r", b"OK\r\n");
+//!
+//! let mut buf = [0; 16];
+//! assert_eq!(device.read(&mut buf)?, 6);
+//! device.write_all(b"AT\r")?;
+//! assert_eq!(device.num_ready_bytes()?, 4);
+//! assert_eq!(device.read(&mut buf)?, 4);
+//! device.finish()?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`CharDevice`]: crate::CharDevice
+
+use crate::expect::Expectations;
+use crate::link::{LinkEnd, SimulatedLink};
+use crate::ReadEvent;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+#[cfg(feature = "tokio")]
+use std::task::ready;
+#[cfg(any(feature = "async-std", feature = "tokio"))]
+use std::{
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+/// A step in a script.
+#[derive(Debug)]
+enum Step {
+    /// Expect the device to be written these bytes.
+    Write(Vec<u8>),
+    /// Make these bytes available to read.
+    Respond(Vec<u8>),
+}
+
+#[derive(Debug, Default)]
+struct Script {
+    /// The steps after the next expected write, starting with it.
+    steps: VecDeque<Step>,
+    expectations: Expectations,
+    /// The bytes which are available to read.
+    ready: VecDeque<u8>,
+    /// The task waiting for something to read.
+    #[cfg(any(feature = "async-std", feature = "tokio"))]
+    waker: Option<Waker>,
+}
+
+impl Script {
+    /// Make the responses up to the next expected write available to read.
+    fn respond(&mut self) {
+        while let Some(Step::Respond(_)) = self.steps.front() {
+            if let Some(Step::Respond(data)) = self.steps.pop_front() {
+                self.ready.extend(data);
+            }
+        }
+        #[cfg(any(feature = "async-std", feature = "tokio"))]
+        if !self.ready.is_empty() {
+            if let Some(waker) = self.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.expectations.check()?;
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.ready.is_empty() {
+            if self.steps.is_empty() {
+                return Ok(0);
+            }
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        let n = buf.len().min(self.ready.len());
+        for (dst, src) in buf.iter_mut().zip(self.ready.drain(..n)) {
+            *dst = src;
+        }
+        Ok(n)
+    }
+
+    /// Read, or if nothing is available yet, wait for a write which makes a
+    /// response available.
+    #[cfg(any(feature = "async-std", feature = "tokio"))]
+    fn poll_read(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        match self.read(buf) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                self.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+            result => Poll::Ready(result),
+        }
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.expectations.check()?;
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let expected = match self.steps.front() {
+            Some(Step::Write(data)) => Ok(&data[..]),
+            _ => Err("no more writes are expected"),
+        };
+        let (n, complete) = self.expectations.write(buf, expected)?;
+        if complete {
+            self.steps.pop_front();
+            self.respond();
+        }
+        Ok(n)
+    }
+}
+
+#[derive(Debug)]
+enum Inner {
+    Scripted(Script),
+    Linked(LinkEnd),
+}
+
+/// An in-memory stand-in for a device.
+///
+/// A scripted device checks that what's written to it matches what the
+/// script expects, split up in any way, and makes each response available
+/// to read once the writes before it have been made. A write which doesn't
+/// match fails, as does every operation after it. Reading when nothing is
+/// available fails with [`io::ErrorKind::WouldBlock`], as a non-blocking
+/// device does, or with the async traits, waits for a write which makes a
+/// response available. Once the script is done, reads return end of file.
+/// At the end of a test, call [`MockDevice::finish`] to check that all of
+/// the expected writes have been made.
+///
+/// A linked device, created with [`MockDevice::pair`], reads what the other
+/// device of the pair writes, waiting until something has been written, and
+/// returns end of file once the other device is dropped.
+///
+/// Unlike the real devices, a `MockDevice` has no file descriptor or handle,
+/// so it doesn't implement the traits for getting one.
+#[derive(Debug)]
+pub struct MockDevice(Inner);
+
+impl MockDevice {
+    /// Create a scripted device with an empty script.
+    pub fn new() -> Self {
+        Self(Inner::Scripted(Script::default()))
+    }
+
+    /// Create a pair of linked devices.
+    pub fn pair() -> (Self, Self) {
+        let (a, b) = SimulatedLink::new().pair();
+        (Self(Inner::Linked(a)), Self(Inner::Linked(b)))
+    }
+
+    /// Add a step to the script: expect `write` to be written, and then make
+    /// `response` available to read.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is a linked device.
+    pub fn expect(&mut self, write: &[u8], response: &[u8]) -> &mut Self {
+        self.script().steps.push_back(Step::Write(write.to_vec()));
+        self.respond(response)
+    }
+
+    /// Add a step to the script: make `data` available to read, once the
+    /// writes expected before it have been made.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is a linked device.
+    pub fn respond(&mut self, data: &[u8]) -> &mut Self {
+        let script = self.script();
+        script.steps.push_back(Step::Respond(data.to_vec()));
+        script.respond();
+        self
+    }
+
+    fn script(&mut self) -> &mut Script {
+        match &mut self.0 {
+            Inner::Scripted(script) => script,
+            Inner::Linked(_) => panic!("a linked `MockDevice` doesn't have a script"),
+        }
+    }
+
+    /// Check that the script was followed, and that all of the expected
+    /// writes have been made. For a linked device, this does nothing.
+    pub fn finish(self) -> io::Result<()> {
+        let script = match self.0 {
+            Inner::Scripted(script) => script,
+            Inner::Linked(_) => return Ok(()),
+        };
+        let missing = script
+            .steps
+            .iter()
+            .filter(|step| matches!(step, Step::Write(_)))
+            .count();
+        script.expectations.finish(missing)
+    }
+
+    /// Read into `buf`, reporting end of file as a [`ReadEvent`], like
+    /// [`CharDevice::read_event`].
+    ///
+    /// [`CharDevice::read_event`]: crate::CharDevice::read_event
+    #[inline]
+    pub fn read_event(&mut self, buf: &mut [u8]) -> io::Result<ReadEvent> {
+        ReadEvent::from_read(buf.len(), self.read(buf))
+    }
+
+    /// Return the number of bytes which are ready to be read immediately,
+    /// like [`CharDevice::num_ready_bytes`].
+    ///
+    /// [`CharDevice::num_ready_bytes`]: crate::CharDevice::num_ready_bytes
+    pub fn num_ready_bytes(&self) -> io::Result<u64> {
+        match &self.0 {
+            Inner::Scripted(script) => Ok(script.ready.len() as u64),
+            Inner::Linked(end) => end.num_ready_bytes(),
+        }
+    }
+}
+
+impl Default for MockDevice {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Read for MockDevice {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.0 {
+            Inner::Scripted(script) => script.read(buf),
+            Inner::Linked(end) => end.read(buf),
+        }
+    }
+}
+
+impl Write for MockDevice {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.0 {
+            Inner::Scripted(script) => script.write(buf),
+            Inner::Linked(end) => end.write(buf),
+        }
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl tokio::io::AsyncRead for MockDevice {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match &mut self.get_mut().0 {
+            Inner::Scripted(script) => {
+                let n = ready!(script.poll_read(cx, buf.initialize_unfilled()))?;
+                buf.advance(n);
+                Poll::Ready(Ok(()))
+            }
+            Inner::Linked(end) => tokio::io::AsyncRead::poll_read(Pin::new(end), cx, buf),
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl tokio::io::AsyncWrite for MockDevice {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match &mut self.get_mut().0 {
+            Inner::Scripted(script) => Poll::Ready(script.write(buf)),
+            Inner::Linked(end) => tokio::io::AsyncWrite::poll_write(Pin::new(end), cx, buf),
+        }
+    }
+
+    #[inline]
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match &mut self.get_mut().0 {
+            Inner::Scripted(_) => Poll::Ready(Ok(())),
+            Inner::Linked(end) => tokio::io::AsyncWrite::poll_shutdown(Pin::new(end), cx),
+        }
+    }
+}
+
+#[cfg(feature = "async-std")]
+impl async_std::io::Read for MockDevice {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        match &mut self.get_mut().0 {
+            Inner::Scripted(script) => script.poll_read(cx, buf),
+            Inner::Linked(end) => async_std::io::Read::poll_read(Pin::new(end), cx, buf),
+        }
+    }
+}
+
+#[cfg(feature = "async-std")]
+impl async_std::io::Write for MockDevice {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match &mut self.get_mut().0 {
+            Inner::Scripted(script) => Poll::Ready(script.write(buf)),
+            Inner::Linked(end) => async_std::io::Write::poll_write(Pin::new(end), cx, buf),
+        }
+    }
+
+    #[inline]
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match &mut self.get_mut().0 {
+            Inner::Scripted(_) => Poll::Ready(Ok(())),
+            Inner::Linked(end) => async_std::io::Write::poll_close(Pin::new(end), cx),
+        }
+    }
+}
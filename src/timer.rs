@@ -0,0 +1,80 @@
+//! Waking tasks at given times, without depending on a particular async
+//! runtime's timers.
+
+use std::io;
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::Waker;
+use std::time::Instant;
+
+/// A thread which wakes tasks at the times they ask for. The thread is
+/// started when it's first needed, and exits when the `Timer` is dropped.
+#[derive(Debug, Default)]
+pub(crate) struct Timer {
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// The tasks to wake, and when, by key.
+    wakeups: Vec<(usize, Instant, Waker)>,
+    started: bool,
+    stopped: bool,
+}
+
+impl Timer {
+    /// Wake `waker` at `deadline`.
+    ///
+    /// Each key has at most one wakeup, which this replaces, so calling this
+    /// on every poll keeps the waker up to date when the polling task
+    /// changes.
+    pub(crate) fn wake_at(&self, key: usize, deadline: Instant, waker: &Waker) -> io::Result<()> {
+        let mut state = self.shared.state.lock().unwrap();
+        match state.wakeups.iter_mut().find(|(k, _, _)| *k == key) {
+            Some((_, d, w)) if *d == deadline && w.will_wake(waker) => return Ok(()),
+            Some(wakeup) => *wakeup = (key, deadline, waker.clone()),
+            None => state.wakeups.push((key, deadline, waker.clone())),
+        }
+        if !state.started {
+            let shared = Arc::clone(&self.shared);
+            std::thread::Builder::new()
+                .name("char-device timer".to_owned())
+                .spawn(move || shared.run())?;
+            state.started = true;
+        }
+        self.shared.changed.notify_all();
+        Ok(())
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().stopped = true;
+        self.shared.changed.notify_all();
+    }
+}
+
+impl Shared {
+    fn run(&self) {
+        let mut state = self.state.lock().unwrap();
+        while !state.stopped {
+            let now = Instant::now();
+            let (due, later) = std::mem::take(&mut state.wakeups)
+                .into_iter()
+                .partition(|(_, deadline, _)| *deadline <= now);
+            state.wakeups = later;
+            for (_, _, waker) in due {
+                waker.wake();
+            }
+            state = match state.wakeups.iter().map(|(_, deadline, _)| *deadline).min() {
+                Some(next) => self.changed.wait_timeout(state, next - now).unwrap().0,
+                None => self.changed.wait(state).unwrap(),
+            };
+        }
+    }
+}
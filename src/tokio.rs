@@ -1,16 +1,26 @@
+use crate::{vectored, CharDeviceOptions, CloseBehavior, ReadEvent};
 use io_lifetimes::IntoFilelike;
 use std::io::IoSlice;
 use std::path::Path;
 use std::pin::Pin;
-use std::task::{Context, Poll};
+use std::task::{ready, Context, Poll};
 use tokio::fs::{File, OpenOptions};
 use tokio::io::{self, AsyncRead, AsyncWrite, ReadBuf};
 #[cfg(not(windows))]
 use {
+    crate::close,
+    crate::ioctl::{self, Ioctl, Plain},
+    crate::lockfile::LockFile,
+    crate::queue::{self, Queue},
+    crate::{CharDevice, DeviceLock},
     io_extras::os::rustix::{AsRawFd, AsRawReadWriteFd, AsReadWriteFd, RawFd},
     io_lifetimes::{AsFd, BorrowedFd},
     rustix::fs::FileTypeExt,
+    std::sync::Arc,
+    tokio::io::unix::AsyncFd,
 };
+#[cfg(feature = "tracing")]
+use {crate::trace, io_extras::grip::AsRawGrip, std::time::Instant};
 #[cfg(windows)]
 use {
     io_extras::os::windows::{
@@ -23,15 +33,42 @@ use {
 
 /// An unbuffered character device.
 ///
-/// This is a wrapper around [`tokio::fs::File`] which is intended for use
-/// with character device "files" such as "/dev/tty".
+/// This is intended for use with character device "files" such as
+/// "/dev/tty".
 ///
-/// TODO: "Unbuffered" here isn't entirely accurate, given how tokio deals
-/// with the underlying OS APIs being effectively synchronous. Figure out what
-/// to say here.
+/// On Posix-ish platforms, when the device's driver supports polling, as
+/// terminals, ptys, and most serial ports do, the device is put in
+/// non-blocking mode and registered with tokio's reactor. Reads and writes
+/// then happen directly when the device is ready, can proceed concurrently,
+/// and don't occupy a thread while they wait. This requires a runtime with
+/// I/O enabled. Non-blocking mode is a property of the open file
+/// description, so it's also seen through any duplicates of the handle.
+///
+/// Otherwise, this falls back to a [`tokio::fs::File`], which performs each
+/// operation on tokio's blocking thread pool, one at a time, and completes
+/// writes in the background until they're flushed.
+#[derive(Debug)]
+pub struct TokioCharDevice(
+    Inner,
+    /// The lock file held while the device is open.
+    #[cfg(not(windows))]
+    Option<Arc<LockFile>>,
+    /// When the read and write in progress were first polled.
+    #[cfg(feature = "tracing")]
+    trace::Timing,
+);
+
+/// How a [`TokioCharDevice`] performs I/O.
 #[derive(Debug)]
-#[repr(transparent)]
-pub struct TokioCharDevice(tokio::fs::File);
+enum Inner {
+    /// The device is in non-blocking mode, and registered with the reactor.
+    #[cfg(not(windows))]
+    Ready(AsyncFd<std::fs::File>),
+
+    /// The device is in blocking mode, and used from the blocking thread
+    /// pool.
+    File(File),
+}
 
 impl TokioCharDevice {
     /// Construct a new `CharDevice`. Fail if the given handle isn't a valid
@@ -49,10 +86,7 @@ impl TokioCharDevice {
         {
             let file_type = file.metadata().await?.file_type();
             if !file_type.is_char_device() {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    "raw fd is not a char device",
-                ));
+                return Err(io::Error::other("raw fd is not a char device"));
             }
         }
 
@@ -61,14 +95,56 @@ impl TokioCharDevice {
             let file_type =
                 winx::winapi_util::file::typ(&*file.as_filelike_view::<std::fs::File>())?;
             if !file_type.is_char() {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    "raw handle is not a char device",
-                ));
+                return Err(io::Error::other("raw handle is not a char device"));
             }
         }
 
-        Ok(Self(file))
+        Ok(Self::from_inner(Inner::new(file.into_std().await)))
+    }
+
+    #[cfg(not(windows))]
+    #[inline]
+    fn from_inner(inner: Inner) -> Self {
+        Self(
+            inner,
+            None,
+            #[cfg(feature = "tracing")]
+            trace::Timing::default(),
+        )
+    }
+
+    #[cfg(windows)]
+    #[inline]
+    fn from_inner(inner: Inner) -> Self {
+        Self(
+            inner,
+            #[cfg(feature = "tracing")]
+            trace::Timing::default(),
+        )
+    }
+
+    /// Hold `lock_file` until this device is closed.
+    #[cfg(not(windows))]
+    #[inline]
+    fn with_lock_file(mut self, lock_file: Option<Arc<LockFile>>) -> Self {
+        self.1 = lock_file;
+        self
+    }
+
+    /// Return when the read and write in progress were first polled.
+    #[cfg(feature = "tracing")]
+    #[inline]
+    fn timing(&mut self) -> &mut trace::Timing {
+        let Self(.., timing) = self;
+        timing
+    }
+
+    /// Construct a new `TokioCharDevice` from a `CharDevice`, keeping its
+    /// lock file.
+    #[cfg(not(windows))]
+    pub(crate) fn from_char_device(device: CharDevice) -> Self {
+        let (file, lock_file) = device.into_parts();
+        Self::from_inner(Inner::new(file)).with_lock_file(lock_file)
     }
 
     /// Construct a new `CharDevice` from the given filename. Fail if the given
@@ -76,7 +152,41 @@ impl TokioCharDevice {
     /// determined.
     #[inline]
     pub async fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
-        Self::_new(OpenOptions::new().read(true).write(true).open(path).await?).await
+        Self::open_with(path, &CharDeviceOptions::new()).await
+    }
+
+    /// Construct a new `CharDevice` from the given filename, with the given
+    /// options.
+    pub async fn open_with<P: AsRef<Path>>(
+        path: P,
+        options: &CharDeviceOptions,
+    ) -> io::Result<Self> {
+        let path = path.as_ref();
+        let open = async {
+            #[cfg(not(windows))]
+            let lock_file = {
+                let (options, path) = (options.clone(), path.to_owned());
+                tokio::task::spawn_blocking(move || options.acquire_lock_file(&path))
+                    .await
+                    .map_err(io::Error::other)??
+            };
+            let file = OpenOptions::from(options.std_options()).open(path).await?;
+            let device = Self::_new(file).await?;
+            options.apply(&device)?;
+            #[cfg(not(windows))]
+            let device = device.with_lock_file(lock_file);
+            Ok(device)
+        };
+
+        #[cfg(not(feature = "tracing"))]
+        {
+            open.await
+        }
+
+        #[cfg(feature = "tracing")]
+        {
+            crate::trace::open_async(path, options, open).await
+        }
     }
 
     /// Construct a new `CharDevice`.
@@ -87,7 +197,18 @@ impl TokioCharDevice {
     #[inline]
     pub unsafe fn new_unchecked<Filelike: IntoFilelike>(filelike: Filelike) -> Self {
         let std_file = std::fs::File::from(filelike.into_filelike());
-        Self(File::from_std(std_file))
+        Self::from_inner(Inner::new(std_file))
+    }
+
+    /// Return whether this device waits for readiness through tokio's
+    /// reactor, rather than performing I/O on the blocking thread pool.
+    #[inline]
+    pub fn is_readiness_based(&self) -> bool {
+        match &self.0 {
+            #[cfg(not(windows))]
+            Inner::Ready(_) => true,
+            Inner::File(_) => false,
+        }
     }
 
     /// Construct a new `CharDevice` which discards writes and reads nothing.
@@ -106,6 +227,24 @@ impl TokioCharDevice {
         }
     }
 
+    /// Read into `buf`, reporting end of file, hangups, and disconnections as
+    /// [`ReadEvent`]s rather than as errors.
+    pub async fn read_event(&mut self, buf: &mut [u8]) -> io::Result<ReadEvent> {
+        // Tokio waits for the device to be readable even when there's nothing
+        // to read into.
+        if buf.is_empty() {
+            return Ok(ReadEvent::Data(0));
+        }
+        let result = std::future::poll_fn(|cx| {
+            let mut buf = ReadBuf::new(buf);
+            Pin::new(&mut *self)
+                .poll_read(cx, &mut buf)
+                .map_ok(|()| buf.filled().len())
+        })
+        .await;
+        ReadEvent::from_result(result)
+    }
+
     /// Return the number of bytes which are ready to be read immediately.
     #[inline]
     pub fn num_ready_bytes(&self) -> io::Result<u64> {
@@ -120,6 +259,277 @@ impl TokioCharDevice {
             Ok(0)
         }
     }
+
+    /// Return the number of bytes which have been written but not yet
+    /// transmitted by the device (`TIOCOUTQ`).
+    #[inline]
+    pub fn pending_output_bytes(&self) -> io::Result<u64> {
+        #[cfg(not(windows))]
+        {
+            queue::pending_output_bytes(self)
+        }
+
+        #[cfg(windows)]
+        {
+            // Return the conservatively correct result.
+            Ok(0)
+        }
+    }
+
+    /// Discard data in the given queues of a terminal device (`tcflush`).
+    #[cfg(not(windows))]
+    #[inline]
+    pub fn discard(&self, queue: Queue) -> io::Result<()> {
+        queue::discard(self, queue)
+    }
+
+    /// Flush this device's buffered writes, and wait until all data written
+    /// to the terminal device has been transmitted (`tcdrain`).
+    ///
+    /// The wait happens on tokio's blocking thread pool, so it never blocks a
+    /// runtime worker.
+    pub async fn drain(&mut self) -> io::Result<()> {
+        std::future::poll_fn(|cx| Pin::new(&mut *self).poll_flush(cx)).await?;
+
+        #[cfg(not(windows))]
+        {
+            let fd = self.as_fd().try_clone_to_owned()?;
+            tokio::task::spawn_blocking(move || queue::drain(fd)).await?
+        }
+
+        #[cfg(windows)]
+        {
+            let Inner::File(file) = &self.0;
+            file.sync_all().await
+        }
+    }
+
+    /// Acquire an exclusive lock on this device, waiting until any other
+    /// holder releases it.
+    ///
+    /// The wait happens on tokio's blocking thread pool. See [`DeviceLock`]
+    /// for details.
+    #[cfg(not(windows))]
+    pub async fn lock_exclusive(&self) -> io::Result<DeviceLock> {
+        let fd = self.as_fd().try_clone_to_owned()?;
+        tokio::task::spawn_blocking(move || DeviceLock::acquire(fd.as_fd(), true)).await?
+    }
+
+    /// Acquire an exclusive lock on this device, failing with
+    /// [`io::ErrorKind::WouldBlock`] if another holder has it.
+    ///
+    /// See [`DeviceLock`] for details.
+    #[cfg(not(windows))]
+    #[inline]
+    pub fn try_lock_exclusive(&self) -> io::Result<DeviceLock> {
+        DeviceLock::acquire(self.as_fd(), false)
+    }
+
+    /// Flush this device's buffered writes and close it, reporting any
+    /// errors from `close`.
+    ///
+    /// Dropping a device also closes it, but ignores errors, which can be
+    /// the only sign that buffered output was lost, for example when a USB
+    /// serial adapter is unplugged.
+    #[inline]
+    pub async fn close(self) -> io::Result<()> {
+        self.close_with(CloseBehavior::Immediate).await
+    }
+
+    /// Flush this device's buffered writes and close it, handling output
+    /// which hasn't been transmitted yet according to `behavior`, and
+    /// reporting any errors.
+    ///
+    /// Waiting for output happens on tokio's blocking thread pool.
+    pub async fn close_with(mut self, behavior: CloseBehavior) -> io::Result<()> {
+        std::future::poll_fn(|cx| Pin::new(&mut self).poll_flush(cx)).await?;
+
+        #[cfg(not(windows))]
+        let lock_file = self.1.take();
+        let file = self.0.into_std().await?;
+
+        #[cfg(not(windows))]
+        {
+            // Hold the lock file until the device is closed.
+            let result =
+                tokio::task::spawn_blocking(move || close::close(file.into(), behavior)).await?;
+            drop(lock_file);
+            result
+        }
+
+        #[cfg(windows)]
+        {
+            // `CloseHandle` errors aren't reported by `std`.
+            let _ = behavior;
+            drop(file);
+            Ok(())
+        }
+    }
+
+    /// Flush this device's buffered writes and convert it into a `Stdio`, for
+    /// use with `tokio::process::Command`, along with the lock file it holds.
+    ///
+    /// Keep the lock file for as long as the child runs. See
+    /// [`CharDevice::into_stdio`].
+    #[cfg(not(windows))]
+    pub async fn into_stdio(mut self) -> io::Result<(std::process::Stdio, Option<Arc<LockFile>>)> {
+        std::future::poll_fn(|cx| Pin::new(&mut self).poll_flush(cx)).await?;
+        let lock_file = self.1.take();
+        Ok((self.0.into_std().await?.into(), lock_file))
+    }
+
+    /// Flush this device's buffered writes and convert it into a `Stdio`, for
+    /// use with `tokio::process::Command`.
+    #[cfg(windows)]
+    pub async fn into_stdio(mut self) -> io::Result<std::process::Stdio> {
+        std::future::poll_fn(|cx| Pin::new(&mut self).poll_flush(cx)).await?;
+        Ok(self.0.into_std().await?.into())
+    }
+
+    /// Perform the `ioctl` described by `request`, which takes no input.
+    ///
+    /// See the [`ioctl`] module for how to declare requests.
+    ///
+    /// [`ioctl`]: mod@crate::ioctl
+    #[cfg(not(windows))]
+    #[inline]
+    pub fn ioctl<Out: Plain>(&self, request: &Ioctl<(), Out>) -> io::Result<Out> {
+        ioctl::call(self, request, ())
+    }
+
+    /// Perform the `ioctl` described by `request`, passing it `input`.
+    ///
+    /// See the [`ioctl`] module for how to declare requests.
+    ///
+    /// [`ioctl`]: mod@crate::ioctl
+    #[cfg(not(windows))]
+    #[inline]
+    pub fn ioctl_with<In: Plain, Out: Plain>(
+        &self,
+        request: &Ioctl<In, Out>,
+        input: In,
+    ) -> io::Result<Out> {
+        ioctl::call(self, request, input)
+    }
+}
+
+impl Inner {
+    /// Use `file` through the reactor if its driver supports polling, and
+    /// through the blocking thread pool otherwise.
+    fn new(file: std::fs::File) -> Self {
+        #[cfg(not(windows))]
+        let file = {
+            let nonblocking = rustix::fs::fcntl_getfl(&file)
+                .is_ok_and(|flags| flags.contains(rustix::fs::OFlags::NONBLOCK));
+
+            // `AsyncFd` panics outside of a runtime, so check for one first.
+            // It fails with `EPERM` if the driver doesn't support polling.
+            let registered = if tokio::runtime::Handle::try_current().is_ok()
+                && rustix::io::ioctl_fionbio(&file, true).is_ok()
+            {
+                AsyncFd::try_new(file).map_err(|err| err.into_parts().0)
+            } else {
+                Err(file)
+            };
+            match registered {
+                Ok(fd) => return Self::Ready(fd),
+                Err(file) => {
+                    // Put the device back in the mode it was in. It's shared
+                    // with any duplicates of the handle, so it isn't ours
+                    // to change.
+                    let _ = rustix::io::ioctl_fionbio(&file, nonblocking);
+                    file
+                }
+            }
+        };
+
+        Self::File(File::from_std(file))
+    }
+
+    /// Convert this into a `std` file in blocking mode, once any in-flight
+    /// operations have completed.
+    async fn into_std(self) -> io::Result<std::fs::File> {
+        match self {
+            #[cfg(not(windows))]
+            Self::Ready(fd) => {
+                let file = fd.into_inner();
+                rustix::io::ioctl_fionbio(&file, false)?;
+                Ok(file)
+            }
+            Self::File(file) => Ok(file.into_std().await),
+        }
+    }
+
+    fn poll_read(&mut self, cx: &mut Context<'_>, buf: &mut ReadBuf) -> Poll<io::Result<()>> {
+        match self {
+            #[cfg(not(windows))]
+            Self::Ready(fd) => loop {
+                let mut guard = ready!(fd.poll_read_ready(cx))?;
+                let unfilled = buf.initialize_unfilled();
+                if let Ok(result) = guard.try_io(|fd| Ok(rustix::io::read(fd, unfilled)?)) {
+                    buf.advance(result?);
+                    return Poll::Ready(Ok(()));
+                }
+            },
+            Self::File(file) => Pin::new(file).poll_read(cx, buf),
+        }
+    }
+
+    fn poll_write(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        match self {
+            #[cfg(not(windows))]
+            Self::Ready(fd) => loop {
+                let mut guard = ready!(fd.poll_write_ready(cx))?;
+                if let Ok(result) = guard.try_io(|fd| Ok(rustix::io::write(fd, buf)?)) {
+                    return Poll::Ready(result);
+                }
+            },
+            Self::File(file) => Pin::new(file).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_write_vectored(
+        &mut self,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        match self {
+            #[cfg(not(windows))]
+            Self::Ready(fd) => loop {
+                let mut guard = ready!(fd.poll_write_ready(cx))?;
+                if let Ok(result) = guard.try_io(|fd| vectored::writev(fd, bufs)) {
+                    return Poll::Ready(result);
+                }
+            },
+            // `tokio::fs::File` writes only the first buffer, so concatenate
+            // the buffers to keep them in a single write to the device. Wait
+            // for any write in progress first, so that they're only
+            // concatenated once, when they can be written.
+            Self::File(file) => {
+                ready!(Pin::new(&mut *file).poll_flush(cx))?;
+                match vectored::coalesce(bufs) {
+                    Some(data) => Pin::new(file).poll_write(cx, &data),
+                    None => Pin::new(file).poll_write_vectored(cx, bufs),
+                }
+            }
+        }
+    }
+
+    fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self {
+            #[cfg(not(windows))]
+            Self::Ready(_) => Poll::Ready(Ok(())),
+            Self::File(file) => Pin::new(file).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self {
+            #[cfg(not(windows))]
+            Self::Ready(_) => Poll::Ready(Ok(())),
+            Self::File(file) => Pin::new(file).poll_shutdown(cx),
+        }
+    }
 }
 
 impl AsyncRead for TokioCharDevice {
@@ -129,7 +539,22 @@ impl AsyncRead for TokioCharDevice {
         cx: &mut Context<'_>,
         buf: &mut ReadBuf,
     ) -> Poll<io::Result<()>> {
-        Pin::new(&mut self.0).poll_read(cx, buf)
+        #[cfg(not(feature = "tracing"))]
+        {
+            self.0.poll_read(cx, buf)
+        }
+
+        #[cfg(feature = "tracing")]
+        {
+            self.timing().read.get_or_insert_with(Instant::now);
+            let filled = buf.filled().len();
+            let result = ready!(self.0.poll_read(cx, buf));
+            let result = result.map(|()| buf.filled().len() - filled);
+            let duration = self.timing().read.take().map(|start| start.elapsed());
+            let payload = [&buf.filled()[filled..]];
+            trace::io(self.as_raw_grip(), "read", &result, duration, payload);
+            Poll::Ready(result.map(drop))
+        }
     }
 }
 
@@ -140,7 +565,15 @@ impl AsyncWrite for TokioCharDevice {
         cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<io::Result<usize>> {
-        Pin::new(&mut self.0).poll_write(cx, buf)
+        #[cfg(feature = "tracing")]
+        self.timing().write.get_or_insert_with(Instant::now);
+        let result = ready!(self.0.poll_write(cx, buf));
+        #[cfg(feature = "tracing")]
+        {
+            let duration = self.timing().write.take().map(|start| start.elapsed());
+            trace::io(self.as_raw_grip(), "write", &result, duration, [buf]);
+        }
+        Poll::Ready(result)
     }
 
     fn poll_write_vectored(
@@ -148,17 +581,36 @@ impl AsyncWrite for TokioCharDevice {
         cx: &mut Context<'_>,
         bufs: &[IoSlice<'_>],
     ) -> Poll<io::Result<usize>> {
-        Pin::new(&mut self.0).poll_write_vectored(cx, bufs)
+        #[cfg(feature = "tracing")]
+        self.timing().write.get_or_insert_with(Instant::now);
+        let result = ready!(self.0.poll_write_vectored(cx, bufs));
+        #[cfg(feature = "tracing")]
+        {
+            let duration = self.timing().write.take().map(|start| start.elapsed());
+            trace::io(
+                self.as_raw_grip(),
+                "write",
+                &result,
+                duration,
+                bufs.iter().map(|buf| &**buf),
+            );
+        }
+        Poll::Ready(result)
+    }
+
+    #[inline]
+    fn is_write_vectored(&self) -> bool {
+        true
     }
 
     #[inline]
     fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
-        Pin::new(&mut self.0).poll_flush(cx)
+        self.0.poll_flush(cx)
     }
 
     #[inline]
     fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
-        Pin::new(&mut self.0).poll_shutdown(cx)
+        self.0.poll_shutdown(cx)
     }
 }
 
@@ -166,7 +618,7 @@ impl AsyncWrite for TokioCharDevice {
 impl AsRawFd for TokioCharDevice {
     #[inline]
     fn as_raw_fd(&self) -> RawFd {
-        self.0.as_raw_fd()
+        self.as_fd().as_raw_fd()
     }
 }
 
@@ -174,7 +626,8 @@ impl AsRawFd for TokioCharDevice {
 impl AsRawHandle for TokioCharDevice {
     #[inline]
     fn as_raw_handle(&self) -> RawHandle {
-        self.0.as_raw_handle()
+        let Inner::File(file) = &self.0;
+        file.as_raw_handle()
     }
 }
 
@@ -182,7 +635,8 @@ impl AsRawHandle for TokioCharDevice {
 impl AsRawHandleOrSocket for TokioCharDevice {
     #[inline]
     fn as_raw_handle_or_socket(&self) -> RawHandleOrSocket {
-        self.0.as_raw_handle_or_socket()
+        let Inner::File(file) = &self.0;
+        file.as_raw_handle_or_socket()
     }
 }
 
@@ -190,7 +644,10 @@ impl AsRawHandleOrSocket for TokioCharDevice {
 impl AsFd for TokioCharDevice {
     #[inline]
     fn as_fd(&self) -> BorrowedFd<'_> {
-        self.0.as_fd()
+        match &self.0 {
+            Inner::Ready(fd) => fd.get_ref().as_fd(),
+            Inner::File(file) => file.as_fd(),
+        }
     }
 }
 
@@ -198,7 +655,8 @@ impl AsFd for TokioCharDevice {
 impl AsHandle for TokioCharDevice {
     #[inline]
     fn as_handle(&self) -> BorrowedHandle<'_> {
-        self.0.as_handle()
+        let Inner::File(file) = &self.0;
+        file.as_handle()
     }
 }
 
@@ -206,7 +664,7 @@ impl AsHandle for TokioCharDevice {
 impl AsHandleOrSocket for TokioCharDevice {
     #[inline]
     fn as_handle_or_socket(&self) -> BorrowedHandleOrSocket<'_> {
-        BorrowedHandleOrSocket::from_handle(self.0.as_handle())
+        BorrowedHandleOrSocket::from_handle(self.as_handle())
     }
 }
 
@@ -0,0 +1,177 @@
+//! Tracing of device I/O.
+//!
+//! With the `tracing` feature, [`CharDevice`], `TokioCharDevice`, and
+//! `AsyncStdCharDevice` report what they do through the [`tracing`] crate,
+//! under the `char_device` target:
+//!
+//!  - Opening a device by path runs in a DEBUG `open` span, with the `path`,
+//!    the open options as `flags`, and, on Posix-ish platforms, the
+//!    `device_id` of the device which was opened, as "major:minor". An
+//!    event in the span reports whether the open succeeded.
+//!  - Each read and write is reported by a DEBUG event with the device's
+//!    `fd`, the `op`, and either the number of `bytes` transferred or the
+//!    `errno` it failed with, and the `duration_us` it took. For the async
+//!    devices, this is measured from when the operation is first polled
+//!    until it completes, so an operation which follows a cancelled one
+//!    includes the time the cancelled one waited.
+//!  - When TRACE is enabled, the bytes read or written are also reported,
+//!    as a hexdump of at most [`payload_limit`] bytes.
+//!
+//! So that each call to the OS is reported exactly once, `CharDevice`'s
+//! `read_exact`, `write_all`, and similar methods are implemented in terms
+//! of `read` and `write` when this feature is enabled.
+//!
+//! [`CharDevice`]: crate::CharDevice
+
+use crate::CharDeviceOptions;
+use io_extras::grip::{AsGrip, AsRawGrip, RawGrip};
+use std::fmt::Write as _;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+#[cfg(any(feature = "async-std", feature = "tokio"))]
+use std::time::Instant;
+use tracing::{field, Level, Span};
+
+static PAYLOAD_LIMIT: AtomicUsize = AtomicUsize::new(64);
+
+/// Return the maximum number of bytes of each read or write to include in
+/// hexdumps. The default is 64.
+#[inline]
+pub fn payload_limit() -> usize {
+    PAYLOAD_LIMIT.load(Ordering::Relaxed)
+}
+
+/// Set the maximum number of bytes of each read or write to include in
+/// hexdumps. Zero disables hexdumps.
+#[inline]
+pub fn set_payload_limit(limit: usize) {
+    PAYLOAD_LIMIT.store(limit, Ordering::Relaxed);
+}
+
+/// Open a device at `path` with `options`, in an `open` span.
+pub(crate) fn open<D: AsGrip>(
+    path: &Path,
+    options: &CharDeviceOptions,
+    open: impl FnOnce() -> io::Result<D>,
+) -> io::Result<D> {
+    let span = open_span(path, options);
+    let result = span.in_scope(open);
+    opened(&span, &result);
+    result
+}
+
+/// Open a device at `path` with `options` asynchronously, in an `open`
+/// span.
+#[cfg(any(feature = "async-std", feature = "tokio"))]
+pub(crate) async fn open_async<D: AsGrip>(
+    path: &Path,
+    options: &CharDeviceOptions,
+    open: impl std::future::Future<Output = io::Result<D>>,
+) -> io::Result<D> {
+    use tracing::Instrument;
+
+    let span = open_span(path, options);
+    let result = open.instrument(span.clone()).await;
+    opened(&span, &result);
+    result
+}
+
+fn open_span(path: &Path, options: &CharDeviceOptions) -> Span {
+    tracing::debug_span!(
+        target: "char_device",
+        "open",
+        path = %path.display(),
+        flags = ?options,
+        device_id = field::Empty,
+    )
+}
+
+fn opened<D: AsGrip>(span: &Span, result: &io::Result<D>) {
+    match result {
+        Ok(device) => {
+            #[cfg(not(windows))]
+            if let Ok(stat) = rustix::fs::fstat(device.as_grip()) {
+                let (major, minor) = (
+                    rustix::fs::major(stat.st_rdev),
+                    rustix::fs::minor(stat.st_rdev),
+                );
+                span.record("device_id", field::display(format_args!("{major}:{minor}")));
+            }
+            tracing::debug!(
+                target: "char_device",
+                parent: span,
+                fd = ?device.as_grip().as_raw_grip(),
+                "opened"
+            );
+        }
+        Err(err) => {
+            tracing::debug!(
+                target: "char_device",
+                parent: span,
+                errno = err.raw_os_error(),
+                error = %err,
+                "open failed"
+            );
+        }
+    }
+}
+
+/// When the read and the write in progress on an async device were first
+/// polled.
+#[cfg(any(feature = "async-std", feature = "tokio"))]
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Timing {
+    pub(crate) read: Option<Instant>,
+    pub(crate) write: Option<Instant>,
+}
+
+/// Report a read or write of the device `fd`, which transferred bytes from
+/// `payload`, or failed.
+pub(crate) fn io<'a>(
+    fd: RawGrip,
+    op: &'static str,
+    result: &io::Result<usize>,
+    duration: Option<Duration>,
+    payload: impl IntoIterator<Item = &'a [u8]>,
+) {
+    let duration_us = duration.map(|duration| duration.as_micros() as u64);
+    match result {
+        Ok(bytes) => {
+            let bytes = *bytes;
+            tracing::debug!(target: "char_device", fd = ?fd, op, bytes, duration_us);
+            let limit = payload_limit();
+            if bytes != 0 && limit != 0 && tracing::enabled!(target: "char_device", Level::TRACE) {
+                let data = hexdump(payload, bytes, limit);
+                tracing::trace!(target: "char_device", fd = ?fd, op, bytes, data = %data);
+            }
+        }
+        Err(err) => {
+            tracing::debug!(
+                target: "char_device",
+                fd = ?fd,
+                op,
+                errno = err.raw_os_error(),
+                error = %err,
+                duration_us
+            );
+        }
+    }
+}
+
+/// Format up to `limit` of the first `len` bytes of `payload` as hex.
+fn hexdump<'a>(payload: impl IntoIterator<Item = &'a [u8]>, len: usize, limit: usize) -> String {
+    let mut out = String::new();
+    let bytes = payload.into_iter().flatten().take(len.min(limit));
+    for (i, byte) in bytes.enumerate() {
+        if i != 0 {
+            out.push(' ');
+        }
+        write!(out, "{byte:02x}").unwrap();
+    }
+    if len > limit {
+        write!(out, " ... ({} more bytes)", len - limit).unwrap();
+    }
+    out
+}
@@ -0,0 +1,844 @@
+//! Batched I/O on many character devices through io_uring.
+//!
+//! A [`Ring`] holds a set of devices, and submits their reads, writes, and
+//! readiness polls together, so that servicing many devices takes one
+//! `io_uring_enter` call rather than a syscall per device and operation.
+//!
+//! Reads go into a pool of buffers which the kernel picks from as data
+//! arrives, so a device which is being read doesn't tie up a buffer of its
+//! own. On kernels which support it (6.7 and later), each device is read
+//! with a single multishot read, which keeps completing for as long as data
+//! arrives. Elsewhere, and for devices whose drivers don't support polling,
+//! each read is resubmitted as it completes.
+//!
+//! When io_uring isn't available, because the kernel is older than 5.11 or
+//! io_uring is disabled, a `Ring` falls back to `poll` and plain reads and
+//! writes, behind the same interface. Devices are put in non-blocking mode
+//! while they're in such a ring, so that a write which doesn't fit in one
+//! device's buffer doesn't hold up the others.
+
+use crate::CharDevice;
+use io_extras::os::rustix::AsRawFd;
+use io_uring::{cqueue, opcode, squeue, types, IoUring, Probe};
+use rustix::event::{PollFd, PollFlags, Timespec};
+use rustix::io::Errno;
+use std::collections::VecDeque;
+use std::time::Duration;
+use std::{fmt, io, ptr};
+
+/// The number of submission queue entries.
+const ENTRIES: u32 = 256;
+
+/// The buffer group which reads select buffers from.
+const BUF_GROUP: u16 = 0;
+
+// The kinds of operations, stored in the low bits of each operation's
+// `user_data`, below the index of the device. The high 32 bits hold a tag:
+// the number of a poll, or one more than the id of a buffer being provided.
+const READ: u64 = 0;
+const WRITE: u64 = 1;
+const POLL: u64 = 2;
+/// Buffer provisions and cancellations.
+const INTERNAL: u64 = 3;
+const KIND_BITS: u32 = 2;
+const TAG_SHIFT: u32 = 32;
+
+/// Identifies a device in a [`Ring`].
+///
+/// Ids aren't reused, so an id of a removed device never refers to a device
+/// added later, even one which is given the same place in the ring.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DeviceId {
+    index: usize,
+    generation: u64,
+}
+
+/// The readiness of a device, or the readiness to wait for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Readiness {
+    /// The device can be read without blocking.
+    pub readable: bool,
+    /// The device can be written without blocking.
+    pub writable: bool,
+    /// The device hung up, or has an error pending. This is always reported,
+    /// whether or not it was waited for.
+    pub hangup: bool,
+}
+
+impl Readiness {
+    fn flags(self) -> PollFlags {
+        let mut flags = PollFlags::empty();
+        flags.set(PollFlags::IN, self.readable);
+        flags.set(PollFlags::OUT, self.writable);
+        flags
+    }
+
+    fn from_flags(flags: PollFlags) -> Self {
+        Self {
+            readable: flags.contains(PollFlags::IN),
+            writable: flags.contains(PollFlags::OUT),
+            hangup: flags.intersects(PollFlags::HUP | PollFlags::ERR),
+        }
+    }
+}
+
+/// The completion of an operation on a device in a [`Ring`].
+#[derive(Debug)]
+pub enum Completion {
+    /// Data was read. Empty data means end-of-file, after which the device
+    /// is no longer read until [`Ring::start_reading`] is called again.
+    Read(DeviceId, io::Result<Vec<u8>>),
+    /// Data passed to [`Ring::write`] was written. Writes which only write
+    /// part of the data are continued until all of it is written, so the
+    /// number of bytes is the length of the data, unless an error or a write
+    /// of zero bytes stopped it partway. An error after part of the data was
+    /// written is reported as the number of bytes written before it.
+    Write(DeviceId, io::Result<usize>),
+    /// A device became ready, as requested with [`Ring::poll_ready`].
+    Ready(DeviceId, io::Result<Readiness>),
+}
+
+/// A set of character devices whose I/O is submitted through io_uring.
+///
+/// See the [module documentation] for details.
+///
+/// [module documentation]: self
+pub struct Ring {
+    backend: Backend,
+    slots: Vec<Option<Slot>>,
+    free: Vec<usize>,
+    /// The generation of the next device added.
+    next_generation: u64,
+    completions: VecDeque<Completion>,
+}
+
+enum Backend {
+    Uring(Box<Uring>),
+    /// Fall back to `poll`, reading into buffers of the given size.
+    Poll(usize),
+}
+
+struct Slot {
+    id: DeviceId,
+    device: CharDevice,
+    /// Whether the device should be read continuously.
+    reading: bool,
+    /// Whether a read is in flight.
+    read_armed: bool,
+    /// Whether multishot reads work on this device.
+    multishot: bool,
+    /// Data to write, in order. With io_uring, the first is in flight.
+    writes: VecDeque<Vec<u8>>,
+    /// The number of bytes of the first write which have been written.
+    written: usize,
+    /// Whether the device is being removed, so that partial writes aren't
+    /// continued.
+    removing: bool,
+    /// Whether the device was in non-blocking mode before it was added, to
+    /// be restored when it's removed from a ring which uses `poll`.
+    nonblocking: bool,
+    /// Readiness waited for, tagged with the number of the poll. With
+    /// io_uring, these are all in flight.
+    polls: Vec<(u32, Readiness)>,
+    /// The number of the next poll.
+    next_poll: u32,
+    /// The number of io_uring operations in flight.
+    in_flight: usize,
+}
+
+impl Ring {
+    /// Create a new `Ring`, with 256 read buffers of 4 KiB each.
+    ///
+    /// This uses io_uring if it's available, and `poll` otherwise.
+    #[inline]
+    pub fn new() -> io::Result<Self> {
+        Self::with_buffers(256, 4096)
+    }
+
+    /// Create a new `Ring`, with `count` read buffers of `size` bytes each.
+    ///
+    /// This uses io_uring if it's available, and `poll` otherwise.
+    pub fn with_buffers(count: u16, size: usize) -> io::Result<Self> {
+        let backend = match Uring::new(count, size)? {
+            Some(uring) => Backend::Uring(Box::new(uring)),
+            None => Backend::Poll(size),
+        };
+        Ok(Self::with_backend(backend))
+    }
+
+    /// Create a new `Ring` which uses `poll`, even if io_uring is
+    /// available, reading into buffers of `size` bytes.
+    #[inline]
+    pub fn without_io_uring(size: usize) -> Self {
+        Self::with_backend(Backend::Poll(size))
+    }
+
+    fn with_backend(backend: Backend) -> Self {
+        Self {
+            backend,
+            slots: Vec::new(),
+            free: Vec::new(),
+            next_generation: 0,
+            completions: VecDeque::new(),
+        }
+    }
+
+    /// Return whether this ring uses io_uring, rather than `poll`.
+    #[inline]
+    pub fn is_io_uring(&self) -> bool {
+        matches!(self.backend, Backend::Uring(_))
+    }
+
+    /// Add `device` to this ring.
+    ///
+    /// If this ring uses `poll`, the device is put in non-blocking mode until
+    /// it's removed.
+    pub fn add(&mut self, device: CharDevice) -> DeviceId {
+        let index = match self.free.pop() {
+            Some(index) => index,
+            None => {
+                assert!(
+                    self.slots.len() < 1 << (TAG_SHIFT - KIND_BITS),
+                    "too many devices in this ring"
+                );
+                self.slots.push(None);
+                self.slots.len() - 1
+            }
+        };
+        let id = DeviceId {
+            index,
+            generation: self.next_generation,
+        };
+        self.next_generation += 1;
+        let nonblocking = rustix::fs::fcntl_getfl(&device)
+            .is_ok_and(|flags| flags.contains(rustix::fs::OFlags::NONBLOCK));
+        if let Backend::Poll(_) = self.backend {
+            let _ = rustix::io::ioctl_fionbio(&device, true);
+        }
+        self.slots[index] = Some(Slot {
+            id,
+            device,
+            reading: false,
+            read_armed: false,
+            multishot: true,
+            writes: VecDeque::new(),
+            written: 0,
+            removing: false,
+            nonblocking,
+            polls: Vec::new(),
+            next_poll: 0,
+            in_flight: 0,
+        });
+        id
+    }
+
+    /// Remove a device from this ring, and return it.
+    ///
+    /// Operations in flight on the device are cancelled, and this waits for
+    /// them to finish. Writes which were in flight may still complete, and
+    /// are reported by the next [`wait`], but the rest of a partially
+    /// completed write isn't written. Writes which hadn't been started are
+    /// discarded.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `id` isn't a device in this ring.
+    ///
+    /// [`wait`]: Self::wait
+    pub fn remove(&mut self, id: DeviceId) -> io::Result<CharDevice> {
+        let slot = slot(&mut self.slots, id);
+        slot.reading = false;
+        slot.removing = true;
+        // With io_uring, the first write is in flight.
+        slot.writes
+            .truncate(usize::from(matches!(self.backend, Backend::Uring(_))));
+        match &mut self.backend {
+            Backend::Uring(uring) => {
+                uring.quiesce(&mut self.slots, id.index, &mut self.completions)?
+            }
+            Backend::Poll(_) => {
+                let _ = rustix::io::ioctl_fionbio(&slot.device, slot.nonblocking);
+            }
+        }
+        let slot = self.slots[id.index].take().unwrap();
+        self.free.push(id.index);
+        Ok(slot.device)
+    }
+
+    /// Return a reference to a device in this ring.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `id` isn't a device in this ring.
+    #[inline]
+    pub fn get(&self, id: DeviceId) -> &CharDevice {
+        match self.slots.get(id.index) {
+            Some(Some(slot)) if slot.id == id => &slot.device,
+            _ => panic!("no device {:?} in this ring", id),
+        }
+    }
+
+    /// Start reading a device continuously, reporting each read as a
+    /// [`Completion::Read`].
+    ///
+    /// Reading stops at end-of-file, at the first error, or when
+    /// [`stop_reading`] is called.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `id` isn't a device in this ring.
+    ///
+    /// [`stop_reading`]: Self::stop_reading
+    pub fn start_reading(&mut self, id: DeviceId) -> io::Result<()> {
+        let slot = slot(&mut self.slots, id);
+        slot.reading = true;
+        match &mut self.backend {
+            Backend::Uring(uring) if !slot.read_armed => uring.arm_read(id.index, slot),
+            _ => Ok(()),
+        }
+    }
+
+    /// Stop reading a device.
+    ///
+    /// A read which is in flight may still complete, and be reported.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `id` isn't a device in this ring.
+    pub fn stop_reading(&mut self, id: DeviceId) -> io::Result<()> {
+        let slot = slot(&mut self.slots, id);
+        slot.reading = false;
+        match &mut self.backend {
+            Backend::Uring(uring) if slot.read_armed => uring.cancel(user_data(id.index, READ, 0)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Write all of `data` to a device, reporting the result as a
+    /// [`Completion::Write`].
+    ///
+    /// Writes to each device are performed in order, one at a time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `id` isn't a device in this ring.
+    pub fn write(&mut self, id: DeviceId, data: Vec<u8>) -> io::Result<()> {
+        let slot = slot(&mut self.slots, id);
+        slot.writes.push_back(data);
+        match &mut self.backend {
+            Backend::Uring(uring) if slot.writes.len() == 1 => uring.arm_write(id.index, slot),
+            _ => Ok(()),
+        }
+    }
+
+    /// Wait for a device to become ready, reporting its readiness as a
+    /// [`Completion::Ready`].
+    ///
+    /// With io_uring, this uses `IORING_OP_POLL_ADD`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `id` isn't a device in this ring.
+    pub fn poll_ready(&mut self, id: DeviceId, interest: Readiness) -> io::Result<()> {
+        let slot = slot(&mut self.slots, id);
+        let tag = slot.next_poll;
+        slot.next_poll = tag.wrapping_add(1);
+        slot.polls.push((tag, interest));
+        match &mut self.backend {
+            Backend::Uring(uring) => uring.arm_poll(id.index, slot, tag, interest),
+            Backend::Poll(_) => Ok(()),
+        }
+    }
+
+    /// Submit any new operations, wait up to `timeout` for at least one
+    /// operation to complete, and append the completions to `completions`.
+    ///
+    /// With no timeout, and no operations in progress, this waits forever.
+    ///
+    /// If handling a completion fails, such as when a buffer used by a read
+    /// couldn't be returned to the ring's pool, every other completion is
+    /// still handled and appended, and then the first error is returned.
+    pub fn wait(
+        &mut self,
+        completions: &mut Vec<Completion>,
+        timeout: Option<Duration>,
+    ) -> io::Result<()> {
+        let mut result = Ok(());
+        if self.completions.is_empty() {
+            match &mut self.backend {
+                Backend::Uring(uring) => {
+                    uring.wait(timeout)?;
+                    result = uring.process(&mut self.slots, &mut self.completions);
+                }
+                Backend::Poll(size) => {
+                    poll(*size, &mut self.slots, &mut self.completions, timeout)?
+                }
+            }
+        }
+        completions.extend(self.completions.drain(..));
+        result
+    }
+}
+
+impl Drop for Ring {
+    fn drop(&mut self) {
+        match &mut self.backend {
+            // The kernel may still be using buffers which are about to be
+            // freed, so wait for everything in flight to finish.
+            Backend::Uring(uring) => {
+                for index in 0..self.slots.len() {
+                    if let Some(slot) = &mut self.slots[index] {
+                        slot.reading = false;
+                        slot.removing = true;
+                        let _ = uring.quiesce(&mut self.slots, index, &mut self.completions);
+                    }
+                }
+            }
+            // Put the devices back in the mode they were in, for any
+            // duplicates of their handles.
+            Backend::Poll(_) => {
+                for slot in self.slots.iter().flatten() {
+                    let _ = rustix::io::ioctl_fionbio(&slot.device, slot.nonblocking);
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Ring {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Ring")
+            .field("io_uring", &self.is_io_uring())
+            .field("devices", &(self.slots.len() - self.free.len()))
+            .finish_non_exhaustive()
+    }
+}
+
+fn slot(slots: &mut [Option<Slot>], id: DeviceId) -> &mut Slot {
+    match slots.get_mut(id.index) {
+        Some(Some(slot)) if slot.id == id => slot,
+        _ => panic!("no device {:?} in this ring", id),
+    }
+}
+
+fn user_data(index: usize, kind: u64, tag: u32) -> u64 {
+    (u64::from(tag) << TAG_SHIFT) | ((index as u64) << KIND_BITS) | kind
+}
+
+struct Uring {
+    // Declared before `buffers`, so that the ring is closed first.
+    ring: IoUring,
+    buffers: Box<[u8]>,
+    buffer_size: usize,
+    multishot: bool,
+}
+
+impl Uring {
+    /// Set up a ring, or return `None` if io_uring or the features used
+    /// here aren't available.
+    fn new(count: u16, size: usize) -> io::Result<Option<Self>> {
+        let Ok(ring) = IoUring::new(ENTRIES) else {
+            return Ok(None);
+        };
+        let mut probe = Probe::new();
+        if ring.submitter().register_probe(&mut probe).is_err()
+            || !ring.params().is_feature_ext_arg()
+            || ![
+                opcode::Read::CODE,
+                opcode::Write::CODE,
+                opcode::PollAdd::CODE,
+                opcode::AsyncCancel::CODE,
+                opcode::ProvideBuffers::CODE,
+            ]
+            .into_iter()
+            .all(|code| probe.is_supported(code))
+        {
+            return Ok(None);
+        }
+
+        let len = i32::try_from(size).map_err(|_| io::ErrorKind::InvalidInput)?;
+        let mut uring = Self {
+            ring,
+            buffers: vec![0; usize::from(count) * size].into_boxed_slice(),
+            buffer_size: size,
+            multishot: probe.is_supported(opcode::ReadMulti::CODE),
+        };
+        let entry =
+            opcode::ProvideBuffers::new(uring.buffers.as_mut_ptr(), len, count, BUF_GROUP, 0)
+                .build()
+                .user_data(INTERNAL);
+        // SAFETY: The buffers live as long as the ring.
+        unsafe { uring.push(&entry) }?;
+        uring.ring.submit_and_wait(1)?;
+        if let Some(cqe) = uring.ring.completion().next() {
+            if cqe.result() < 0 {
+                return Err(io::Error::from_raw_os_error(-cqe.result()));
+            }
+        }
+        Ok(Some(uring))
+    }
+
+    /// Push `entry` onto the submission queue, submitting the queue first
+    /// if it's full.
+    ///
+    /// # Safety
+    ///
+    /// Any memory `entry` refers to must live until it completes.
+    unsafe fn push(&mut self, entry: &squeue::Entry) -> io::Result<()> {
+        while self.ring.submission().push(entry).is_err() {
+            self.ring.submit()?;
+        }
+        Ok(())
+    }
+
+    fn arm_read(&mut self, index: usize, slot: &mut Slot) -> io::Result<()> {
+        let fd = types::Fd(slot.device.as_raw_fd());
+        let entry = if self.multishot && slot.multishot {
+            opcode::ReadMulti::new(fd, 0, BUF_GROUP).build()
+        } else {
+            opcode::Read::new(fd, ptr::null_mut(), self.buffer_size as u32)
+                .offset(u64::MAX)
+                .buf_group(BUF_GROUP)
+                .build()
+                .flags(squeue::Flags::BUFFER_SELECT)
+        };
+        // SAFETY: Reads select buffers, which live as long as the ring.
+        unsafe { self.push(&entry.user_data(user_data(index, READ, 0))) }?;
+        slot.read_armed = true;
+        slot.in_flight += 1;
+        Ok(())
+    }
+
+    fn arm_write(&mut self, index: usize, slot: &mut Slot) -> io::Result<()> {
+        let data = &slot.writes[0][slot.written..];
+        let entry = opcode::Write::new(
+            types::Fd(slot.device.as_raw_fd()),
+            data.as_ptr(),
+            data.len().try_into().unwrap_or(u32::MAX),
+        )
+        .offset(u64::MAX)
+        .build()
+        // Drivers which can't attempt a write without blocking would
+        // otherwise block the thread submitting it, until the write fits in
+        // the device's buffer, so have a kernel worker perform it.
+        .flags(squeue::Flags::ASYNC)
+        .user_data(user_data(index, WRITE, 0));
+        // SAFETY: `data` stays in `slot.writes` until the write completes,
+        // and removing the device waits for it.
+        unsafe { self.push(&entry) }?;
+        slot.in_flight += 1;
+        Ok(())
+    }
+
+    fn arm_poll(
+        &mut self,
+        index: usize,
+        slot: &mut Slot,
+        tag: u32,
+        interest: Readiness,
+    ) -> io::Result<()> {
+        let fd = types::Fd(slot.device.as_raw_fd());
+        let entry = opcode::PollAdd::new(fd, interest.flags().bits().into())
+            .build()
+            .user_data(user_data(index, POLL, tag));
+        // SAFETY: Polls don't refer to any memory.
+        unsafe { self.push(&entry) }?;
+        slot.in_flight += 1;
+        Ok(())
+    }
+
+    /// Cancel the operation whose `user_data` is `target`.
+    fn cancel(&mut self, target: u64) -> io::Result<()> {
+        let entry = opcode::AsyncCancel::new(target).build().user_data(INTERNAL);
+        // SAFETY: Cancellations don't refer to any memory.
+        unsafe { self.push(&entry) }
+    }
+
+    /// Give buffer `id` back to the kernel, to be read into again.
+    fn provide(&mut self, id: u16) -> io::Result<()> {
+        let start = usize::from(id) * self.buffer_size;
+        let entry = opcode::ProvideBuffers::new(
+            self.buffers[start..].as_mut_ptr(),
+            self.buffer_size as i32,
+            1,
+            BUF_GROUP,
+            id,
+        )
+        .build()
+        .user_data(user_data(0, INTERNAL, u32::from(id) + 1));
+        // SAFETY: The buffers live as long as the ring.
+        unsafe { self.push(&entry) }
+    }
+
+    /// Cancel everything in flight on device `index`, and wait for it to
+    /// finish.
+    fn quiesce(
+        &mut self,
+        slots: &mut [Option<Slot>],
+        index: usize,
+        completions: &mut VecDeque<Completion>,
+    ) -> io::Result<()> {
+        let slot = slots[index].as_mut().unwrap();
+        if slot.read_armed {
+            self.cancel(user_data(index, READ, 0))?;
+        }
+        if !slot.writes.is_empty() {
+            self.cancel(user_data(index, WRITE, 0))?;
+        }
+        for &(tag, _) in &slot.polls {
+            self.cancel(user_data(index, POLL, tag))?;
+        }
+        let mut result = Ok(());
+        while slots[index].as_ref().unwrap().in_flight != 0 {
+            self.wait(None)?;
+            result = result.and(self.process(slots, completions));
+        }
+        result
+    }
+
+    /// Submit any new operations, and wait up to `timeout` for at least one
+    /// completion.
+    fn wait(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        let result = match timeout {
+            Some(timeout) => {
+                let timespec = types::Timespec::from(timeout);
+                let args = types::SubmitArgs::new().timespec(&timespec);
+                self.ring.submitter().submit_with_args(1, &args)
+            }
+            None => self.ring.submit_and_wait(1),
+        };
+        match result {
+            Ok(_) => Ok(()),
+            Err(err)
+                if [Errno::TIME, Errno::INTR, Errno::BUSY]
+                    .iter()
+                    .any(|errno| err.raw_os_error() == Some(errno.raw_os_error())) =>
+            {
+                Ok(())
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Handle everything in the completion queue. If handling any of the
+    /// completions fails, the rest are still handled, and then the first
+    /// error is returned.
+    fn process(
+        &mut self,
+        slots: &mut [Option<Slot>],
+        completions: &mut VecDeque<Completion>,
+    ) -> io::Result<()> {
+        let cqes: Vec<cqueue::Entry> = self.ring.completion().collect();
+        let mut error = None;
+        for cqe in cqes {
+            if let Err(e) = self.process_one(slots, &cqe, completions) {
+                error.get_or_insert(e);
+            }
+        }
+        error.map_or(Ok(()), Err)
+    }
+
+    /// Handle one completion.
+    fn process_one(
+        &mut self,
+        slots: &mut [Option<Slot>],
+        cqe: &cqueue::Entry,
+        completions: &mut VecDeque<Completion>,
+    ) -> io::Result<()> {
+        let kind = cqe.user_data() & ((1 << KIND_BITS) - 1);
+        let index = ((cqe.user_data() as u32) >> KIND_BITS) as usize;
+        let tag = (cqe.user_data() >> TAG_SHIFT) as u32;
+        if kind == INTERNAL {
+            // Cancellations may fail harmlessly, if what they cancel has
+            // already finished. A buffer which couldn't be provided again
+            // would be lost to the pool, so try again, and report it.
+            if tag != 0 && cqe.result() < 0 {
+                self.provide((tag - 1) as u16)?;
+                return Err(io::Error::from_raw_os_error(-cqe.result()));
+            }
+            return Ok(());
+        }
+        let Some(Some(slot)) = slots.get_mut(index) else {
+            return Ok(());
+        };
+        let id = slot.id;
+        let result = match cqe.result() {
+            n if n >= 0 => Ok(n as usize),
+            errno => Err(Errno::from_raw_os_error(-errno)),
+        };
+        match kind {
+            READ => self.complete_read(index, slot, cqe, result, completions)?,
+            WRITE => {
+                slot.in_flight -= 1;
+                if let Some(result) = slot.complete_write(result) {
+                    completions.push_back(Completion::Write(id, result));
+                }
+                if !slot.writes.is_empty() && !slot.removing {
+                    self.arm_write(index, slot)?;
+                }
+            }
+            _ => {
+                slot.in_flight -= 1;
+                if let Some(i) = slot.polls.iter().position(|(t, _)| *t == tag) {
+                    slot.polls.remove(i);
+                }
+                let readiness = result
+                    .map(|flags| Readiness::from_flags(PollFlags::from_bits_truncate(flags as _)));
+                if readiness != Err(Errno::CANCELED) {
+                    completions.push_back(Completion::Ready(id, readiness.map_err(Into::into)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn complete_read(
+        &mut self,
+        index: usize,
+        slot: &mut Slot,
+        cqe: &cqueue::Entry,
+        result: Result<usize, Errno>,
+        completions: &mut VecDeque<Completion>,
+    ) -> io::Result<()> {
+        let id = slot.id;
+        let more = cqueue::more(cqe.flags());
+        if !more {
+            slot.read_armed = false;
+            slot.in_flight -= 1;
+        }
+        let buffer = cqueue::buffer_select(cqe.flags());
+        let data = buffer.map(|buffer| {
+            let start = usize::from(buffer) * self.buffer_size;
+            let len = *result.as_ref().unwrap_or(&0);
+            self.buffers[start..start + len].to_vec()
+        });
+        // If the buffer can't be provided again, still report the data and
+        // keep reading, and then report the error.
+        let provided = buffer.map_or(Ok(()), |buffer| self.provide(buffer));
+
+        match result {
+            Ok(0) => {
+                slot.reading = false;
+                completions.push_back(Completion::Read(id, Ok(Vec::new())));
+            }
+            Ok(_) => completions.push_back(Completion::Read(id, Ok(data.unwrap_or_default()))),
+            // The kernel ran out of buffers; they've been provided again, so
+            // just resubmit.
+            Err(Errno::NOBUFS) => {}
+            // The driver doesn't support polling, which multishot reads need.
+            Err(Errno::BADFD | Errno::INVAL) if self.multishot && slot.multishot => {
+                slot.multishot = false;
+            }
+            Err(Errno::CANCELED) if !slot.reading => {}
+            Err(errno) => {
+                slot.reading = false;
+                completions.push_back(Completion::Read(id, Err(errno.into())));
+            }
+        }
+
+        if slot.read_armed && !slot.reading && more {
+            self.cancel(user_data(index, READ, 0))?;
+        } else if !slot.read_armed && slot.reading {
+            self.arm_read(index, slot)?;
+        }
+        provided
+    }
+}
+
+impl Slot {
+    /// Account for a write of part of the first data in `writes`. Once it's
+    /// all written, or the write fails or writes nothing, remove it, and
+    /// return the result to report.
+    fn complete_write(&mut self, result: Result<usize, Errno>) -> Option<io::Result<usize>> {
+        let len = self.writes[0].len();
+        let result = match result {
+            Ok(n) if n != 0 && self.written + n < len => {
+                self.written += n;
+                return None;
+            }
+            Ok(n) => Ok(self.written + n),
+            Err(_) if self.written != 0 => Ok(self.written),
+            Err(errno) => Err(errno.into()),
+        };
+        self.writes.pop_front();
+        self.written = 0;
+        Some(result)
+    }
+}
+
+/// Wait for devices with `poll`, and perform whatever I/O they're ready
+/// for.
+fn poll(
+    size: usize,
+    slots: &mut [Option<Slot>],
+    completions: &mut VecDeque<Completion>,
+    timeout: Option<Duration>,
+) -> io::Result<()> {
+    let mut indices = Vec::new();
+    let mut fds = Vec::new();
+    for (index, slot) in slots.iter().enumerate() {
+        let Some(slot) = slot else { continue };
+        let mut flags = PollFlags::empty();
+        flags.set(PollFlags::IN, slot.reading);
+        flags.set(PollFlags::OUT, !slot.writes.is_empty());
+        for (_, interest) in &slot.polls {
+            flags |= interest.flags();
+        }
+        if !flags.is_empty() {
+            indices.push(index);
+            fds.push(PollFd::new(&slot.device, flags));
+        }
+    }
+    let timeout = timeout.map(|timeout| Timespec {
+        tv_sec: timeout.as_secs() as _,
+        tv_nsec: timeout.subsec_nanos() as _,
+    });
+    match rustix::event::poll(&mut fds, timeout.as_ref()) {
+        Ok(_) | Err(Errno::INTR) => {}
+        Err(errno) => return Err(errno.into()),
+    }
+    let revents: Vec<PollFlags> = fds.iter().map(PollFd::revents).collect();
+    drop(fds);
+
+    for (index, revents) in indices.into_iter().zip(revents) {
+        let slot = slots[index].as_mut().unwrap();
+        let id = slot.id;
+        let hangup = PollFlags::HUP | PollFlags::ERR;
+        if slot.reading && revents.intersects(PollFlags::IN | hangup) {
+            let mut data = vec![0; size];
+            match rustix::io::read(&slot.device, &mut data) {
+                Ok(n) => {
+                    data.truncate(n);
+                    slot.reading = n != 0;
+                    completions.push_back(Completion::Read(id, Ok(data)));
+                }
+                Err(Errno::AGAIN | Errno::INTR) => {}
+                Err(errno) => {
+                    slot.reading = false;
+                    completions.push_back(Completion::Read(id, Err(errno.into())));
+                }
+            }
+        }
+        if !slot.writes.is_empty() && revents.intersects(PollFlags::OUT | hangup) {
+            // The device is in non-blocking mode, so this writes what fits,
+            // and the rest waits for the next time it's writable.
+            match rustix::io::write(&slot.device, &slot.writes[0][slot.written..]) {
+                Err(Errno::AGAIN | Errno::INTR) => {}
+                result => {
+                    if let Some(result) = slot.complete_write(result) {
+                        completions.push_back(Completion::Write(id, result));
+                    }
+                }
+            }
+        }
+        let readiness = Readiness::from_flags(revents);
+        slot.polls.retain(|(_, interest)| {
+            let ready = revents.intersects(interest.flags() | hangup);
+            if ready {
+                completions.push_back(Completion::Ready(id, Ok(readiness)));
+            }
+            !ready
+        });
+    }
+    Ok(())
+}
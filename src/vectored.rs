@@ -0,0 +1,92 @@
+//! Helpers for vectored I/O on character devices.
+
+use std::io::{self, IoSlice};
+#[cfg(not(windows))]
+use {io_lifetimes::AsFd, std::io::IoSliceMut};
+
+/// The maximum number of buffers passed to a single `readv` or `writev`.
+///
+/// Linux and the BSDs all use 1024. Elsewhere, use the minimum that POSIX
+/// guarantees.
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_vendor = "apple",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "dragonfly",
+))]
+pub(crate) const IOV_MAX: usize = 1024;
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_vendor = "apple",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "dragonfly",
+)))]
+pub(crate) const IOV_MAX: usize = 16;
+
+/// Call `readv` on `fd` with at most `IOV_MAX` of the given buffers.
+#[cfg(not(windows))]
+#[inline]
+pub(crate) fn readv<Fd: AsFd>(fd: Fd, bufs: &mut [IoSliceMut]) -> io::Result<usize> {
+    let len = bufs.len().min(IOV_MAX);
+    Ok(rustix::io::readv(fd, &mut bufs[..len])?)
+}
+
+/// Call `writev` on `fd` with at most `IOV_MAX` of the given buffers.
+#[cfg(not(windows))]
+#[inline]
+pub(crate) fn writev<Fd: AsFd>(fd: Fd, bufs: &[IoSlice]) -> io::Result<usize> {
+    let len = bufs.len().min(IOV_MAX);
+    Ok(rustix::io::writev(fd, &bufs[..len])?)
+}
+
+/// Write all of `bufs` using `write_vectored`, advancing the slices as data
+/// is written.
+///
+/// This is equivalent to the unstable `Write::write_all_vectored`.
+pub(crate) fn write_all_vectored<W: io::Write + ?Sized>(
+    writer: &mut W,
+    mut bufs: &mut [IoSlice],
+) -> io::Result<()> {
+    // Skip any leading empty buffers, so that an entirely empty input doesn't
+    // look like a zero-length write.
+    IoSlice::advance_slices(&mut bufs, 0);
+    while !bufs.is_empty() {
+        match writer.write_vectored(bufs) {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    "failed to write whole buffer",
+                ))
+            }
+            Ok(n) => IoSlice::advance_slices(&mut bufs, n),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Concatenate `bufs` into a single buffer, for async backends which can only
+/// submit one contiguous buffer per operation.
+///
+/// Returns `None` if there's at most one non-empty buffer, in which case the
+/// caller can just write that buffer directly.
+#[cfg(any(feature = "async-std", feature = "tokio"))]
+pub(crate) fn coalesce(bufs: &[IoSlice]) -> Option<Vec<u8>> {
+    let bufs = &bufs[..bufs.len().min(IOV_MAX)];
+    if bufs.iter().filter(|buf| !buf.is_empty()).count() <= 1 {
+        return None;
+    }
+    let len = bufs.iter().map(|buf| buf.len()).sum();
+    let mut data = Vec::with_capacity(len);
+    for buf in bufs {
+        data.extend_from_slice(buf);
+    }
+    Some(data)
+}
@@ -0,0 +1,62 @@
+mod common;
+
+#[cfg(feature = "async-std")]
+use char_device::AsyncStdCharDevice;
+#[cfg(feature = "tokio")]
+use char_device::TokioCharDevice;
+use char_device::{CharDevice, CloseBehavior};
+use std::time::Duration;
+
+#[test]
+fn close() {
+    CharDevice::null().unwrap().close().unwrap();
+    CharDevice::null()
+        .unwrap()
+        .close_with(CloseBehavior::Drain(Duration::from_secs(1)))
+        .unwrap();
+    CharDevice::null()
+        .unwrap()
+        .close_with(CloseBehavior::DiscardPending)
+        .unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn close_pty() {
+    use std::io::Write;
+
+    let (_master, name) = common::pty();
+
+    let mut slave = CharDevice::open(&name).unwrap();
+    slave.write_all(b"hello\n").unwrap();
+    slave
+        .close_with(CloseBehavior::Drain(Duration::from_secs(1)))
+        .unwrap();
+
+    let mut slave = CharDevice::open(&name).unwrap();
+    slave.write_all(b"hello\n").unwrap();
+    slave.close_with(CloseBehavior::DiscardPending).unwrap();
+}
+
+#[cfg(feature = "async-std")]
+#[async_std::test]
+async fn async_std_close() {
+    use async_std::io::WriteExt;
+
+    let mut char_device = AsyncStdCharDevice::null().await.unwrap();
+    char_device.write_all(b"abcdefg").await.unwrap();
+    char_device.close().await.unwrap();
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn tokio_close() {
+    use tokio::io::AsyncWriteExt;
+
+    let mut char_device = TokioCharDevice::null().await.unwrap();
+    char_device.write_all(b"abcdefg").await.unwrap();
+    char_device
+        .close_with(CloseBehavior::Drain(Duration::from_secs(1)))
+        .await
+        .unwrap();
+}
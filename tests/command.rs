@@ -0,0 +1,77 @@
+#![cfg(unix)]
+
+mod common;
+
+use char_device::command::{attach, attach_controlling_terminal, Streams};
+use char_device::CharDevice;
+use std::io::Read;
+use std::process::Command;
+
+#[test]
+fn stdio() {
+    let status = Command::new("sh")
+        .args(["-c", "echo hello"])
+        .stdout(CharDevice::null().unwrap())
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let mut command = Command::new("sh");
+    command.args(["-c", "cat; echo hello >&2"]);
+    attach(&mut command, &CharDevice::null().unwrap(), Streams::ALL).unwrap();
+    assert!(command.status().unwrap().success());
+}
+
+#[test]
+fn controlling_terminal() {
+    let (mut master, slave) = common::pty_device();
+
+    // "/dev/tty" refers to the controlling terminal, which is the pty.
+    let mut command = Command::new("sh");
+    command.args(["-c", "echo hello > /dev/tty"]);
+    attach_controlling_terminal(&mut command, &slave, Streams::STDIN).unwrap();
+    assert!(command.status().unwrap().success());
+
+    let mut buf = [0_u8; 5];
+    master.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"hello");
+}
+
+#[test]
+fn stdio_keeps_lock_file() {
+    use char_device::lockfile::LockDir;
+    use char_device::CharDeviceOptions;
+
+    let dir = common::temp_dir("stdio-lock");
+    let (_master, name) = common::pty();
+    let device = CharDeviceOptions::new()
+        .lock_file(Some(LockDir::with_dir(&dir)))
+        .open(&name)
+        .unwrap();
+    let lock_path = LockDir::with_dir(&dir).lock_path(&name).unwrap();
+
+    let (stdio, lock_file) = device.into_stdio();
+    let mut child = Command::new("sleep")
+        .arg("0.1")
+        .stdin(stdio)
+        .spawn()
+        .unwrap();
+    assert!(lock_path.exists());
+    assert!(child.wait().unwrap().success());
+    drop(lock_file);
+    assert!(!lock_path.exists());
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn tokio_stdio() {
+    use char_device::TokioCharDevice;
+
+    let stdout = TokioCharDevice::null().await.unwrap();
+    let status = Command::new("sh")
+        .args(["-c", "echo hello"])
+        .stdout(stdout.into_stdio().await.unwrap().0)
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
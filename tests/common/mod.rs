@@ -0,0 +1,65 @@
+//! Helpers shared by the tests.
+
+// Each test crate uses only some of these.
+#![allow(dead_code)]
+
+#[cfg(unix)]
+use {
+    char_device::CharDevice,
+    rustix::fd::AsFd,
+    rustix::termios::{tcgetattr, tcsetattr, LocalModes, OptionalActions},
+    std::fs::File,
+};
+
+/// Open a pty, and return its master side and the path of its slave side.
+#[cfg(unix)]
+pub fn pty() -> (File, String) {
+    use rustix::pty::{grantpt, openpt, ptsname, unlockpt, OpenptFlags};
+
+    let master = openpt(OpenptFlags::RDWR | OpenptFlags::NOCTTY).unwrap();
+    grantpt(&master).unwrap();
+    unlockpt(&master).unwrap();
+    let name = ptsname(&master, Vec::new()).unwrap();
+    (File::from(master), name.into_string().unwrap())
+}
+
+/// Open a pty, and return its master side and its slave side.
+#[cfg(unix)]
+pub fn pty_device() -> (File, CharDevice) {
+    let (master, name) = pty();
+    (master, CharDevice::open(name).unwrap())
+}
+
+/// Open a pty with echoing turned off, so that its master side only reads
+/// what's written to its slave side.
+#[cfg(unix)]
+pub fn pty_without_echo() -> (File, CharDevice) {
+    let (master, slave) = pty_device();
+    disable_echo(&slave);
+    (master, slave)
+}
+
+/// Open a pty in raw mode, so that bytes pass through it unchanged.
+#[cfg(unix)]
+pub fn raw_pty() -> (File, CharDevice) {
+    let (master, slave) = pty_device();
+    let mut termios = tcgetattr(&slave).unwrap();
+    termios.make_raw();
+    tcsetattr(&slave, OptionalActions::Now, &termios).unwrap();
+    (master, slave)
+}
+
+/// Turn off echoing on the terminal `fd`.
+#[cfg(unix)]
+pub fn disable_echo<Fd: AsFd>(fd: Fd) {
+    let mut termios = tcgetattr(&fd).unwrap();
+    termios.local_modes -= LocalModes::ECHO;
+    tcsetattr(&fd, OptionalActions::Now, &termios).unwrap();
+}
+
+/// Create a directory for the test `name` to put files in.
+pub fn temp_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("char-device-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -0,0 +1,108 @@
+#![cfg(unix)]
+
+mod common;
+
+#[cfg(feature = "async-std")]
+use char_device::AsyncStdCharDevice;
+#[cfg(feature = "tokio")]
+use char_device::TokioCharDevice;
+use char_device::{CharDevice, ReadEvent};
+use rustix::io::Errno;
+use std::io::{self, Write};
+
+#[test]
+fn classify() {
+    let nodev = io::Error::from_raw_os_error(Errno::NODEV.raw_os_error());
+    assert_eq!(
+        ReadEvent::from_result(Err(nodev)).unwrap(),
+        ReadEvent::Disconnected
+    );
+    let io = io::Error::from_raw_os_error(Errno::IO.raw_os_error());
+    assert_eq!(ReadEvent::from_result(Err(io)).unwrap(), ReadEvent::Hangup);
+    let again = io::Error::from(io::ErrorKind::WouldBlock);
+    assert!(ReadEvent::from_result(Err(again)).is_err());
+    assert_eq!(ReadEvent::from_result(Ok(0)).unwrap(), ReadEvent::Eof);
+    assert_eq!(ReadEvent::from_result(Ok(3)).unwrap(), ReadEvent::Data(3));
+}
+
+#[test]
+fn eof() {
+    let mut null = CharDevice::null().unwrap();
+    assert_eq!(null.read_event(&mut [0; 4]).unwrap(), ReadEvent::Eof);
+}
+
+#[test]
+fn hangup() {
+    let (master, mut slave) = common::pty_device();
+    let mut master = CharDevice::new(master).unwrap();
+
+    slave.write_all(b"hi").unwrap();
+    let mut buf = [0; 4];
+    assert_eq!(master.read_event(&mut buf).unwrap(), ReadEvent::Data(2));
+
+    drop(slave);
+    assert_eq!(master.read_event(&mut buf).unwrap(), ReadEvent::Hangup);
+}
+
+#[test]
+fn master_closed() {
+    let (master, mut slave) = common::pty_device();
+
+    // The slave side sees end of file, and writes to it fail.
+    drop(master);
+    let mut buf = [0; 4];
+    assert_eq!(slave.read_event(&mut buf).unwrap(), ReadEvent::Eof);
+    let err = slave.write(b"hi").unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(Errno::IO.raw_os_error()));
+}
+
+#[test]
+fn empty_buffer() {
+    // Reading into an empty buffer returns 0, which isn't end of file.
+    let (master, mut slave) = common::pty_device();
+    assert_eq!(slave.read_event(&mut []).unwrap(), ReadEvent::Data(0));
+    let mut master = CharDevice::new(master).unwrap();
+    assert_eq!(master.read_event(&mut []).unwrap(), ReadEvent::Data(0));
+
+    // Even after a hangup, which a non-empty read reports.
+    drop(slave);
+    assert_eq!(master.read_event(&mut []).unwrap(), ReadEvent::Data(0));
+    assert_eq!(master.read_event(&mut [0; 4]).unwrap(), ReadEvent::Hangup);
+}
+
+#[cfg(feature = "async-std")]
+#[async_std::test]
+async fn async_std_hangup() {
+    let (master, slave) = common::pty_device();
+    let mut master = AsyncStdCharDevice::new(async_std::fs::File::from(master))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        master.read_event(&mut []).await.unwrap(),
+        ReadEvent::Data(0)
+    );
+    drop(slave);
+    assert_eq!(
+        master.read_event(&mut [0; 4]).await.unwrap(),
+        ReadEvent::Hangup
+    );
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn tokio_hangup() {
+    let (master, slave) = common::pty_device();
+    // SAFETY: `master` is a pty master, which is a character device.
+    let mut master = unsafe { TokioCharDevice::new_unchecked(master) };
+
+    assert_eq!(
+        master.read_event(&mut []).await.unwrap(),
+        ReadEvent::Data(0)
+    );
+    drop(slave);
+    assert_eq!(
+        master.read_event(&mut [0; 4]).await.unwrap(),
+        ReadEvent::Hangup
+    );
+}
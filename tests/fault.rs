@@ -0,0 +1,259 @@
+#![cfg(unix)]
+
+mod common;
+
+use char_device::fault::{Fault, FaultPolicy, FaultyDevice, Op};
+use char_device::{CharDevice, ReadEvent};
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+#[test]
+fn schedule() {
+    let mut policy = FaultPolicy::new();
+    policy
+        .at(Op::Write, 0, Fault::Interrupted)
+        .at(Op::Write, 1, Fault::Short(3))
+        .at(Op::Write, 2, Fault::WouldBlock)
+        .at(Op::Read, 0, Fault::Io)
+        .at(Op::Read, 2, Fault::Short(1));
+    let mut device = FaultyDevice::new(CharDevice::open("/dev/zero").unwrap(), policy);
+
+    let err = device.write(b"abcdefg").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+    assert_eq!(device.write(b"abcdefg").unwrap(), 3);
+    let err = device.write(b"abcdefg").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+    assert_eq!(device.write(b"abcdefg").unwrap(), 7);
+
+    let mut buf = [1; 8];
+    let err = device.read(&mut buf).unwrap_err();
+    assert_eq!(
+        err.raw_os_error(),
+        Some(rustix::io::Errno::IO.raw_os_error())
+    );
+    assert_eq!(device.read(&mut buf).unwrap(), 8);
+    assert_eq!(device.read(&mut buf).unwrap(), 1);
+}
+
+#[test]
+fn write_all_recovers() {
+    let mut policy = FaultPolicy::new();
+    policy
+        .at(Op::Write, 0, Fault::Short(2))
+        .at(Op::Write, 1, Fault::Interrupted)
+        .at(Op::Write, 2, Fault::Short(1));
+    let mut device = FaultyDevice::new(CharDevice::null().unwrap(), policy);
+    device.write_all(b"abcdefg").unwrap();
+}
+
+#[test]
+fn short_zero() {
+    let mut policy = FaultPolicy::new();
+    policy
+        .at(Op::Read, 0, Fault::Short(0))
+        .at(Op::Write, 0, Fault::Short(0));
+    let mut device = FaultyDevice::new(CharDevice::open("/dev/zero").unwrap(), policy);
+
+    let mut buf = [1; 4];
+    assert_eq!(
+        ReadEvent::from_result(device.read(&mut buf)).unwrap(),
+        ReadEvent::Eof
+    );
+    let err = device.write_all(b"abc").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+}
+
+#[test]
+fn hangup() {
+    let mut policy = FaultPolicy::new();
+    policy.at(Op::Read, 1, Fault::Hangup);
+    let mut device = FaultyDevice::new(CharDevice::open("/dev/zero").unwrap(), policy);
+
+    let mut buf = [0; 4];
+    assert_eq!(
+        ReadEvent::from_result(device.read(&mut buf)).unwrap(),
+        ReadEvent::Data(4)
+    );
+    for _ in 0..3 {
+        assert_eq!(
+            ReadEvent::from_result(device.read(&mut buf)).unwrap(),
+            ReadEvent::Hangup
+        );
+    }
+    assert!(device.write(b"abc").is_err());
+}
+
+#[test]
+fn delay() {
+    let mut policy = FaultPolicy::new();
+    policy.at(Op::Read, 0, Fault::Delay(Duration::from_millis(50)));
+    let mut device = FaultyDevice::new(CharDevice::open("/dev/zero").unwrap(), policy);
+
+    let start = Instant::now();
+    assert_eq!(device.read(&mut [0; 4]).unwrap(), 4);
+    assert!(start.elapsed() >= Duration::from_millis(50));
+}
+
+/// Record which writes fail under a random policy.
+fn random_run(seed: u64) -> Vec<bool> {
+    let mut policy = FaultPolicy::new();
+    policy.random(seed, 0.25, &[Fault::Interrupted, Fault::Io]);
+    let mut device = FaultyDevice::new(CharDevice::null().unwrap(), policy);
+    (0..200).map(|_| device.write(b"x").is_err()).collect()
+}
+
+#[test]
+fn random() {
+    let run = random_run(7);
+    assert_eq!(random_run(7), run);
+    assert_ne!(random_run(8), run);
+
+    let failures = run.iter().filter(|failed| **failed).count();
+    assert!(failures > 20 && failures < 80, "{} failures", failures);
+
+    // A rate of zero injects nothing.
+    let mut policy = FaultPolicy::new();
+    policy.random(7, 0.0, &[Fault::Io]);
+    let mut device = FaultyDevice::new(CharDevice::null().unwrap(), policy);
+    for _ in 0..100 {
+        device.write_all(b"x").unwrap();
+    }
+}
+
+#[cfg(feature = "async-std")]
+#[async_std::test]
+async fn async_std_faults() {
+    use async_std::io::prelude::{ReadExt, WriteExt};
+    use char_device::AsyncStdCharDevice;
+
+    let mut policy = FaultPolicy::new();
+    policy
+        .at(Op::Write, 0, Fault::Short(2))
+        .at(Op::Write, 1, Fault::Interrupted)
+        .at(Op::Read, 0, Fault::Delay(Duration::from_millis(50)))
+        .at(Op::Read, 1, Fault::Short(3));
+    let device = AsyncStdCharDevice::open("/dev/zero").await.unwrap();
+    let mut device = FaultyDevice::new(device, policy);
+
+    assert_eq!(device.write(b"abcdefg").await.unwrap(), 2);
+    let err = device.write(b"cdefg").await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+    device.write_all(b"cdefg").await.unwrap();
+
+    let start = Instant::now();
+    let mut buf = [1; 8];
+    assert_eq!(device.read(&mut buf).await.unwrap(), 8);
+    assert!(start.elapsed() >= Duration::from_millis(50));
+    assert_eq!(device.read(&mut buf).await.unwrap(), 3);
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn tokio_faults() {
+    use char_device::TokioCharDevice;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let mut policy = FaultPolicy::new();
+    policy
+        .at(Op::Write, 0, Fault::Short(2))
+        .at(Op::Write, 1, Fault::Interrupted)
+        .at(Op::Read, 0, Fault::Delay(Duration::from_millis(50)))
+        .at(Op::Read, 1, Fault::Short(3))
+        .at(Op::Read, 2, Fault::WouldBlock);
+    let device = TokioCharDevice::open("/dev/zero").await.unwrap();
+    let mut device = FaultyDevice::new(device, policy);
+
+    assert_eq!(device.write(b"abcdefg").await.unwrap(), 2);
+    let err = device.write(b"cdefg").await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+    device.write_all(b"cdefg").await.unwrap();
+
+    let start = Instant::now();
+    let mut buf = [1; 8];
+    assert_eq!(device.read(&mut buf).await.unwrap(), 8);
+    assert!(start.elapsed() >= Duration::from_millis(50));
+    assert_eq!(device.read(&mut buf).await.unwrap(), 3);
+    let err = device.read(&mut buf).await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn tokio_inner_error() {
+    use char_device::TokioCharDevice;
+    use tokio::io::AsyncReadExt;
+
+    let mut inner = FaultPolicy::new();
+    inner.at(Op::Read, 0, Fault::Io);
+    let device = TokioCharDevice::open("/dev/zero").await.unwrap();
+    let device = FaultyDevice::new(device, inner);
+
+    // A failure of the wrapped device ends the read, so the next read gets
+    // its own fault.
+    let mut outer = FaultPolicy::new();
+    outer
+        .at(Op::Read, 0, Fault::Short(3))
+        .at(Op::Read, 1, Fault::Interrupted);
+    let mut device = FaultyDevice::new(device, outer);
+
+    let mut buf = [1; 8];
+    let err = device.read(&mut buf).await.unwrap_err();
+    assert_eq!(
+        err.raw_os_error(),
+        Some(rustix::io::Errno::IO.raw_os_error())
+    );
+    let err = device.read(&mut buf).await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+    assert_eq!(device.read(&mut buf).await.unwrap(), 8);
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn tokio_short_zero() {
+    use char_device::TokioCharDevice;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let mut policy = FaultPolicy::new();
+    policy
+        .at(Op::Read, 0, Fault::Short(0))
+        .at(Op::Write, 0, Fault::Short(0));
+    // Nothing is ever written to the pty, so it never becomes readable.
+    let (_master, name) = common::pty();
+    let device = TokioCharDevice::open(name).await.unwrap();
+    let mut device = FaultyDevice::new(device, policy);
+
+    let mut buf = [1; 8];
+    assert_eq!(device.read(&mut buf).await.unwrap(), 0);
+    assert_eq!(device.write(b"hello").await.unwrap(), 0);
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn tokio_delay_new_waker() {
+    use char_device::TokioCharDevice;
+    use std::future::Future;
+    use std::task::{Context, Waker};
+    use tokio::io::AsyncReadExt;
+
+    let mut policy = FaultPolicy::new();
+    policy.at(Op::Read, 0, Fault::Delay(Duration::from_millis(50)));
+    let device = TokioCharDevice::open("/dev/zero").await.unwrap();
+    let mut device = FaultyDevice::new(device, policy);
+
+    // The first poll comes from a task which then loses interest, so the
+    // task which polls next must be the one woken.
+    let mut buf = [1; 8];
+    let mut read = Box::pin(device.read(&mut buf));
+    let mut cx = Context::from_waker(Waker::noop());
+    let start = Instant::now();
+    assert!(read.as_mut().poll(&mut cx).is_pending());
+    let n = tokio::time::timeout(Duration::from_secs(5), read)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(
+        start.elapsed() < Duration::from_secs(1),
+        "the read wasn't woken"
+    );
+    assert_eq!(n, 8);
+}
@@ -0,0 +1,218 @@
+#![cfg(unix)]
+
+mod common;
+
+use char_device::hub::{CharDeviceHub, LagPolicy, Lagged};
+use char_device::CharDevice;
+use std::fs::File;
+use std::io::{Read, Write};
+
+/// Write `line` to the device, and wait until `sync`, the most recent
+/// subscriber, has received it.
+fn send(master: &mut File, sync: &mut impl Read, line: &[u8]) {
+    master.write_all(line).unwrap();
+    let mut buf = vec![0; line.len()];
+    sync.read_exact(&mut buf).unwrap();
+    assert_eq!(buf, line);
+}
+
+#[test]
+fn broadcast() {
+    let (mut master, slave) = common::pty_without_echo();
+    let hub = CharDeviceHub::new(slave, 1024, LagPolicy::Error).unwrap();
+    let mut a = hub.subscribe();
+    let mut b = hub.subscribe();
+
+    send(&mut master, &mut a, b"hello\n");
+    let mut buf = [0; 6];
+    b.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"hello\n");
+
+    a.write_all(b"ping\n").unwrap();
+    hub.write_all(b"pong\n").unwrap();
+    let mut buf = [0; 12];
+    master.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"ping\r\npong\r\n");
+
+    // Depending on timing, the hub sees the pty hang up as either an error or
+    // the end of file, and either way subscribers see the same.
+    drop(master);
+    let a_result = a.read(&mut buf).ok();
+    assert!(matches!(a_result, None | Some(0)));
+    assert_eq!(b.read(&mut buf).ok(), a_result);
+}
+
+#[test]
+fn lag_error() {
+    let (mut master, slave) = common::pty_without_echo();
+    let hub = CharDeviceHub::new(slave, 8, LagPolicy::Error).unwrap();
+    // The hub delivers to subscribers in the order they subscribed, so once
+    // the last one has received something, all of them have.
+    let mut slow = hub.subscribe();
+    let mut sync = hub.subscribe();
+
+    send(&mut master, &mut sync, b"abc\n");
+    send(&mut master, &mut sync, b"defgh\n");
+
+    let mut buf = [0; 16];
+    assert_eq!(slow.read(&mut buf).unwrap(), 4);
+    assert_eq!(&buf[..4], b"abc\n");
+    let err = slow.read(&mut buf).unwrap_err();
+    let lagged = err.get_ref().unwrap().downcast_ref::<Lagged>().unwrap();
+    assert_eq!(lagged.missed(), 6);
+
+    send(&mut master, &mut sync, b"hi\n");
+    assert_eq!(slow.read(&mut buf).unwrap(), 3);
+    assert_eq!(&buf[..3], b"hi\n");
+}
+
+#[test]
+fn lag_drop_oldest() {
+    let (mut master, slave) = common::pty_without_echo();
+    let hub = CharDeviceHub::new(slave, 8, LagPolicy::DropOldest).unwrap();
+    // The hub delivers to subscribers in the order they subscribed, so once
+    // the last one has received something, all of them have.
+    let mut slow = hub.subscribe();
+    let mut sync = hub.subscribe();
+
+    send(&mut master, &mut sync, b"abc\n");
+    send(&mut master, &mut sync, b"defgh\n");
+
+    let mut buf = [0; 16];
+    assert_eq!(slow.read(&mut buf).unwrap(), 8);
+    assert_eq!(&buf[..8], b"c\ndefgh\n");
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn tokio_broadcast() {
+    use char_device::hub::TokioCharDeviceHub;
+    use char_device::TokioCharDevice;
+    use tokio::io::AsyncReadExt;
+
+    let (mut master, slave) = common::pty_without_echo();
+    // SAFETY: `slave` is a character device.
+    let slave = unsafe { TokioCharDevice::new_unchecked(slave) };
+    let hub = TokioCharDeviceHub::new(slave, 1024, LagPolicy::Error).unwrap();
+    let mut a = hub.subscribe();
+    let mut b = hub.subscribe();
+
+    master.write_all(b"hello\n").unwrap();
+    let mut buf = [0; 6];
+    a.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello\n");
+    b.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello\n");
+
+    a.write_all(b"ping\n").await.unwrap();
+    hub.write_all(b"pong\n").await.unwrap();
+    let mut buf = [0; 12];
+    master.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"ping\r\npong\r\n");
+}
+
+#[test]
+fn nonblocking() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    // Race another reader for the device, so that the hub is sometimes woken
+    // with nothing left to read, which a non-blocking device reports with
+    // `WouldBlock`.
+    let (mut master, slave) = common::pty_without_echo();
+    rustix::io::ioctl_fionbio(&slave, true).unwrap();
+    let mut other = slave.try_clone().unwrap();
+    let hub = CharDeviceHub::new(slave, 1024, LagPolicy::DropOldest).unwrap();
+    let mut subscriber = hub.subscribe();
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let thief = std::thread::spawn({
+        let stop = Arc::clone(&stop);
+        move || {
+            while !stop.load(Ordering::Relaxed) {
+                let _ = other.read(&mut [0; 64]);
+            }
+        }
+    });
+    for _ in 0..1000 {
+        master.write_all(b"x\n").unwrap();
+        std::thread::yield_now();
+    }
+    stop.store(true, Ordering::Relaxed);
+    thief.join().unwrap();
+
+    // The hub is still reading.
+    master.write_all(b"done\n").unwrap();
+    let mut received = Vec::new();
+    while !received.ends_with(b"done\n") {
+        let mut buf = [0; 64];
+        let n = subscriber.read(&mut buf).unwrap();
+        assert_ne!(n, 0);
+        received.extend_from_slice(&buf[..n]);
+    }
+}
+
+/// Wait until every descriptor for the slave side of the pty with master
+/// side `master` has been closed.
+fn wait_for_hangup(master: &File) {
+    use rustix::event::{poll, PollFd, PollFlags, Timespec};
+
+    let mut fds = [PollFd::new(master, PollFlags::IN)];
+    let timeout = Timespec {
+        tv_sec: 5,
+        tv_nsec: 0,
+    };
+    poll(&mut fds, Some(&timeout)).unwrap();
+    assert!(fds[0].revents().contains(PollFlags::HUP));
+}
+
+#[test]
+fn stop_on_drop() {
+    let (master, slave) = common::pty_without_echo();
+    let hub = CharDeviceHub::new(slave, 8, LagPolicy::Error).unwrap();
+    let subscriber = hub.subscribe();
+
+    // The reader stops, closing its handle for the device, without waiting
+    // for more input.
+    drop(hub);
+    drop(subscriber);
+    wait_for_hangup(&master);
+}
+
+#[test]
+fn closed_error() {
+    use char_device::ReadEvent;
+
+    // Read from the master side, which fails with `EIO` once the slave side
+    // is closed.
+    let (master, slave) = common::pty_without_echo();
+    let master = CharDevice::new(master).unwrap();
+    let hub = CharDeviceHub::new(master, 8, LagPolicy::Error).unwrap();
+    let mut subscriber = hub.subscribe();
+
+    drop(slave);
+    let result = subscriber.read(&mut [0; 8]);
+    assert_eq!(ReadEvent::from_result(result).unwrap(), ReadEvent::Hangup);
+    // Later subscribers see the same error.
+    let result = hub.subscribe().read(&mut [0; 8]);
+    assert_eq!(ReadEvent::from_result(result).unwrap(), ReadEvent::Hangup);
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn tokio_stop_on_drop() {
+    use char_device::hub::TokioCharDeviceHub;
+    use char_device::TokioCharDevice;
+
+    let (master, slave) = common::pty_without_echo();
+    // SAFETY: `slave` is a character device.
+    let slave = unsafe { TokioCharDevice::new_unchecked(slave) };
+    let hub = TokioCharDeviceHub::new(slave, 8, LagPolicy::Error).unwrap();
+    let subscriber = hub.subscribe();
+
+    drop(hub);
+    drop(subscriber);
+    // Let the reader task notice.
+    tokio::task::yield_now().await;
+    wait_for_hangup(&master);
+}
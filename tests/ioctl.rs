@@ -0,0 +1,44 @@
+#![cfg(unix)]
+
+mod common;
+
+use char_device::ioctl::{Winsize, TIOCGWINSZ, TIOCSWINSZ};
+use char_device::CharDevice;
+
+char_device::ioctl! {
+    /// Get the evdev driver version.
+    // SAFETY: `EVIOCGVERSION` reads an `int`.
+    const EVIOCGVERSION: Ioctl<(), i32> = unsafe { read(b'E', 0x01) };
+}
+
+#[test]
+fn encoding() {
+    // `_IOR('E', 0x01, int)` on the common Linux encoding.
+    #[cfg(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "x86", target_arch = "aarch64")
+    ))]
+    assert_eq!(EVIOCGVERSION.opcode(), 0x8004_4501);
+
+    let _ = EVIOCGVERSION;
+}
+
+#[test]
+fn winsize() {
+    let (_master, slave) = common::pty_device();
+
+    let winsize = Winsize {
+        ws_row: 24,
+        ws_col: 80,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    slave.ioctl_with(&TIOCSWINSZ, winsize).unwrap();
+    assert_eq!(slave.ioctl(&TIOCGWINSZ).unwrap(), winsize);
+}
+
+#[test]
+fn not_a_tty() {
+    let null = CharDevice::null().unwrap();
+    assert!(null.ioctl(&TIOCGWINSZ).is_err());
+}
@@ -0,0 +1,235 @@
+use char_device::link::{Parity, SimulatedLink};
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+#[test]
+fn byte_time() {
+    assert_eq!(SimulatedLink::new().byte_time(), Duration::ZERO);
+    let mut link = SimulatedLink::new();
+    assert_eq!(
+        link.baud(9600).byte_time(),
+        Duration::from_nanos(10 * 1_000_000_000 / 9600)
+    );
+    assert_eq!(
+        link.baud(1200).frame(7, Parity::Even, 2).byte_time(),
+        Duration::from_nanos(11 * 1_000_000_000 / 1200)
+    );
+}
+
+#[test]
+fn throughput() {
+    let (mut a, mut b) = SimulatedLink::new().baud(9600).pair();
+
+    let start = Instant::now();
+    a.write_all(&[b'x'; 96]).unwrap();
+    let mut buf = [0; 96];
+    b.read_exact(&mut buf).unwrap();
+    let elapsed = start.elapsed();
+    assert_eq!(buf, [b'x'; 96]);
+    assert!(elapsed >= Duration::from_millis(99), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_secs(1), "{:?}", elapsed);
+}
+
+#[test]
+fn latency() {
+    let (mut a, mut b) = SimulatedLink::new()
+        .latency(Duration::from_millis(50))
+        .pair();
+
+    let start = Instant::now();
+    b.write_all(b"hello").unwrap();
+    let mut buf = [0; 5];
+    a.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"hello");
+    assert!(start.elapsed() >= Duration::from_millis(50));
+}
+
+#[test]
+fn hangup() {
+    let (mut a, mut b) = SimulatedLink::new()
+        .latency(Duration::from_millis(10))
+        .pair();
+
+    a.write_all(b"bye").unwrap();
+    drop(a);
+    let mut buf = Vec::new();
+    b.read_to_end(&mut buf).unwrap();
+    assert_eq!(buf, b"bye");
+    assert_eq!(b.write(b"x").unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+}
+
+/// Send `data` over a link with the given bit-error rate, returning what
+/// arrives.
+#[test]
+fn buffer_size() {
+    let (mut a, mut b) = SimulatedLink::new().buffer_size(8).pair();
+
+    let writer = std::thread::spawn(move || {
+        a.write_all(&[7; 20]).unwrap();
+        a
+    });
+    std::thread::sleep(Duration::from_millis(50));
+    assert!(!writer.is_finished());
+
+    let mut buf = [0; 20];
+    b.read_exact(&mut buf).unwrap();
+    assert_eq!(buf, [7; 20]);
+    writer.join().unwrap();
+}
+
+#[test]
+fn buffer_full_hangup() {
+    let (mut a, b) = SimulatedLink::new().buffer_size(4).pair();
+    let reader = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(20));
+        drop(b);
+    });
+    let err = a.write_all(&[0; 8]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+    reader.join().unwrap();
+}
+
+fn corrupt(link: &mut SimulatedLink, data: &[u8]) -> Vec<u8> {
+    let (mut a, mut b) = link.pair();
+    a.write_all(data).unwrap();
+    drop(a);
+    let mut buf = Vec::new();
+    b.read_to_end(&mut buf).unwrap();
+    buf
+}
+
+#[test]
+fn bit_errors() {
+    let data = [0x55; 1000];
+
+    let received = corrupt(SimulatedLink::new().bit_error_rate(0.01, 1), &data);
+    assert_eq!(received.len(), data.len());
+    let flipped: u32 = received
+        .iter()
+        .zip(&data)
+        .map(|(r, d)| (r ^ d).count_ones())
+        .sum();
+    assert!(flipped > 40 && flipped < 120, "{} bits flipped", flipped);
+
+    // The same seed corrupts the same bits.
+    assert_eq!(
+        corrupt(SimulatedLink::new().bit_error_rate(0.01, 1), &data),
+        received
+    );
+
+    // With 7 data bits, the high bit is lost, and never flipped back on.
+    let received = corrupt(
+        SimulatedLink::new()
+            .frame(7, Parity::Odd, 1)
+            .bit_error_rate(0.5, 1),
+        &[0xff; 100],
+    );
+    assert!(received.iter().all(|byte| byte & 0x80 == 0));
+}
+
+#[cfg(unix)]
+#[test]
+fn pty() {
+    let (mut a, mut b) = SimulatedLink::new().baud(19200).pty().unwrap();
+
+    // The ptys are raw, so bytes pass through unchanged.
+    let start = Instant::now();
+    a.write_all(b"\r\nhello\x03\n").unwrap();
+    let mut buf = [0; 9];
+    b.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"\r\nhello\x03\n");
+    assert!(start.elapsed() >= Duration::from_nanos(9 * 10 * 1_000_000_000 / 19200));
+
+    b.write_all(b"ok").unwrap();
+    let mut buf = [0; 2];
+    a.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"ok");
+}
+
+#[cfg(feature = "async-std")]
+#[async_std::test]
+async fn async_std_link() {
+    use async_std::io::prelude::{ReadExt, WriteExt};
+
+    let (mut a, mut b) = SimulatedLink::new()
+        .baud(9600)
+        .latency(Duration::from_millis(20))
+        .pair();
+
+    let start = Instant::now();
+    WriteExt::write_all(&mut a, b"ping").await.unwrap();
+    let mut buf = [0; 4];
+    ReadExt::read_exact(&mut b, &mut buf).await.unwrap();
+    assert_eq!(&buf, b"ping");
+    assert!(start.elapsed() >= Duration::from_millis(20));
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn tokio_link() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let (mut a, mut b) = SimulatedLink::new()
+        .baud(9600)
+        .latency(Duration::from_millis(20))
+        .pair();
+
+    let start = Instant::now();
+    AsyncWriteExt::write_all(&mut a, b"ping").await.unwrap();
+    let mut buf = [0; 4];
+    AsyncReadExt::read_exact(&mut b, &mut buf).await.unwrap();
+    assert_eq!(&buf, b"ping");
+    assert!(start.elapsed() >= Duration::from_millis(20));
+
+    AsyncWriteExt::shutdown(&mut b).await.unwrap();
+    assert_eq!(AsyncReadExt::read(&mut a, &mut buf).await.unwrap(), 0);
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn tokio_buffer_size() {
+    use futures_lite::future::poll_once;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let (mut a, mut b) = SimulatedLink::new().buffer_size(4).pair();
+
+    assert_eq!(AsyncWriteExt::write(&mut a, b"abcdef").await.unwrap(), 4);
+    assert!(poll_once(AsyncWriteExt::write(&mut a, b"ef"))
+        .await
+        .is_none());
+
+    let mut buf = [0; 4];
+    AsyncReadExt::read_exact(&mut b, &mut buf).await.unwrap();
+    assert_eq!(&buf, b"abcd");
+    assert_eq!(AsyncWriteExt::write(&mut a, b"ef").await.unwrap(), 2);
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn tokio_link_new_waker() {
+    use std::future::Future;
+    use std::task::{Context, Waker};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let (mut a, mut b) = SimulatedLink::new()
+        .latency(Duration::from_millis(50))
+        .pair();
+    AsyncWriteExt::write_all(&mut a, b"ping").await.unwrap();
+
+    // The first poll comes from a task which then loses interest, so the
+    // task which polls next must be the one woken when the bytes arrive.
+    let mut buf = [0; 4];
+    let mut read = Box::pin(AsyncReadExt::read(&mut b, &mut buf));
+    let mut cx = Context::from_waker(Waker::noop());
+    let start = Instant::now();
+    assert!(read.as_mut().poll(&mut cx).is_pending());
+    let n = tokio::time::timeout(Duration::from_secs(5), read)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(
+        start.elapsed() < Duration::from_secs(1),
+        "the read wasn't woken"
+    );
+    assert_eq!(n, 4);
+}
@@ -0,0 +1,87 @@
+#![cfg(unix)]
+
+mod common;
+
+#[cfg(feature = "async-std")]
+use char_device::AsyncStdCharDevice;
+#[cfg(feature = "tokio")]
+use char_device::TokioCharDevice;
+use char_device::{CharDevice, CharDeviceOptions};
+use std::io;
+
+// Each test locks the slave side of a pty of its own, so that the tests don't
+// contend with each other, or with anything else using a shared device.
+#[test]
+fn lock() {
+    let (_master, name) = common::pty();
+    let a = CharDevice::open(&name).unwrap();
+    let b = CharDevice::open(&name).unwrap();
+
+    let guard = a.lock_exclusive().unwrap();
+    assert_eq!(
+        b.try_lock_exclusive().unwrap_err().kind(),
+        io::ErrorKind::WouldBlock
+    );
+    drop(guard);
+
+    let _guard = b.try_lock_exclusive().unwrap();
+}
+
+#[test]
+fn options_exclusive() {
+    let (_master, name) = common::pty();
+    let a = CharDeviceOptions::new()
+        .exclusive(true)
+        .open(&name)
+        .unwrap();
+    let err = CharDeviceOptions::new()
+        .exclusive(true)
+        .open(&name)
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+
+    // The lock lasts until the device is closed.
+    drop(a);
+    let _b = CharDeviceOptions::new()
+        .exclusive(true)
+        .open(&name)
+        .unwrap();
+}
+
+#[cfg(feature = "async-std")]
+#[async_std::test]
+async fn async_std_lock() {
+    let (_master, name) = common::pty();
+    let a = AsyncStdCharDevice::open(&name).await.unwrap();
+    let b = AsyncStdCharDevice::open(&name).await.unwrap();
+
+    let guard = a.lock_exclusive().await.unwrap();
+    assert_eq!(
+        b.try_lock_exclusive().unwrap_err().kind(),
+        io::ErrorKind::WouldBlock
+    );
+    drop(guard);
+
+    let options = CharDeviceOptions::new().exclusive(true).clone();
+    let _c = AsyncStdCharDevice::open_with(&name, &options)
+        .await
+        .unwrap();
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn tokio_lock() {
+    let (_master, name) = common::pty();
+    let a = TokioCharDevice::open(&name).await.unwrap();
+    let b = TokioCharDevice::open(&name).await.unwrap();
+
+    let guard = a.lock_exclusive().await.unwrap();
+    assert_eq!(
+        b.try_lock_exclusive().unwrap_err().kind(),
+        io::ErrorKind::WouldBlock
+    );
+    drop(guard);
+
+    let options = CharDeviceOptions::new().exclusive(true).clone();
+    let _c = TokioCharDevice::open_with(&name, &options).await.unwrap();
+}
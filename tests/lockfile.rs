@@ -0,0 +1,119 @@
+#![cfg(unix)]
+
+mod common;
+
+use char_device::lockfile::LockDir;
+use char_device::{CharDeviceOptions, CloseBehavior};
+use std::io;
+
+#[test]
+fn acquire_release() {
+    let dir = common::temp_dir("acquire");
+    let locks = LockDir::with_dir(&dir);
+
+    let lock = locks.acquire("/dev/null").unwrap();
+    assert_eq!(lock.path(), dir.join("LCK..null"));
+    assert_eq!(
+        std::fs::read_to_string(lock.path()).unwrap(),
+        format!("{:10}\n", std::process::id())
+    );
+    assert_eq!(locks.owner("/dev/null").unwrap(), Some(std::process::id()));
+    assert_eq!(
+        locks.acquire("/dev/null").unwrap_err().kind(),
+        io::ErrorKind::WouldBlock
+    );
+
+    lock.release().unwrap();
+    assert_eq!(locks.owner("/dev/null").unwrap(), None);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn stale() {
+    let dir = common::temp_dir("stale");
+    let locks = LockDir::with_dir(&dir);
+
+    // A PID beyond the kernel's maximum never refers to a live process.
+    std::fs::write(dir.join("LCK..zero"), format!("{:10}\n", i32::MAX)).unwrap();
+    assert_eq!(locks.owner("/dev/zero").unwrap(), None);
+
+    let device = CharDeviceOptions::new()
+        .lock_file(Some(locks.clone()))
+        .open("/dev/zero")
+        .unwrap();
+    assert_eq!(locks.owner("/dev/zero").unwrap(), Some(std::process::id()));
+    drop(device);
+    assert!(!dir.join("LCK..zero").exists());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn open_options() {
+    let dir = common::temp_dir("options");
+    let mut options = CharDeviceOptions::new();
+    options.lock_file(Some(LockDir::with_dir(&dir)));
+
+    let device = options.open("/dev/null").unwrap();
+    let lock_path = dir.join("LCK..null");
+    assert!(lock_path.exists());
+    assert_eq!(
+        options.open("/dev/null").unwrap_err().kind(),
+        io::ErrorKind::WouldBlock
+    );
+
+    // Clones share the lock file.
+    let clone = device.try_clone().unwrap();
+    drop(device);
+    assert!(lock_path.exists());
+    clone.close_with(CloseBehavior::DiscardPending).unwrap();
+    assert!(!lock_path.exists());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn tokio_open_options() {
+    use char_device::TokioCharDevice;
+
+    let dir = common::temp_dir("tokio-options");
+    let mut options = CharDeviceOptions::new();
+    options.lock_file(Some(LockDir::with_dir(&dir)));
+
+    let device = TokioCharDevice::open_with("/dev/null", &options)
+        .await
+        .unwrap();
+    assert!(dir.join("LCK..null").exists());
+    assert_eq!(
+        TokioCharDevice::open_with("/dev/null", &options)
+            .await
+            .unwrap_err()
+            .kind(),
+        io::ErrorKind::WouldBlock
+    );
+    drop(device);
+    assert!(!dir.join("LCK..null").exists());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "async-std")]
+#[async_std::test]
+async fn async_std_open_options() {
+    use char_device::AsyncStdCharDevice;
+
+    let dir = common::temp_dir("async-std-options");
+    let mut options = CharDeviceOptions::new();
+    options.lock_file(Some(LockDir::with_dir(&dir)));
+
+    let device = AsyncStdCharDevice::open_with("/dev/null", &options)
+        .await
+        .unwrap();
+    assert!(dir.join("LCK..null").exists());
+    drop(device);
+    assert!(!dir.join("LCK..null").exists());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,54 @@
+#![cfg(all(unix, feature = "mio"))]
+
+mod common;
+
+use char_device::MioCharDevice;
+use mio::{Events, Interest, Poll, Token};
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+#[test]
+fn mio() {
+    let (mut master, name) = common::pty();
+    let mut slave = MioCharDevice::open(name).unwrap();
+    let mut poll = Poll::new().unwrap();
+    let mut events = Events::with_capacity(4);
+    poll.registry()
+        .register(&mut slave, Token(7), Interest::READABLE)
+        .unwrap();
+
+    let mut buf = [0; 8];
+    assert_eq!(
+        slave.read(&mut buf).unwrap_err().kind(),
+        io::ErrorKind::WouldBlock
+    );
+
+    master.write_all(b"hello\n").unwrap();
+    poll.poll(&mut events, Some(Duration::from_secs(5)))
+        .unwrap();
+    let event = events.iter().next().unwrap();
+    assert_eq!(event.token(), Token(7));
+    assert!(event.is_readable());
+    assert_eq!(slave.num_ready_bytes().unwrap(), 6);
+    assert_eq!(slave.read(&mut buf).unwrap(), 6);
+    assert_eq!(&buf[..6], b"hello\n");
+
+    poll.registry().deregister(&mut slave).unwrap();
+    let device = slave.into_inner().unwrap();
+    assert!(!rustix::fs::fcntl_getfl(&device)
+        .unwrap()
+        .contains(rustix::fs::OFlags::NONBLOCK));
+}
+
+#[test]
+fn mio_no_poll() {
+    // "/dev/null" doesn't support polling.
+    let mut null = MioCharDevice::open("/dev/null").unwrap();
+    let poll = Poll::new().unwrap();
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    assert!(poll
+        .registry()
+        .register(&mut null, Token(0), Interest::READABLE)
+        .is_err());
+    assert_eq!(null.write(b"abc").unwrap(), 3);
+}
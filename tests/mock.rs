@@ -0,0 +1,174 @@
+use char_device::testing::MockDevice;
+use char_device::ReadEvent;
+use std::io::{self, Read, Write};
+
+/// The protocol under test, generic over the device, as code using
+/// `CharDevice` would be: send a query, and read the response.
+fn query<D: Read + Write>(device: &mut D, command: &[u8]) -> io::Result<Vec<u8>> {
+    device.write_all(command)?;
+    let mut buf = [0; 64];
+    let n = device.read(&mut buf)?;
+    Ok(buf[..n].to_vec())
+}
+
+#[test]
+fn script() {
+    let mut device = MockDevice::new();
+    device
+        .respond(b"READY\n")
+        .expect(b"AT\r", b"OK\r\n")
+        .expect(b"ATI\r", b"mock\r\n");
+
+    assert_eq!(device.num_ready_bytes().unwrap(), 6);
+    let mut buf = [0; 6];
+    device.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"READY\n");
+
+    // Nothing is available until the expected write is made.
+    assert_eq!(device.num_ready_bytes().unwrap(), 0);
+    assert_eq!(
+        device.read(&mut buf).unwrap_err().kind(),
+        io::ErrorKind::WouldBlock
+    );
+
+    assert_eq!(query(&mut device, b"AT\r").unwrap(), b"OK\r\n");
+
+    // Writes may be split up differently than the script's.
+    device.write_all(b"AT").unwrap();
+    assert_eq!(device.num_ready_bytes().unwrap(), 0);
+    device.write_all(b"I\r").unwrap();
+    assert_eq!(device.num_ready_bytes().unwrap(), 6);
+    assert_eq!(device.read(&mut buf).unwrap(), 6);
+    assert_eq!(&buf, b"mock\r\n");
+
+    // Once the script is done, reads return end of file.
+    assert_eq!(device.read_event(&mut buf).unwrap(), ReadEvent::Eof);
+    device.finish().unwrap();
+}
+
+#[test]
+fn mismatch() {
+    let mut device = MockDevice::new();
+    device.expect(b"AT\r", b"OK\r\n");
+    let err = device.write_all(b"ATZ\r").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(device.read(&mut [0; 8]).is_err());
+    assert!(device.finish().is_err());
+
+    // Writing after the script is done is caught too.
+    let mut device = MockDevice::new();
+    assert!(device.write_all(b"AT\r").is_err());
+
+    // Not making an expected write is caught by `finish`.
+    let mut device = MockDevice::new();
+    device.expect(b"AT\r", b"OK\r\n");
+    assert!(device.finish().is_err());
+}
+
+#[test]
+fn pair() {
+    let (mut host, mut device) = MockDevice::pair();
+
+    let responder = std::thread::spawn(move || {
+        let mut buf = [0; 3];
+        device.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"AT\r");
+        device.write_all(b"OK\r\n").unwrap();
+        device
+    });
+    assert_eq!(query(&mut host, b"AT\r").unwrap(), b"OK\r\n");
+    let mut device = responder.join().unwrap();
+
+    device.write_all(b"abc").unwrap();
+    assert_eq!(host.num_ready_bytes().unwrap(), 3);
+    drop(device);
+    let mut buf = Vec::new();
+    host.read_to_end(&mut buf).unwrap();
+    assert_eq!(buf, b"abc");
+    host.finish().unwrap();
+}
+
+#[cfg(feature = "async-std")]
+#[async_std::test]
+async fn async_std_mock() {
+    use async_std::io::prelude::{ReadExt, WriteExt};
+
+    // `MockDevice` also implements `std::io::Read` and `Write`, so name the
+    // async traits explicitly.
+    let mut device = MockDevice::new();
+    device.expect(b"AT\r", b"OK\r\n");
+    WriteExt::write_all(&mut device, b"AT\r").await.unwrap();
+    let mut buf = [0; 4];
+    ReadExt::read_exact(&mut device, &mut buf).await.unwrap();
+    assert_eq!(&buf, b"OK\r\n");
+    device.finish().unwrap();
+
+    let (mut a, mut b) = MockDevice::pair();
+    WriteExt::write_all(&mut a, b"ping").await.unwrap();
+    ReadExt::read_exact(&mut b, &mut buf).await.unwrap();
+    assert_eq!(&buf, b"ping");
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn tokio_mock() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let mut device = MockDevice::new();
+    device.expect(b"AT\r", b"OK\r\n");
+    AsyncWriteExt::write_all(&mut device, b"AT\r")
+        .await
+        .unwrap();
+    let mut buf = [0; 4];
+    AsyncReadExt::read_exact(&mut device, &mut buf)
+        .await
+        .unwrap();
+    assert_eq!(&buf, b"OK\r\n");
+    device.finish().unwrap();
+
+    let (mut a, mut b) = MockDevice::pair();
+    let reader = tokio::spawn(async move {
+        let mut buf = [0; 4];
+        AsyncReadExt::read_exact(&mut b, &mut buf).await.unwrap();
+        buf
+    });
+    AsyncWriteExt::write_all(&mut a, b"ping").await.unwrap();
+    assert_eq!(&reader.await.unwrap(), b"ping");
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn tokio_mock_waits() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let mut device = MockDevice::new();
+    device.expect(b"AT\r", b"OK\r\n");
+    let (mut reader, mut writer) = tokio::io::split(device);
+
+    // The read waits for the write which makes the response available.
+    let reader = tokio::spawn(async move {
+        let mut buf = [0; 4];
+        reader.read_exact(&mut buf).await.unwrap();
+        buf
+    });
+    tokio::task::yield_now().await;
+    writer.write_all(b"AT\r").await.unwrap();
+    assert_eq!(&reader.await.unwrap(), b"OK\r\n");
+}
+
+#[cfg(feature = "async-std")]
+#[async_std::test]
+async fn async_std_mock_waits() {
+    use async_std::io::prelude::{ReadExt, WriteExt};
+    use futures_lite::future::poll_once;
+
+    let mut device = MockDevice::new();
+    device.expect(b"AT\r", b"OK\r\n");
+    let mut buf = [0; 4];
+    assert!(poll_once(ReadExt::read(&mut device, &mut buf))
+        .await
+        .is_none());
+    WriteExt::write_all(&mut device, b"AT\r").await.unwrap();
+    ReadExt::read_exact(&mut device, &mut buf).await.unwrap();
+    assert_eq!(&buf, b"OK\r\n");
+}
@@ -0,0 +1,38 @@
+#![cfg(all(any(target_os = "android", target_os = "linux"), feature = "libc"))]
+
+mod common;
+
+use char_device::notify::notifications;
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+use std::time::Duration;
+
+#[test]
+fn pty_notify() {
+    let (mut master, slave) = common::pty_device();
+
+    let signal = libc::SIGRTMIN() + 1;
+    let ready = notifications(signal).unwrap();
+    slave.enable_async_notify(signal).unwrap();
+
+    master.write_all(b"hello\n").unwrap();
+
+    let fd = ready.recv_timeout(Duration::from_secs(10)).unwrap();
+    assert_eq!(fd, slave.as_raw_fd());
+
+    slave.disable_async_notify().unwrap();
+}
+
+#[test]
+fn existing_handler() {
+    extern "C" fn handler(_signal: libc::c_int) {}
+
+    let signal = libc::SIGRTMIN() + 2;
+    // SAFETY: `handler` does nothing.
+    unsafe {
+        libc::signal(signal, handler as *const () as libc::sighandler_t);
+    }
+
+    let err = notifications(signal).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+}
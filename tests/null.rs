@@ -1,3 +1,5 @@
+#[cfg(all(unix, feature = "use_async_io"))]
+use char_device::AsyncCharDevice;
 #[cfg(feature = "async-std")]
 use char_device::AsyncStdCharDevice;
 use char_device::CharDevice;
@@ -27,6 +29,21 @@ async fn async_std_null() {
     assert_eq!(char_device.read(&mut buf).await.unwrap(), 0);
 }
 
+#[cfg(all(unix, feature = "use_async_io"))]
+#[test]
+fn async_io_null() {
+    use futures_lite::io::{AsyncReadExt, AsyncWriteExt};
+
+    futures_lite::future::block_on(async {
+        let mut char_device = AsyncCharDevice::null().await.unwrap();
+        char_device.write_all(b"abcdefg").await.unwrap();
+        char_device.flush().await.unwrap();
+
+        let mut buf = vec![0_u8; 32];
+        assert_eq!(char_device.read(&mut buf).await.unwrap(), 0);
+    });
+}
+
 #[cfg(feature = "tokio")]
 #[tokio::test]
 async fn tokio_null() {
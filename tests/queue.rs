@@ -0,0 +1,58 @@
+#![cfg(unix)]
+
+mod common;
+
+#[cfg(feature = "async-std")]
+use char_device::AsyncStdCharDevice;
+#[cfg(feature = "tokio")]
+use char_device::TokioCharDevice;
+use char_device::{CharDevice, Queue};
+use std::io::Write;
+
+#[test]
+fn queues() {
+    let (mut master, name) = common::pty();
+    let slave = CharDevice::open(name).unwrap();
+
+    master.write_all(b"hello\n").unwrap();
+    while slave.num_ready_bytes().unwrap() == 0 {
+        std::thread::yield_now();
+    }
+    slave.discard(Queue::Input).unwrap();
+    assert_eq!(slave.num_ready_bytes().unwrap(), 0);
+
+    assert_eq!(slave.pending_output_bytes().unwrap(), 0);
+    slave.drain().unwrap();
+    slave.discard(Queue::Both).unwrap();
+}
+
+#[test]
+fn not_a_tty() {
+    let null = CharDevice::null().unwrap();
+    assert!(null.pending_output_bytes().is_err());
+    assert!(null.drain().is_err());
+}
+
+#[cfg(feature = "async-std")]
+#[async_std::test]
+async fn async_std_drain() {
+    use async_std::io::WriteExt;
+
+    let (_master, name) = common::pty();
+    let mut slave = AsyncStdCharDevice::open(name).await.unwrap();
+    slave.write_all(b"hello\n").await.unwrap();
+    slave.drain().await.unwrap();
+    assert_eq!(slave.pending_output_bytes().unwrap(), 0);
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn tokio_drain() {
+    use tokio::io::AsyncWriteExt;
+
+    let (_master, name) = common::pty();
+    let mut slave = TokioCharDevice::open(name).await.unwrap();
+    slave.write_all(b"hello\n").await.unwrap();
+    slave.drain().await.unwrap();
+    assert_eq!(slave.pending_output_bytes().unwrap(), 0);
+}
@@ -0,0 +1,265 @@
+#![cfg(all(
+    unix,
+    any(feature = "async-std", feature = "tokio", feature = "use_async_io")
+))]
+
+mod common;
+
+#[cfg(feature = "use_async_io")]
+use char_device::AsyncCharDevice;
+#[cfg(feature = "async-std")]
+use char_device::AsyncStdCharDevice;
+#[cfg(feature = "tokio")]
+use char_device::TokioCharDevice;
+use rustix::fs::{fcntl_getfl, OFlags};
+use std::fs::File;
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn tokio_readiness() {
+    use std::io::{Read, Write};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let (mut master, slave) = common::pty();
+    let device = TokioCharDevice::open(slave).await.unwrap();
+    assert!(device.is_readiness_based());
+
+    // Write while a read is pending. With the blocking thread pool, the
+    // write would wait for the read to finish.
+    let (mut reader, mut writer) = tokio::io::split(device);
+    let read = async {
+        let mut buf = [0; 8];
+        let n = reader.read(&mut buf).await.unwrap();
+        buf[..n].to_vec()
+    };
+    let write = async {
+        writer.write_all(b"ping").await.unwrap();
+        let mut buf = [0; 4];
+        master.read_exact(&mut buf).unwrap();
+        master.write_all(b"pong\n").unwrap();
+        buf
+    };
+    let (read, written) = tokio::join!(read, write);
+    assert_eq!(&written, b"ping");
+    assert_eq!(read, b"pong\n");
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn tokio_fallback() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    // "/dev/null" doesn't support polling.
+    let mut device = TokioCharDevice::null().await.unwrap();
+    assert!(!device.is_readiness_based());
+    assert!(!fcntl_getfl(&device).unwrap().contains(OFlags::NONBLOCK));
+    device.write_all(b"abc").await.unwrap();
+    assert_eq!(device.read(&mut [0; 8]).await.unwrap(), 0);
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn tokio_fallback_keeps_mode() {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    // "/dev/null" doesn't support polling, so registering it fails after
+    // it's been put in non-blocking mode, which must then be undone.
+    let file = File::options()
+        .write(true)
+        .custom_flags(OFlags::NONBLOCK.bits() as i32)
+        .open("/dev/null")
+        .unwrap();
+    // SAFETY: "/dev/null" is a character device.
+    let device = unsafe { TokioCharDevice::new_unchecked(file) };
+    assert!(!device.is_readiness_based());
+    assert!(fcntl_getfl(&device).unwrap().contains(OFlags::NONBLOCK));
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn tokio_outside_runtime() {
+    let (_master, slave) = common::pty();
+    let slave = File::options().read(true).write(true).open(slave).unwrap();
+    // SAFETY: `slave` is a pty slave, which is a character device.
+    let device = unsafe { TokioCharDevice::new_unchecked(slave) };
+    assert!(!device.is_readiness_based());
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn tokio_close_restores_blocking() {
+    use io_lifetimes::AsFd;
+
+    let (_master, slave) = common::pty();
+    let device = TokioCharDevice::open(slave).await.unwrap();
+    let dup = device.as_fd().try_clone_to_owned().unwrap();
+    assert!(fcntl_getfl(&dup).unwrap().contains(OFlags::NONBLOCK));
+
+    device.close().await.unwrap();
+    assert!(!fcntl_getfl(&dup).unwrap().contains(OFlags::NONBLOCK));
+}
+
+#[cfg(feature = "async-std")]
+#[async_std::test]
+async fn async_std_readiness() {
+    use async_std::io::prelude::{ReadExt, WriteExt};
+    use std::io::{Read, Write};
+
+    let (mut master, slave) = common::pty();
+    let mut device = AsyncStdCharDevice::open(slave).await.unwrap();
+    assert!(device.is_readiness_based());
+
+    // Write while a read is pending through a clone.
+    let mut reader = device.clone();
+    let read = async_std::task::spawn(async move {
+        let mut buf = [0; 8];
+        let n = reader.read(&mut buf).await.unwrap();
+        buf[..n].to_vec()
+    });
+    device.write_all(b"ping").await.unwrap();
+    let mut buf = [0; 4];
+    master.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"ping");
+    master.write_all(b"pong\n").unwrap();
+    assert_eq!(read.await, b"pong\n");
+}
+
+#[cfg(feature = "async-std")]
+#[async_std::test]
+async fn async_std_cancel() {
+    use async_std::io::prelude::ReadExt;
+    use io_lifetimes::AsFd;
+    use std::time::Duration;
+
+    let (_master, slave) = common::pty();
+    let mut device = AsyncStdCharDevice::open(slave).await.unwrap();
+    let dup = device.as_fd().try_clone_to_owned().unwrap();
+    assert!(fcntl_getfl(&dup).unwrap().contains(OFlags::NONBLOCK));
+
+    // Cancel a read which is waiting for input. No thread is left holding
+    // the device, so it can be closed right away.
+    let mut buf = [0; 8];
+    let result = async_std::io::timeout(Duration::from_millis(20), device.read(&mut buf)).await;
+    assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::TimedOut);
+    device.close().await.unwrap();
+    assert!(!fcntl_getfl(&dup).unwrap().contains(OFlags::NONBLOCK));
+}
+
+#[cfg(feature = "async-std")]
+#[async_std::test]
+async fn async_std_fallback() {
+    use async_std::io::prelude::{ReadExt, WriteExt};
+
+    // "/dev/null" doesn't support polling.
+    let mut device = AsyncStdCharDevice::null().await.unwrap();
+    assert!(!device.is_readiness_based());
+    assert!(!fcntl_getfl(&device).unwrap().contains(OFlags::NONBLOCK));
+    device.write_all(b"abc").await.unwrap();
+    assert_eq!(device.read(&mut [0; 8]).await.unwrap(), 0);
+}
+
+#[cfg(feature = "async-std")]
+#[async_std::test]
+async fn async_std_fallback_keeps_mode() {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let file = File::options()
+        .write(true)
+        .custom_flags(OFlags::NONBLOCK.bits() as i32)
+        .open("/dev/null")
+        .unwrap();
+    // SAFETY: "/dev/null" is a character device.
+    let device = unsafe { AsyncStdCharDevice::new_unchecked(file) };
+    assert!(!device.is_readiness_based());
+    assert!(fcntl_getfl(&device).unwrap().contains(OFlags::NONBLOCK));
+}
+
+#[cfg(feature = "use_async_io")]
+#[test]
+fn async_io_readiness() {
+    use futures_lite::io::{AsyncReadExt, AsyncWriteExt};
+    use std::io::{Read, Write};
+
+    let (mut master, slave) = common::pty();
+    futures_lite::future::block_on(async {
+        let device = AsyncCharDevice::open(slave).await.unwrap();
+        assert!(device.is_readiness_based());
+        assert!(fcntl_getfl(&device).unwrap().contains(OFlags::NONBLOCK));
+
+        // Write while a read is pending.
+        let (mut reader, mut writer) = futures_lite::io::split(device);
+        let read = async {
+            let mut buf = [0; 8];
+            let n = reader.read(&mut buf).await.unwrap();
+            buf[..n].to_vec()
+        };
+        let write = async {
+            writer.write_all(b"ping").await.unwrap();
+            let mut buf = [0; 4];
+            master.read_exact(&mut buf).unwrap();
+            master.write_all(b"pong\n").unwrap();
+            buf
+        };
+        let (read, written) = futures_lite::future::zip(read, write).await;
+        assert_eq!(&written, b"ping");
+        assert_eq!(read, b"pong\n");
+    });
+}
+
+#[cfg(feature = "use_async_io")]
+#[test]
+fn async_io_fallback() {
+    use futures_lite::io::{AsyncReadExt, AsyncWriteExt};
+
+    futures_lite::future::block_on(async {
+        // "/dev/zero" doesn't support polling.
+        let mut device = AsyncCharDevice::open("/dev/zero").await.unwrap();
+        assert!(!device.is_readiness_based());
+        assert!(!fcntl_getfl(&device).unwrap().contains(OFlags::NONBLOCK));
+        device.write_all(b"abc").await.unwrap();
+        device.flush().await.unwrap();
+
+        let mut buf = [1; 4];
+        device.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, [0; 4]);
+    });
+}
+
+#[cfg(feature = "use_async_io")]
+#[test]
+fn async_io_fallback_keeps_mode() {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let file = File::options()
+        .write(true)
+        .custom_flags(OFlags::NONBLOCK.bits() as i32)
+        .open("/dev/null")
+        .unwrap();
+    // SAFETY: "/dev/null" is a character device.
+    let device = unsafe { AsyncCharDevice::new_unchecked(file) };
+    assert!(!device.is_readiness_based());
+    assert!(fcntl_getfl(&device).unwrap().contains(OFlags::NONBLOCK));
+}
+
+#[cfg(feature = "use_async_io")]
+#[test]
+fn async_io_fallback_errors() {
+    use futures_lite::io::AsyncWriteExt;
+    use std::io::IoSlice;
+
+    futures_lite::future::block_on(async {
+        // "/dev/full" doesn't support polling, and fails writes with
+        // `ENOSPC`. Writes complete in the background, so each failure is
+        // reported by the next operation.
+        let mut device = AsyncCharDevice::open("/dev/full").await.unwrap();
+        assert!(!device.is_readiness_based());
+        let bufs = [IoSlice::new(b"ab"), IoSlice::new(b"cd")];
+        assert_eq!(device.write_vectored(&bufs).await.unwrap(), 4);
+        let err = device.write_vectored(&bufs).await.unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ENOSPC));
+
+        assert_eq!(device.write_vectored(&bufs).await.unwrap(), 4);
+        let err = device.close().await.unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ENOSPC));
+    });
+}
@@ -0,0 +1,246 @@
+#![cfg(unix)]
+
+mod common;
+
+use char_device::reconnect::{ReconnectEvent, ReconnectOptions, ReconnectingCharDevice};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Open a pty, and point the symlink at `link` to its slave side, standing in
+/// for a device which can be unplugged and plugged back in.
+fn plug_in(link: &Path) -> File {
+    let (master, name) = common::pty();
+    let _ = std::fs::remove_file(link);
+    std::os::unix::fs::symlink(name, link).unwrap();
+    master
+}
+
+fn echo_enabled<Fd: rustix::fd::AsFd>(fd: Fd) -> bool {
+    use rustix::termios::{tcgetattr, LocalModes};
+
+    tcgetattr(fd)
+        .unwrap()
+        .local_modes
+        .contains(LocalModes::ECHO)
+}
+
+fn recording_options(events: &Arc<Mutex<Vec<ReconnectEvent>>>) -> ReconnectOptions {
+    let events = Arc::clone(events);
+    let mut options = ReconnectOptions::new();
+    options
+        .initial_delay(Duration::from_millis(10))
+        .on_event(move |event| events.lock().unwrap().push(event));
+    options
+}
+
+#[test]
+fn reconnect_on_hangup() {
+    let link = common::temp_dir("reconnect").join("tty");
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let options = recording_options(&events);
+
+    let mut master = plug_in(&link);
+    let mut device = ReconnectingCharDevice::open(&link, &options).unwrap();
+    common::disable_echo(device.get_ref().unwrap());
+    device.save_termios().unwrap();
+
+    master.write_all(b"one\n").unwrap();
+    let mut buf = [0; 16];
+    assert_eq!(device.read(&mut buf).unwrap(), 4);
+    assert_eq!(&buf[..4], b"one\n");
+
+    // Unplug the device and plug in a new one.
+    drop(master);
+    let mut master = plug_in(&link);
+    master.write_all(b"two\n").unwrap();
+
+    assert_eq!(device.read(&mut buf).unwrap(), 4);
+    assert_eq!(&buf[..4], b"two\n");
+    assert_eq!(
+        *events.lock().unwrap(),
+        [
+            ReconnectEvent::Disconnected,
+            ReconnectEvent::Reconnected { attempts: 1 }
+        ]
+    );
+
+    assert!(!echo_enabled(device.get_ref().unwrap()));
+
+    // Writes reconnect too.
+    drop(master);
+    let mut master = plug_in(&link);
+    device.write_all(b"three\n").unwrap();
+    let n = master.read(&mut buf).unwrap();
+    assert_eq!(&buf[..n], b"three\r\n");
+}
+
+#[test]
+fn max_attempts() {
+    let link = common::temp_dir("max-attempts").join("tty");
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let mut options = recording_options(&events);
+    options.max_attempts(Some(3));
+
+    let master = plug_in(&link);
+    let mut device = ReconnectingCharDevice::open(&link, &options).unwrap();
+
+    drop(master);
+    std::fs::remove_file(&link).unwrap();
+    assert!(device.read(&mut [0; 16]).is_err());
+    assert_eq!(*events.lock().unwrap(), [ReconnectEvent::Disconnected]);
+}
+
+#[test]
+fn reconnect_exclusive() {
+    use char_device::CharDeviceOptions;
+
+    let link = common::temp_dir("reconnect-exclusive").join("tty");
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let mut options = recording_options(&events);
+    let mut open_options = CharDeviceOptions::new();
+    open_options.exclusive(true);
+    // With the old device still open, its lock would make every attempt
+    // fail.
+    options.open_options(open_options).max_attempts(Some(1));
+
+    let mut master = plug_in(&link);
+    let mut device = ReconnectingCharDevice::open(&link, &options).unwrap();
+    common::disable_echo(device.get_ref().unwrap());
+    device.save_termios().unwrap();
+
+    device.reconnect().unwrap();
+    assert_eq!(
+        *events.lock().unwrap(),
+        [
+            ReconnectEvent::Disconnected,
+            ReconnectEvent::Reconnected { attempts: 1 }
+        ]
+    );
+
+    // The new device holds the lock.
+    assert!(CharDeviceOptions::new()
+        .exclusive(true)
+        .open(&link)
+        .is_err());
+
+    master.write_all(b"one\n").unwrap();
+    let mut buf = [0; 16];
+    assert_eq!(device.read(&mut buf).unwrap(), 4);
+    assert_eq!(&buf[..4], b"one\n");
+}
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+#[test]
+fn wait_for_node() {
+    let link = common::temp_dir("wait-for-node").join("tty");
+    let mut options = ReconnectOptions::new();
+    options
+        .initial_delay(Duration::from_secs(30))
+        .wait_for_node(true);
+
+    let master = plug_in(&link);
+    let mut device = ReconnectingCharDevice::open(&link, &options).unwrap();
+
+    // Unplug the device, and plug it back in after the first attempt to
+    // reconnect has failed.
+    drop(master);
+    std::fs::remove_file(&link).unwrap();
+    let plugger = std::thread::spawn({
+        let link = link.clone();
+        move || {
+            std::thread::sleep(Duration::from_millis(100));
+            let mut master = plug_in(&link);
+            master.write_all(b"back\n").unwrap();
+            master
+        }
+    });
+
+    let start = Instant::now();
+    let mut buf = [0; 16];
+    assert_eq!(device.read(&mut buf).unwrap(), 5);
+    assert!(start.elapsed() < Duration::from_secs(30));
+    drop(plugger.join().unwrap());
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn tokio_reconnect_on_hangup() {
+    use char_device::reconnect::TokioReconnectingCharDevice;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let link = common::temp_dir("tokio-reconnect").join("tty");
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let options = recording_options(&events);
+
+    let mut master = plug_in(&link);
+    let mut device = TokioReconnectingCharDevice::open(&link, &options)
+        .await
+        .unwrap();
+    common::disable_echo(device.get_ref().unwrap());
+    device.save_termios().unwrap();
+
+    master.write_all(b"one\n").unwrap();
+    let mut buf = [0; 16];
+    assert_eq!(device.read(&mut buf).await.unwrap(), 4);
+
+    drop(master);
+    let mut master = plug_in(&link);
+    master.write_all(b"two\n").unwrap();
+
+    assert_eq!(device.read(&mut buf).await.unwrap(), 4);
+    assert_eq!(&buf[..4], b"two\n");
+    assert_eq!(events.lock().unwrap().len(), 2);
+
+    assert!(!echo_enabled(device.get_ref().unwrap()));
+
+    device.write_all(b"three\n").await.unwrap();
+    device.flush().await.unwrap();
+    // The output may arrive in pieces.
+    let mut output = Vec::new();
+    while !output.ends_with(b"three\r\n") {
+        let n = master.read(&mut buf).unwrap();
+        assert_ne!(n, 0);
+        output.extend_from_slice(&buf[..n]);
+    }
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn tokio_drop_while_reconnecting() {
+    use char_device::reconnect::TokioReconnectingCharDevice;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let link = common::temp_dir("tokio-drop-reconnect").join("tty");
+    let attempts = Arc::new(AtomicUsize::new(0));
+    let mut options = ReconnectOptions::new();
+    options
+        .initial_delay(Duration::from_millis(10))
+        .max_delay(Duration::from_millis(10))
+        .configure({
+            let attempts = Arc::clone(&attempts);
+            move |_| match attempts.fetch_add(1, Ordering::SeqCst) {
+                0 => Ok(()),
+                _ => Err(std::io::Error::other("refused")),
+            }
+        });
+
+    let _master = plug_in(&link);
+    let mut device = TokioReconnectingCharDevice::open(&link, &options)
+        .await
+        .unwrap();
+
+    // Every attempt after the first open fails, so this keeps retrying until
+    // the device is dropped.
+    let reconnect = tokio::time::timeout(Duration::from_millis(100), device.reconnect()).await;
+    assert!(reconnect.is_err());
+    assert!(attempts.load(Ordering::SeqCst) > 1);
+    drop(device);
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let stopped = attempts.load(Ordering::SeqCst);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(attempts.load(Ordering::SeqCst), stopped);
+}
@@ -0,0 +1,87 @@
+#![cfg(unix)]
+
+mod common;
+
+use char_device::record::{is_truncated, Record, RecordReader};
+use char_device::CharDevice;
+use std::io::{self, Write};
+
+/// A line read from a terminal in canonical mode, which returns one line
+/// per `read`.
+#[derive(Debug, PartialEq)]
+struct Line(Vec<u8>);
+
+impl Record for Line {
+    const MAX_SIZE: usize = 16;
+
+    fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        Ok(Self(bytes.to_vec()))
+    }
+}
+
+#[test]
+fn lines() {
+    let (mut master, name) = common::pty();
+    let mut reader = RecordReader::<Line>::new(CharDevice::open(name).unwrap());
+
+    master
+        .write_all(b"hello\nworld\nthis line is too long\n")
+        .unwrap();
+    assert_eq!(
+        reader.read_record().unwrap(),
+        Some(Line(b"hello\n".to_vec()))
+    );
+    assert_eq!(reader.read_bytes().unwrap(), Some(&b"world\n"[..]));
+    assert!(is_truncated(&reader.read_record().unwrap_err()));
+}
+
+#[test]
+fn fixed_size() {
+    // "/dev/zero" fills the whole buffer, so every record looks too long.
+    let mut reader = RecordReader::<[u8; 4]>::new(CharDevice::open("/dev/zero").unwrap());
+    assert!(is_truncated(&reader.next().unwrap().unwrap_err()));
+
+    let mut reader = RecordReader::<[u8; 4]>::new(CharDevice::null().unwrap());
+    assert!(reader.next().is_none());
+}
+
+#[cfg(feature = "async-std")]
+#[async_std::test]
+async fn async_std_lines() {
+    use async_std::stream::StreamExt;
+    use char_device::record::AsyncStdRecordStream;
+    use char_device::AsyncStdCharDevice;
+
+    let (mut master, name) = common::pty();
+    let device = AsyncStdCharDevice::open(name).await.unwrap();
+    let mut stream = AsyncStdRecordStream::<Line>::new(device);
+
+    master.write_all(b"hello\n").unwrap();
+    assert_eq!(
+        stream.next().await.unwrap().unwrap(),
+        Line(b"hello\n".to_vec())
+    );
+    master.write_all(b"world\n").unwrap();
+    assert_eq!(
+        stream.read_record().await.unwrap(),
+        Some(Line(b"world\n".to_vec()))
+    );
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn tokio_lines() {
+    use char_device::record::TokioRecordStream;
+    use char_device::TokioCharDevice;
+
+    let (mut master, name) = common::pty();
+    let device = TokioCharDevice::open(name).await.unwrap();
+    let mut stream = TokioRecordStream::<Line>::new(device);
+
+    master.write_all(b"hello\nthis line is too long\n").unwrap();
+    assert_eq!(
+        stream.read_record().await.unwrap(),
+        Some(Line(b"hello\n".to_vec()))
+    );
+    assert!(is_truncated(&stream.read_record().await.unwrap_err()));
+}
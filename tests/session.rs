@@ -0,0 +1,235 @@
+#![cfg(unix)]
+
+mod common;
+
+use char_device::session::{read_session, Direction, Recorder, Replayer};
+use char_device::CharDevice;
+use std::io::{self, Read, Write};
+
+/// The protocol under test: send a query, and read the response.
+fn query<D: Read + Write>(device: &mut D) -> io::Result<Vec<u8>> {
+    device.write_all(b"ping\n")?;
+    let mut buf = [0; 64];
+    let n = device.read(&mut buf)?;
+    Ok(buf[..n].to_vec())
+}
+
+/// Record a session of `query` against a pty standing in for a device.
+fn record() -> Vec<u8> {
+    let (mut master, slave) = common::pty_without_echo();
+    master.write_all(b"pong\n").unwrap();
+
+    let mut recorder = Recorder::new(slave, Vec::new()).unwrap();
+    assert_eq!(query(&mut recorder).unwrap(), b"pong\n");
+    let (_slave, log) = recorder.into_inner();
+
+    let mut buf = [0; 6];
+    master.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"ping\r\n");
+    log
+}
+
+#[test]
+fn record_replay() {
+    let log = record();
+
+    let entries = read_session(&log[..]).unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].direction, Direction::Write);
+    assert_eq!(entries[0].data, b"ping\n");
+    assert_eq!(entries[1].direction, Direction::Read);
+    assert_eq!(entries[1].data, b"pong\n");
+    assert!(entries[0].time <= entries[1].time);
+
+    let mut replayer = Replayer::new(&log[..]).unwrap();
+    assert_eq!(query(&mut replayer).unwrap(), b"pong\n");
+    let mut buf = [0; 4];
+    assert_eq!(replayer.read(&mut buf).unwrap(), 0);
+    replayer.finish().unwrap();
+}
+
+#[test]
+fn split_writes() {
+    let mut replayer = Replayer::new(&record()[..]).unwrap();
+    replayer.write_all(b"pi").unwrap();
+    replayer.write_all(b"ng\n").unwrap();
+    let mut buf = [0; 3];
+    replayer.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"pon");
+    replayer.read_exact(&mut buf[..2]).unwrap();
+    assert_eq!(&buf[..2], b"g\n");
+    replayer.finish().unwrap();
+}
+
+#[test]
+fn mismatch() {
+    let mut replayer = Replayer::new(&record()[..]).unwrap();
+    let err = replayer.write_all(b"pink\n").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(replayer.read(&mut [0; 8]).is_err());
+    assert!(replayer.finish().is_err());
+
+    // Reading first doesn't follow the session either.
+    let mut replayer = Replayer::new(&record()[..]).unwrap();
+    assert!(replayer.read(&mut [0; 8]).is_err());
+
+    // Not making a recorded write is caught by `finish`.
+    let replayer = Replayer::new(&record()[..]).unwrap();
+    assert!(replayer.finish().is_err());
+}
+
+#[test]
+fn record_errors() {
+    use char_device::fault::{Fault, FaultPolicy, FaultyDevice, Op};
+    use rustix::io::Errno;
+
+    let mut policy = FaultPolicy::new();
+    policy
+        .at(Op::Write, 0, Fault::Interrupted)
+        .at(Op::Read, 0, Fault::Io);
+    let device = FaultyDevice::new(CharDevice::null().unwrap(), policy);
+    let mut recorder = Recorder::new(device, Vec::new()).unwrap();
+    recorder.write_all(b"hi").unwrap();
+    let err = recorder.read(&mut [0; 8]).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(Errno::IO.raw_os_error()));
+    assert_eq!(recorder.read(&mut [0; 8]).unwrap(), 0);
+
+    let (_device, log) = recorder.into_inner();
+    let entries = read_session(&log[..]).unwrap();
+    assert_eq!(entries.len(), 4);
+    assert_eq!(entries[0].direction, Direction::Write);
+    assert_eq!(entries[0].error, Some(Errno::INTR.raw_os_error()));
+    assert!(entries[0].data.is_empty());
+    assert_eq!(entries[1].data, b"hi");
+    assert_eq!(entries[1].error, None);
+    assert_eq!(entries[2].direction, Direction::Read);
+    assert_eq!(entries[2].error, Some(Errno::IO.raw_os_error()));
+
+    // The replayer fails the same way.
+    let mut replayer = Replayer::new(&log[..]).unwrap();
+    let err = replayer.write(b"hi").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+    replayer.write_all(b"hi").unwrap();
+    let err = replayer.read(&mut [0; 8]).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(Errno::IO.raw_os_error()));
+    assert_eq!(replayer.read(&mut [0; 8]).unwrap(), 0);
+    replayer.finish().unwrap();
+}
+
+#[test]
+fn not_a_session() {
+    assert!(Replayer::new(&b"garbage!"[..]).is_err());
+}
+
+#[cfg(feature = "async-std")]
+#[async_std::test]
+async fn async_std_replay() {
+    use async_std::io::{ReadExt, WriteExt};
+
+    // `Replayer` also implements `std::io::Read` and `Write`, so name the
+    // async traits explicitly.
+    let mut replayer = Replayer::new(&record()[..]).unwrap();
+    WriteExt::write_all(&mut replayer, b"ping\n").await.unwrap();
+    let mut buf = [0; 5];
+    ReadExt::read_exact(&mut replayer, &mut buf).await.unwrap();
+    assert_eq!(&buf, b"pong\n");
+    replayer.finish().unwrap();
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn tokio_replay() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let mut replayer = Replayer::new(&record()[..]).unwrap();
+    AsyncWriteExt::write_all(&mut replayer, b"ping\n")
+        .await
+        .unwrap();
+    let mut buf = [0; 5];
+    AsyncReadExt::read_exact(&mut replayer, &mut buf)
+        .await
+        .unwrap();
+    assert_eq!(&buf, b"pong\n");
+    replayer.finish().unwrap();
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn tokio_record() {
+    use char_device::TokioCharDevice;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use char_device::fault::{Fault, FaultPolicy, FaultyDevice, Op};
+
+    let mut policy = FaultPolicy::new();
+    policy.at(Op::Read, 0, Fault::Io);
+    let device = TokioCharDevice::null().await.unwrap();
+    let device = FaultyDevice::new(device, policy);
+    let mut recorder = Recorder::new(device, Vec::new()).unwrap();
+    recorder.write_all(b"hello").await.unwrap();
+    recorder.flush().await.unwrap();
+    assert!(recorder.read(&mut [0; 8]).await.is_err());
+    assert_eq!(recorder.read(&mut [0; 8]).await.unwrap(), 0);
+
+    let (_device, log) = recorder.into_inner();
+    let entries = read_session(&log[..]).unwrap();
+    assert_eq!(entries.len(), 3);
+    assert_eq!(entries[0].direction, Direction::Write);
+    assert_eq!(entries[0].data, b"hello");
+    assert_eq!(entries[1].direction, Direction::Read);
+    assert_eq!(entries[1].error, Some(rustix::io::Errno::IO.raw_os_error()));
+    assert_eq!(entries[2].direction, Direction::Read);
+    assert!(entries[2].data.is_empty());
+    assert_eq!(entries[2].error, None);
+}
+
+/// A log which fails every write.
+struct BrokenLog;
+
+impl Write for BrokenLog {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Accept the header, so that the recorder can be created.
+        if buf.starts_with(b"chardev1") {
+            return Ok(buf.len());
+        }
+        Err(io::ErrorKind::StorageFull.into())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn log_error() {
+    // The data a read takes from the device can't be put back, so it's
+    // returned, and the error writing the log is reported next.
+    let mut recorder = Recorder::new(&b"hello"[..], BrokenLog).unwrap();
+    let mut buf = [0; 8];
+    assert_eq!(recorder.read(&mut buf).unwrap(), 5);
+    assert_eq!(&buf[..5], b"hello");
+    let err = recorder.read(&mut buf).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::StorageFull);
+
+    // Likewise, data the device has accepted isn't written again.
+    let mut recorder = Recorder::new(Vec::new(), BrokenLog).unwrap();
+    assert_eq!(recorder.write(b"hello").unwrap(), 5);
+    let err = recorder.flush().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::StorageFull);
+    assert_eq!(recorder.into_inner().0, b"hello");
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn tokio_log_error() {
+    use char_device::TokioCharDevice;
+    use tokio::io::AsyncWriteExt;
+
+    let device = TokioCharDevice::null().await.unwrap();
+    let mut recorder = Recorder::new(device, BrokenLog).unwrap();
+    // The log is written by another thread, so the error is reported by the
+    // flush.
+    recorder.write_all(b"hello").await.unwrap();
+    let err = recorder.flush().await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::StorageFull);
+}
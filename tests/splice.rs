@@ -0,0 +1,100 @@
+#![cfg(any(target_os = "android", target_os = "linux"))]
+
+mod common;
+
+use char_device::splice::{copy_bidirectional, SplicePipe};
+use char_device::CharDevice;
+use rustix::net::{socketpair, AddressFamily, SocketFlags, SocketType};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::sync::mpsc;
+use std::time::Duration;
+
+#[test]
+fn zero_to_pipe() {
+    let zero = CharDevice::open("/dev/zero").unwrap();
+    let (reader, writer) = rustix::pipe::pipe().unwrap();
+
+    let mut pipe = SplicePipe::new().unwrap();
+    let n = zero.splice_to(&writer, 4096, &mut pipe).unwrap();
+    assert!(n > 0 && n <= 4096);
+
+    let mut buf = vec![0xff_u8; n];
+    let mut reader = std::fs::File::from(reader);
+    reader.read_exact(&mut buf).unwrap();
+    assert!(buf.iter().all(|b| *b == 0));
+}
+
+#[test]
+fn pty_to_socket() {
+    let (mut master, slave) = common::raw_pty();
+    let (a, b) = socketpair(
+        AddressFamily::UNIX,
+        SocketType::STREAM,
+        SocketFlags::CLOEXEC,
+        None,
+    )
+    .unwrap();
+
+    master.write_all(b"hello").unwrap();
+
+    let mut pipe = SplicePipe::new().unwrap();
+    let mut moved = 0;
+    while moved < 5 {
+        moved += slave.splice_to(&a, 5 - moved, &mut pipe).unwrap();
+    }
+
+    let mut buf = [0_u8; 5];
+    File::from(b).read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"hello");
+}
+
+#[test]
+fn failed_write_keeps_data() {
+    let (mut master, slave) = common::raw_pty();
+    master.write_all(b"hello").unwrap();
+
+    // Writing to a pipe with no reader fails after the data has been read
+    // from the device.
+    let (reader, writer) = rustix::pipe::pipe().unwrap();
+    drop(reader);
+    let mut pipe = SplicePipe::new().unwrap();
+    let err = slave.splice_to(&writer, 5, &mut pipe).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe);
+    assert_eq!(pipe.buffered(), 5);
+
+    // The next transfer writes what was kept.
+    let (reader, writer) = rustix::pipe::pipe().unwrap();
+    assert_eq!(slave.splice_to(&writer, 5, &mut pipe).unwrap(), 5);
+    assert_eq!(pipe.buffered(), 0);
+    let mut buf = [0_u8; 5];
+    File::from(reader).read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"hello");
+}
+
+#[test]
+fn copy_bidirectional_stops() {
+    let (mut master, slave) = common::raw_pty();
+    let (a, b) = socketpair(
+        AddressFamily::UNIX,
+        SocketType::STREAM,
+        SocketFlags::CLOEXEC,
+        None,
+    )
+    .unwrap();
+
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || sender.send(copy_bidirectional(&slave, &a)));
+
+    let mut b = File::from(b);
+    b.write_all(b"ping").unwrap();
+    let mut buf = [0_u8; 4];
+    master.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"ping");
+
+    // Closing the socket ends the copy to the device, which stops the copy
+    // from the device, even though the device never reaches end-of-file.
+    drop(b);
+    let result = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(result.unwrap(), (0, 4));
+}
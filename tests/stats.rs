@@ -0,0 +1,273 @@
+#![cfg(all(unix, feature = "metrics"))]
+
+mod common;
+
+use char_device::fault::{Fault, FaultPolicy, FaultyDevice, Op};
+use char_device::stats::MeteredDevice;
+use char_device::CharDevice;
+use metrics::{
+    Counter, CounterFn, Gauge, GaugeFn, Histogram, HistogramFn, Key, KeyName, Metadata, Recorder,
+    SharedString, Unit,
+};
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A metric's value: the sum of what's been recorded, or the last value set.
+#[derive(Default)]
+struct Value(Mutex<f64>);
+
+impl CounterFn for Value {
+    fn increment(&self, value: u64) {
+        *self.0.lock().unwrap() += value as f64;
+    }
+
+    fn absolute(&self, value: u64) {
+        *self.0.lock().unwrap() = value as f64;
+    }
+}
+
+impl GaugeFn for Value {
+    fn increment(&self, value: f64) {
+        *self.0.lock().unwrap() += value;
+    }
+
+    fn decrement(&self, value: f64) {
+        *self.0.lock().unwrap() -= value;
+    }
+
+    fn set(&self, value: f64) {
+        *self.0.lock().unwrap() = value;
+    }
+}
+
+impl HistogramFn for Value {
+    fn record(&self, _value: f64) {
+        // Count the samples.
+        *self.0.lock().unwrap() += 1.0;
+    }
+}
+
+/// A recorder which keeps metrics by their name and labels, as in
+/// `name{label=value,...}`.
+#[derive(Default)]
+struct Capture(Mutex<BTreeMap<String, Arc<Value>>>);
+
+impl Capture {
+    fn value(&self, key: &Key) -> Arc<Value> {
+        let labels: Vec<String> = key
+            .labels()
+            .map(|label| format!("{}={}", label.key(), label.value()))
+            .collect();
+        let name = format!("{}{{{}}}", key.name(), labels.join(","));
+        Arc::clone(self.0.lock().unwrap().entry(name).or_default())
+    }
+
+    fn get(&self, name: &str) -> f64 {
+        let metrics = self.0.lock().unwrap();
+        match metrics.get(name) {
+            Some(value) => *value.0.lock().unwrap(),
+            None => panic!("no metric {} in {:?}", name, metrics.keys()),
+        }
+    }
+}
+
+impl Recorder for Capture {
+    fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+    fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+    fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+    fn register_counter(&self, key: &Key, _: &Metadata<'_>) -> Counter {
+        Counter::from_arc(self.value(key))
+    }
+
+    fn register_gauge(&self, key: &Key, _: &Metadata<'_>) -> Gauge {
+        Gauge::from_arc(self.value(key))
+    }
+
+    fn register_histogram(&self, key: &Key, _: &Metadata<'_>) -> Histogram {
+        Histogram::from_arc(self.value(key))
+    }
+}
+
+#[test]
+fn stats() {
+    let capture = Capture::default();
+    metrics::with_local_recorder(&capture, || {
+        let mut policy = FaultPolicy::new();
+        policy
+            .at(Op::Write, 0, Fault::Interrupted)
+            .at(Op::Write, 1, Fault::Short(3))
+            .at(Op::Read, 0, Fault::Io);
+        let device = FaultyDevice::new(CharDevice::null().unwrap(), policy);
+        let mut device = MeteredDevice::new(device, "null");
+        assert_eq!(device.name(), "null");
+
+        device.write_all(b"hello").unwrap();
+        let mut buf = [0; 8];
+        assert!(device.read(&mut buf).is_err());
+        assert_eq!(device.read(&mut buf).unwrap(), 0);
+
+        let stats = device.stats();
+        assert_eq!(stats.writes, 3);
+        assert_eq!(stats.bytes_written, 5);
+        assert_eq!(stats.reads, 2);
+        assert_eq!(stats.bytes_read, 0);
+        let errors: Vec<(i32, u64)> = stats.errors.into_iter().collect();
+        assert_eq!(
+            errors,
+            [
+                (rustix::io::Errno::INTR.raw_os_error(), 1),
+                (rustix::io::Errno::IO.raw_os_error(), 1),
+            ]
+        );
+    });
+
+    assert_eq!(capture.get("char_device_writes{device=null}"), 3.0);
+    assert_eq!(capture.get("char_device_bytes_written{device=null}"), 5.0);
+    assert_eq!(capture.get("char_device_reads{device=null}"), 2.0);
+    assert_eq!(capture.get("char_device_read_seconds{device=null}"), 2.0);
+    let eintr = rustix::io::Errno::INTR.raw_os_error();
+    assert_eq!(
+        capture.get(&format!(
+            "char_device_errors{{device=null,errno={}}}",
+            eintr
+        )),
+        1.0
+    );
+}
+
+#[test]
+fn ready_bytes_and_read_time() {
+    let capture = Capture::default();
+    metrics::with_local_recorder(&capture, || -> io::Result<()> {
+        let (mut master, slave) = common::raw_pty();
+        let mut device = MeteredDevice::new(slave, "pty");
+
+        // The pty delivers input asynchronously, so wait for it.
+        master.write_all(b"hello")?;
+        let start = Instant::now();
+        while device.num_ready_bytes()? < 5 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        let mut buf = [0; 8];
+        assert_eq!(device.read(&mut buf)?, 5);
+        assert_eq!(device.num_ready_bytes()?, 0);
+
+        let writer = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            master.write_all(b"x").unwrap();
+            master
+        });
+        assert_eq!(device.read(&mut buf)?, 1);
+        let _master = writer.join().unwrap();
+
+        let stats = device.stats();
+        assert_eq!(stats.max_ready_bytes, 5);
+        assert_eq!(stats.bytes_read, 6);
+        assert!(stats.read_time >= Duration::from_millis(40));
+        Ok(())
+    })
+    .unwrap();
+
+    assert_eq!(capture.get("char_device_max_ready_bytes{device=pty}"), 5.0);
+}
+
+#[test]
+fn vectored() {
+    use std::io::{IoSlice, IoSliceMut};
+
+    let capture = Capture::default();
+    metrics::with_local_recorder(&capture, || {
+        let mut device = MeteredDevice::new(CharDevice::open("/dev/zero").unwrap(), "zero");
+        let (mut a, mut b) = ([1; 3], [1; 5]);
+        let n = device
+            .read_vectored(&mut [IoSliceMut::new(&mut a), IoSliceMut::new(&mut b)])
+            .unwrap();
+        assert_eq!(n, 8);
+        let n = device
+            .write_vectored(&[IoSlice::new(b"hel"), IoSlice::new(b"lo")])
+            .unwrap();
+        assert_eq!(n, 5);
+
+        let stats = device.stats();
+        assert_eq!((stats.reads, stats.bytes_read), (1, 8));
+        assert_eq!((stats.writes, stats.bytes_written), (1, 5));
+    });
+
+    assert_eq!(capture.get("char_device_bytes_read{device=zero}"), 8.0);
+    assert_eq!(capture.get("char_device_bytes_written{device=zero}"), 5.0);
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn tokio_cancelled_read() {
+    use char_device::TokioCharDevice;
+    use futures_lite::future::poll_once;
+    use tokio::io::AsyncReadExt;
+
+    let (mut master, slave) = common::raw_pty();
+    let slave = unsafe { TokioCharDevice::new_unchecked(slave) };
+    let mut device = MeteredDevice::new(slave, "pty");
+
+    // Start a read, and give up on it.
+    let mut buf = [0; 8];
+    assert!(poll_once(device.read(&mut buf)).await.is_none());
+    std::thread::sleep(Duration::from_millis(100));
+
+    // The time spent before the next read isn't counted as waiting for it.
+    master.write_all(b"x").unwrap();
+    assert_eq!(device.read(&mut buf).await.unwrap(), 1);
+    let stats = device.stats();
+    assert_eq!(stats.reads, 1);
+    assert!(stats.read_time < Duration::from_millis(100));
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn tokio_stats() {
+    use char_device::TokioCharDevice;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let mut device = MeteredDevice::new(TokioCharDevice::null().await.unwrap(), "null");
+    device.write_all(b"hello").await.unwrap();
+    device.flush().await.unwrap();
+    assert_eq!(device.read(&mut [0; 8]).await.unwrap(), 0);
+
+    let n = device
+        .write_vectored(&[io::IoSlice::new(b"a"), io::IoSlice::new(b"bc")])
+        .await
+        .unwrap();
+    assert_eq!(n, 3);
+
+    let stats = device.stats();
+    assert_eq!(stats.bytes_written, 8);
+    assert_eq!(stats.writes, 2);
+    assert_eq!(stats.reads, 1);
+    assert!(stats.errors.is_empty());
+}
+
+#[cfg(feature = "async-std")]
+#[async_std::test]
+async fn async_std_stats() {
+    use async_std::io::prelude::{ReadExt, WriteExt};
+    use char_device::AsyncStdCharDevice;
+
+    let mut device = MeteredDevice::new(AsyncStdCharDevice::null().await.unwrap(), "null");
+    device.write_all(b"hello").await.unwrap();
+    assert_eq!(device.read(&mut [0; 8]).await.unwrap(), 0);
+    let mut buf = [0; 4];
+    let n = device
+        .read_vectored(&mut [io::IoSliceMut::new(&mut buf)])
+        .await
+        .unwrap();
+    assert_eq!(n, 0);
+
+    let stats = device.stats();
+    assert_eq!(stats.bytes_written, 5);
+    assert_eq!(stats.reads, 2);
+}
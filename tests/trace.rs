@@ -0,0 +1,178 @@
+#![cfg(all(unix, feature = "tracing"))]
+
+mod common;
+
+use char_device::CharDevice;
+use std::fmt::{self, Write as _};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+
+/// A subscriber which formats each span and event as a line of text.
+#[derive(Clone, Default)]
+struct Capture {
+    lines: Arc<Mutex<Vec<String>>>,
+    next_id: Arc<AtomicU64>,
+}
+
+struct Fields<'a>(&'a mut String);
+
+impl Visit for Fields<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        write!(self.0, " {}={:?}", field.name(), value).unwrap();
+    }
+}
+
+impl Capture {
+    fn push(&self, line: String) {
+        self.lines.lock().unwrap().push(line);
+    }
+
+    fn lines(&self) -> Vec<String> {
+        self.lines.lock().unwrap().clone()
+    }
+}
+
+impl Subscriber for Capture {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.target() == "char_device"
+    }
+
+    fn new_span(&self, attrs: &Attributes<'_>) -> Id {
+        let mut line = format!("span {}:", attrs.metadata().name());
+        attrs.record(&mut Fields(&mut line));
+        self.push(line);
+        Id::from_u64(self.next_id.fetch_add(1, Ordering::Relaxed) + 1)
+    }
+
+    fn record(&self, _span: &Id, values: &Record<'_>) {
+        let mut line = String::from("record:");
+        values.record(&mut Fields(&mut line));
+        self.push(line);
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut line = format!("{}:", event.metadata().level());
+        event.record(&mut Fields(&mut line));
+        self.push(line);
+    }
+
+    fn enter(&self, _span: &Id) {}
+
+    fn exit(&self, _span: &Id) {}
+}
+
+fn find<'a>(lines: &'a [String], parts: &[&str]) -> &'a str {
+    lines
+        .iter()
+        .find(|line| parts.iter().all(|part| line.contains(part)))
+        .unwrap_or_else(|| panic!("no line with {:?} in {:#?}", parts, lines))
+}
+
+#[test]
+fn trace() {
+    use std::io::{Read, Write};
+
+    let capture = Capture::default();
+    tracing::subscriber::with_default(capture.clone(), || {
+        char_device::trace::set_payload_limit(4);
+
+        let mut device = CharDevice::null().unwrap();
+        device.write_all(b"hello").unwrap();
+        assert_eq!(device.read(&mut [0; 8]).unwrap(), 0);
+
+        assert!(CharDevice::open("/nonexistent/tty").is_err());
+    });
+
+    let lines = capture.lines();
+    find(&lines, &["span open:", "path=/dev/null", "flags="]);
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    find(&lines, &["record:", "device_id=1:3"]);
+    find(&lines, &["DEBUG:", "opened", "fd="]);
+
+    let write = find(&lines, &["DEBUG:", "op=\"write\"", "bytes=5"]);
+    assert!(write.contains("duration_us="), "{}", write);
+    find(
+        &lines,
+        &[
+            "TRACE:",
+            "op=\"write\"",
+            "data=68 65 6c 6c ... (1 more bytes)",
+        ],
+    );
+    find(&lines, &["DEBUG:", "op=\"read\"", "bytes=0"]);
+
+    find(&lines, &["span open:", "path=/nonexistent/tty"]);
+    find(&lines, &["DEBUG:", "open failed", "errno=2"]);
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn tokio_trace() {
+    use char_device::TokioCharDevice;
+    use tokio::io::AsyncWriteExt;
+
+    let capture = Capture::default();
+    let _guard = tracing::subscriber::set_default(capture.clone());
+
+    let mut device = TokioCharDevice::null().await.unwrap();
+    device.write_all(b"hello").await.unwrap();
+
+    let lines = capture.lines();
+    find(&lines, &["span open:", "path=/dev/null"]);
+    find(&lines, &["DEBUG:", "opened"]);
+    let write = find(&lines, &["DEBUG:", "op=\"write\"", "bytes=5"]);
+    assert!(write.contains("duration_us="), "{}", write);
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn tokio_read_duration() {
+    use char_device::TokioCharDevice;
+    use std::io::Write;
+    use std::time::Duration;
+    use tokio::io::AsyncReadExt;
+
+    let capture = Capture::default();
+    let _guard = tracing::subscriber::set_default(capture.clone());
+
+    let (mut master, name) = common::pty();
+    let mut device = TokioCharDevice::open(name).await.unwrap();
+    let writer = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(50));
+        master.write_all(b"hi\n").unwrap();
+        master
+    });
+    let mut buf = [0; 8];
+    assert_eq!(device.read(&mut buf).await.unwrap(), 3);
+    let _master = writer.join().unwrap();
+
+    // The duration covers the wait for the data, not just the final poll.
+    let lines = capture.lines();
+    let read = find(&lines, &["DEBUG:", "op=\"read\"", "bytes=3"]);
+    let duration_us = read.split("duration_us=").nth(1).unwrap();
+    let duration_us: u64 = duration_us.split(' ').next().unwrap().parse().unwrap();
+    assert!(duration_us >= 50_000, "{}", read);
+}
+
+#[cfg(feature = "async-std")]
+#[async_std::test]
+async fn async_std_trace() {
+    use async_std::io::prelude::WriteExt;
+    use char_device::AsyncStdCharDevice;
+
+    let capture = Capture::default();
+    let _guard = tracing::subscriber::set_default(capture.clone());
+
+    let mut device = AsyncStdCharDevice::null().await.unwrap();
+    device.write_all(b"hello").await.unwrap();
+
+    let lines = capture.lines();
+    find(&lines, &["span open:", "path=/dev/null"]);
+    let write = find(&lines, &["DEBUG:", "op=\"write\"", "bytes=5"]);
+    assert!(write.contains("duration_us="), "{}", write);
+}
@@ -11,6 +11,7 @@ fn tty() {
     const NXIO: i32 = rustix::io::Errno::NXIO.raw_os_error();
 
     // For now, just ensure that we can open this.
+    #[allow(clippy::unnecessary_literal_unwrap)]
     let _tty = match CharDevice::open("/dev/tty") {
         Ok(tty) => tty,
         Err(e) => match e.raw_os_error() {
@@ -21,12 +22,39 @@ fn tty() {
     };
 }
 
+#[test]
+fn controlling_terminal() {
+    // Headless environments have no controlling terminal, which isn't an
+    // error.
+    let _tty = CharDevice::controlling_terminal().unwrap();
+}
+
+#[test]
+fn stdio() {
+    use rustix::fs::{fstat, FileType};
+
+    // The test harness may run us with any kind of stdio, so just check that
+    // the result agrees with the file type.
+    let stdin = CharDevice::stdin().unwrap();
+    let file_type = FileType::from_raw_mode(fstat(std::io::stdin()).unwrap().st_mode);
+    assert_eq!(stdin.is_some(), file_type == FileType::CharacterDevice);
+
+    let stdout = CharDevice::stdout().unwrap();
+    let file_type = FileType::from_raw_mode(fstat(std::io::stdout()).unwrap().st_mode);
+    assert_eq!(stdout.is_some(), file_type == FileType::CharacterDevice);
+
+    let stderr = CharDevice::stderr().unwrap();
+    let file_type = FileType::from_raw_mode(fstat(std::io::stderr()).unwrap().st_mode);
+    assert_eq!(stderr.is_some(), file_type == FileType::CharacterDevice);
+}
+
 #[cfg(feature = "async-std")]
 #[async_std::test]
 async fn async_std_tty() {
     const NXIO: i32 = rustix::io::Errno::NXIO.raw_os_error();
 
     // For now, just ensure that we can open this.
+    #[allow(clippy::unnecessary_literal_unwrap)]
     let _tty = match AsyncStdCharDevice::open("/dev/tty").await {
         Ok(tty) => tty,
         Err(e) => match e.raw_os_error() {
@@ -43,6 +71,7 @@ async fn tokio_tty() {
     const NXIO: i32 = rustix::io::Errno::NXIO.raw_os_error();
 
     // For now, just ensure that we can open this.
+    #[allow(clippy::unnecessary_literal_unwrap)]
     let _tty = match TokioCharDevice::open("/dev/tty").await {
         Ok(tty) => tty,
         Err(e) => match e.raw_os_error() {
@@ -0,0 +1,226 @@
+#![cfg(all(any(target_os = "android", target_os = "linux"), feature = "io-uring"))]
+
+mod common;
+
+use char_device::uring::{Completion, DeviceId, Readiness, Ring};
+use char_device::CharDevice;
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+
+/// Wait for the next completion on device `id`, ignoring other devices.
+fn next(ring: &mut Ring, id: DeviceId, pending: &mut Vec<Completion>) -> Completion {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let position = pending.iter().position(|completion| match completion {
+            Completion::Read(device, _)
+            | Completion::Write(device, _)
+            | Completion::Ready(device, _) => *device == id,
+        });
+        if let Some(position) = position {
+            return pending.remove(position);
+        }
+        assert!(Instant::now() < deadline, "timed out");
+        ring.wait(pending, Some(Duration::from_millis(100)))
+            .unwrap();
+    }
+}
+
+fn exercise(mut ring: Ring) {
+    let mut pending = Vec::new();
+    let (mut master, slave) = common::pty_without_echo();
+    let id = ring.add(slave);
+
+    // Read continuously.
+    ring.start_reading(id).unwrap();
+    for line in [&b"hello\n"[..], b"world\n"] {
+        master.write_all(line).unwrap();
+        match next(&mut ring, id, &mut pending) {
+            Completion::Read(_, data) => assert_eq!(data.unwrap(), line),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    // Writes complete in order.
+    ring.write(id, b"ping".to_vec()).unwrap();
+    ring.write(id, b"pong".to_vec()).unwrap();
+    for _ in 0..2 {
+        match next(&mut ring, id, &mut pending) {
+            Completion::Write(_, n) => assert_eq!(n.unwrap(), 4),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+    let mut buf = [0; 8];
+    master.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"pingpong");
+
+    let interest = Readiness {
+        writable: true,
+        ..Readiness::default()
+    };
+    ring.poll_ready(id, interest).unwrap();
+    match next(&mut ring, id, &mut pending) {
+        Completion::Ready(_, readiness) => assert!(readiness.unwrap().writable),
+        other => panic!("unexpected {:?}", other),
+    }
+
+    // "/dev/zero" doesn't support polling, so it's read without multishot
+    // reads, and is always ready.
+    let zero = ring.add(CharDevice::open("/dev/zero").unwrap());
+    ring.start_reading(zero).unwrap();
+    match next(&mut ring, zero, &mut pending) {
+        Completion::Read(_, data) => assert!(data.unwrap().iter().all(|byte| *byte == 0)),
+        other => panic!("unexpected {:?}", other),
+    }
+    ring.stop_reading(zero).unwrap();
+    ring.remove(zero).unwrap();
+
+    // Removing cancels the read in progress.
+    let mut slave = ring.remove(id).unwrap();
+    master.write_all(b"after\n").unwrap();
+    let mut buf = [0; 8];
+    assert_eq!(slave.read(&mut buf).unwrap(), 6);
+    assert_eq!(&buf[..6], b"after\n");
+}
+
+#[test]
+fn uring() {
+    // This uses `poll` if the kernel doesn't support io_uring.
+    exercise(Ring::new().unwrap());
+}
+
+#[test]
+fn uring_fallback() {
+    let ring = Ring::without_io_uring(4096);
+    assert!(!ring.is_io_uring());
+    exercise(ring);
+}
+
+#[test]
+fn uring_drop_in_flight() {
+    let (_master, slave) = common::pty_without_echo();
+    let mut ring = Ring::new().unwrap();
+    let id = ring.add(slave);
+    ring.start_reading(id).unwrap();
+    ring.poll_ready(
+        id,
+        Readiness {
+            readable: true,
+            ..Readiness::default()
+        },
+    )
+    .unwrap();
+    ring.wait(&mut Vec::new(), Some(Duration::from_millis(10)))
+        .unwrap();
+}
+
+fn stale_ids(mut ring: Ring) {
+    let mut pending = Vec::new();
+    let (_master, slave) = common::pty_without_echo();
+    let old = ring.add(slave);
+    ring.write(old, b"ping".to_vec()).unwrap();
+    let slave = ring.remove(old).unwrap();
+
+    // The new device takes the old one's place, but not its id, so
+    // completions left over from the old device aren't reported for it.
+    let new = ring.add(slave);
+    assert_ne!(old, new);
+    ring.poll_ready(
+        new,
+        Readiness {
+            writable: true,
+            ..Readiness::default()
+        },
+    )
+    .unwrap();
+    match next(&mut ring, new, &mut pending) {
+        Completion::Ready(_, readiness) => assert!(readiness.unwrap().writable),
+        other => panic!("unexpected {:?}", other),
+    }
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        ring.get(old);
+    }));
+    assert!(result.is_err());
+}
+
+#[test]
+fn uring_stale_ids() {
+    stale_ids(Ring::new().unwrap());
+}
+
+#[test]
+fn uring_fallback_stale_ids() {
+    stale_ids(Ring::without_io_uring(4096));
+}
+
+#[test]
+fn uring_polls() {
+    let mut pending = Vec::new();
+    let (mut master, slave) = common::pty_without_echo();
+    let mut ring = Ring::new().unwrap();
+    let id = ring.add(slave);
+    let readable = Readiness {
+        readable: true,
+        ..Readiness::default()
+    };
+    let writable = Readiness {
+        writable: true,
+        ..Readiness::default()
+    };
+
+    // The poll for writability completes first, leaving the poll for
+    // readability waiting, which completes once there's something to read.
+    ring.poll_ready(id, readable).unwrap();
+    ring.poll_ready(id, writable).unwrap();
+    match next(&mut ring, id, &mut pending) {
+        Completion::Ready(_, readiness) => assert!(readiness.unwrap().writable),
+        other => panic!("unexpected {:?}", other),
+    }
+    master.write_all(b"hello\n").unwrap();
+    match next(&mut ring, id, &mut pending) {
+        Completion::Ready(_, readiness) => assert!(readiness.unwrap().readable),
+        other => panic!("unexpected {:?}", other),
+    }
+
+    // With nothing left to wait for, removing the device cancels nothing.
+    ring.remove(id).unwrap();
+}
+
+fn large_write(mut ring: Ring) {
+    let mut pending = Vec::new();
+    let (mut master, slave) = common::pty_without_echo();
+    let (mut other_master, other_slave) = common::pty_without_echo();
+    let id = ring.add(slave);
+    let other = ring.add(other_slave);
+
+    // This is more than the pty's buffer holds, so it's written in pieces,
+    // and nothing waits for it while the other device is read.
+    let data = vec![b'x'; 1 << 20];
+    ring.write(id, data.clone()).unwrap();
+    ring.start_reading(other).unwrap();
+    other_master.write_all(b"hello\n").unwrap();
+    match next(&mut ring, other, &mut pending) {
+        Completion::Read(_, data) => assert_eq!(data.unwrap(), b"hello\n"),
+        other => panic!("unexpected {:?}", other),
+    }
+
+    let reader = std::thread::spawn(move || {
+        let mut received = vec![0; 1 << 20];
+        master.read_exact(&mut received).unwrap();
+        received
+    });
+    match next(&mut ring, id, &mut pending) {
+        Completion::Write(_, n) => assert_eq!(n.unwrap(), data.len()),
+        other => panic!("unexpected {:?}", other),
+    }
+    assert_eq!(reader.join().unwrap(), data);
+}
+
+#[test]
+fn uring_large_write() {
+    large_write(Ring::new().unwrap());
+}
+
+#[test]
+fn uring_fallback_large_write() {
+    large_write(Ring::without_io_uring(4096));
+}
@@ -0,0 +1,34 @@
+#![cfg(unix)]
+
+mod common;
+
+use char_device::CharDevice;
+use std::io::{IoSlice, IoSliceMut, Read, Write};
+
+#[test]
+fn pty_vectored() {
+    let (mut master, name) = common::pty();
+    let mut slave = CharDevice::open(name).unwrap();
+    assert!(slave.is_write_vectored());
+
+    // Use more buffers than `IOV_MAX`, to exercise splitting.
+    let mut reader = master.try_clone().unwrap();
+    let reader = std::thread::spawn(move || {
+        let mut data = vec![0_u8; 6000];
+        reader.read_exact(&mut data).unwrap();
+        data
+    });
+    let data = [b"abc".as_slice(); 2000];
+    let mut bufs = data.iter().map(|d| IoSlice::new(d)).collect::<Vec<_>>();
+    slave.write_all_vectored(&mut bufs).unwrap();
+    assert_eq!(reader.join().unwrap(), b"abc".repeat(2000));
+
+    master.write_all(b"hello\n").unwrap();
+    let mut data = [[0_u8; 1]; 2000];
+    let mut bufs = data
+        .iter_mut()
+        .map(|d| IoSliceMut::new(d))
+        .collect::<Vec<_>>();
+    assert_eq!(slave.read_vectored(&mut bufs).unwrap(), 6);
+    assert_eq!(data[..6].concat(), b"hello\n");
+}